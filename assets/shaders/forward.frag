@@ -21,17 +21,32 @@
 // SOFTWARE.
 #version 450
 
-const int MAX_LIGHTS = 10;
+const int MAX_DIRECTIONAL_LIGHTS = 4;
+// How many times albedo textures repeat per voxel, so that each covers four voxels.
+const float TEXTURE_SCALE = 0.25;
+// How much of the light a fully occluded vertex loses.
+const float OCCLUSION_STRENGTH = 0.7;
+// How far along its normal a surface is moved before looking it up in the shadow map, about a
+// texel of the shadow map, so that it doesn't shadow itself.
+const float SHADOW_NORMAL_OFFSET = 0.1;
 
-struct Light {
-    mat4 proj;
+struct DirectionalLight {
+    // the direction towards the light
+    vec4 direction;
+    vec4 color;
+};
+
+struct PointLight {
+    // xyz: position, w: range
     vec4 pos;
+    // the color, multiplied by the intensity
     vec4 color;
 };
 
 layout(location = 0) in vec3 v_Position;
 layout(location = 1) in vec3 v_Normal;
 layout(location = 2) in vec2 v_Uv;
+layout(location = 3) in float v_Occlusion;
 
 layout(location = 0) out vec4 o_Target;
 
@@ -41,8 +56,9 @@ layout(set = 0, binding = 0) uniform Camera {
 
 layout(set = 1, binding = 0) uniform Lights {
     vec3 AmbientColor;
+    // x: number of directional lights
     uvec4 NumLights;
-    Light SceneLights[MAX_LIGHTS];
+    DirectionalLight DirectionalLights[MAX_DIRECTIONAL_LIGHTS];
 };
 
 layout(set = 3, binding = 0) uniform StandardMaterial_albedo {
@@ -53,36 +69,132 @@ layout(set = 1, binding = 1) uniform YLevel {
     vec3 YLevelValue;
 };
 
+layout(set = 1, binding = 2) uniform ShadowLight {
+    mat4 ShadowViewProj;
+    // x: depth bias, y: size of a shadow map texel in texture coordinates, z: 1 if enabled
+    vec4 ShadowParams;
+};
+layout(set = 1, binding = 3) uniform texture2D ShadowMap;
+layout(set = 1, binding = 4) uniform sampler ShadowMap_sampler;
+
+layout(set = 1, binding = 5) readonly buffer PointLights {
+    PointLight ScenePointLights[];
+};
+// The point lights which reach into each cluster of a grid of boxes in front of the camera.
+layout(set = 1, binding = 6) readonly buffer LightClusters {
+    // the number of clusters along each axis
+    ivec4 ClusterCounts;
+    // xyz: the minimum corner of the first cluster, w: the size of each cluster
+    vec4 ClusterOrigin;
+    // for each cluster, the offset of its lights' indices in ClusterData and how many there
+    // are, followed by the indices themselves
+    uint ClusterData[];
+};
+
+// How much of the primary light reaches a point, from 0 in full shadow to 1. Compares the
+// point's depth with the 3x3 nearest texels of the shadow map and averages the results, which
+// softens the edges of the shadows.
+float shadow(vec3 position, vec3 normal) {
+    if (ShadowParams.z == 0.0) {
+        return 1.0;
+    }
+    vec4 clip = ShadowViewProj * vec4(position + normal * SHADOW_NORMAL_OFFSET, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    // texture coordinates run downwards, unlike clip space
+    vec2 uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    // anything outside of the shadow map is lit
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    float lit = 0.0;
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            vec2 offset = vec2(x, y) * ShadowParams.y;
+            float depth = texture(sampler2D(ShadowMap, ShadowMap_sampler), uv + offset).r;
+            lit += ndc.z - ShadowParams.x <= depth ? 1.0 : 0.0;
+        }
+    }
+    return lit / 9.0;
+}
+
+// How much of a point light reaches a point at a distance from it. It falls off with the
+// square of the distance, and smoothly down to nothing at the light's range.
+float attenuation(float distance, float range) {
+    float falloff = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return falloff * falloff / (distance * distance + 1.0);
+}
+
+// The light from the point lights in the cluster a point is in.
+vec3 point_lights(vec3 position, vec3 normal) {
+    ivec3 cluster = ivec3(floor((position - ClusterOrigin.xyz) / ClusterOrigin.w));
+    // nothing outside of the clusters is lit by point lights
+    if (any(lessThan(cluster, ivec3(0))) || any(greaterThanEqual(cluster, ClusterCounts.xyz))) {
+        return vec3(0.0);
+    }
+    int index = (cluster.z * ClusterCounts.y + cluster.y) * ClusterCounts.x + cluster.x;
+    uint offset = ClusterData[2 * index];
+    uint count = ClusterData[2 * index + 1];
+    vec3 color = vec3(0.0);
+    for (uint i = offset; i < offset + count; ++i) {
+        PointLight light = ScenePointLights[ClusterData[i]];
+        vec3 to_light = light.pos.xyz - position;
+        float distance = length(to_light);
+        float diffuse = max(0.0, dot(normal, to_light / max(distance, 0.0001)));
+        color += diffuse * attenuation(distance, light.pos.w) * light.color.xyz;
+    }
+    return color;
+}
+
 # ifdef STANDARDMATERIAL_ALBEDO_TEXTURE
 layout(set = 3, binding = 1) uniform texture2D StandardMaterial_albedo_texture;
 layout(set = 3, binding = 2) uniform sampler StandardMaterial_albedo_texture_sampler;
+
+// Projects the albedo texture onto the surface along each axis, and blends the projections by
+// how squarely the surface faces along that axis, so that terrain doesn't need UVs.
+vec4 triplanar_albedo(vec3 position, vec3 normal) {
+    vec3 weights = pow(abs(normal), vec3(4.0));
+    weights /= max(weights.x + weights.y + weights.z, 0.0001);
+    vec3 uvw = fract(position * TEXTURE_SCALE);
+    vec4 x = texture(
+        sampler2D(StandardMaterial_albedo_texture, StandardMaterial_albedo_texture_sampler),
+        uvw.zy);
+    vec4 y = texture(
+        sampler2D(StandardMaterial_albedo_texture, StandardMaterial_albedo_texture_sampler),
+        uvw.xz);
+    vec4 z = texture(
+        sampler2D(StandardMaterial_albedo_texture, StandardMaterial_albedo_texture_sampler),
+        uvw.xy);
+    return x * weights.x + y * weights.y + z * weights.z;
+}
 # endif
 
 void main() {
     vec4 output_color = Albedo;
 # ifdef STANDARDMATERIAL_ALBEDO_TEXTURE
-    output_color *= texture(
-        sampler2D(StandardMaterial_albedo_texture, StandardMaterial_albedo_texture_sampler),
-        v_Uv);
+    output_color *= triplanar_albedo(v_Position, normalize(v_Normal));
 # endif
 
 # ifdef STANDARDMATERIAL_SHADED
     vec3 normal = normalize(v_Normal);
     // accumulate color
     vec3 color = AmbientColor;
-    for (int i=0; i<int(NumLights.x) && i<MAX_LIGHTS; ++i) {
-        Light light = SceneLights[i];
+    for (int i=0; i<int(NumLights.x) && i<MAX_DIRECTIONAL_LIGHTS; ++i) {
+        DirectionalLight light = DirectionalLights[i];
         // compute Lambertian diffuse term
-        vec3 light_dir = normalize(light.pos.xyz - v_Position);
-        float diffuse = max(0.0, dot(normal, light_dir));
+        float diffuse = max(0.0, dot(normal, normalize(light.direction.xyz)));
+        // only the primary light, which comes first, casts shadows
+        if (i == 0) {
+            diffuse *= shadow(v_Position, normal);
+        }
         // add light contribution
         color += diffuse * light.color.xyz;
     }
-
-    // average the lights so that we will never get something with > 1.0
-    color /= max(float(NumLights.x), 1.0);
+    color += point_lights(v_Position, normal);
 
     output_color.xyz *= color;
+
+    // darken crevices, tunnels and the corners of rooms
+    output_color.xyz *= 1.0 - OCCLUSION_STRENGTH * v_Occlusion;
 # endif
 
     // multiply the light by material color
@@ -24,10 +24,13 @@
 layout(location = 0) in vec3 Vertex_Position;
 layout(location = 1) in vec3 Vertex_Normal;
 layout(location = 2) in vec2 Vertex_Uv;
+// How occluded the vertex is by the terrain around it. Meshes without it are left unoccluded.
+layout(location = 3) in float Vertex_Occlusion;
 
 layout(location = 0) out vec3 v_Position;
 layout(location = 1) out vec3 v_Normal;
 layout(location = 2) out vec2 v_Uv;
+layout(location = 3) out float v_Occlusion;
 
 layout(set = 0, binding = 0) uniform Camera {
     mat4 ViewProj;
@@ -41,5 +44,6 @@ void main() {
     v_Normal = mat3(Model) * Vertex_Normal;
     v_Position = (Model * vec4(Vertex_Position, 1.0)).xyz;
     v_Uv = Vertex_Uv;
+    v_Occlusion = Vertex_Occlusion;
     gl_Position = ViewProj * vec4(v_Position, 1.0);
 }
\ No newline at end of file
@@ -22,17 +22,31 @@
 // SOFTWARE.
 precision highp float;
 
-const int MAX_LIGHTS = 10;
+const int MAX_DIRECTIONAL_LIGHTS = 4;
+// WebGL has no storage buffers, so only the point lights nearest the camera are used.
+const int MAX_POINT_LIGHTS = 32;
+// How many times albedo textures repeat per voxel, so that each covers four voxels.
+const float TEXTURE_SCALE = 0.25;
+// How much of the light a fully occluded vertex loses.
+const float OCCLUSION_STRENGTH = 0.7;
 
-struct Light {
-    mat4 proj;
+struct DirectionalLight {
+    // the direction towards the light
+    vec4 direction;
+    vec4 color;
+};
+
+struct PointLight {
+    // xyz: position, w: range
     vec4 pos;
+    // the color, multiplied by the intensity
     vec4 color;
 };
 
 in vec3 v_Position;
 in vec3 v_Normal;
 in vec2 v_Uv;
+in float v_Occlusion;
 
 out vec4 o_Target;
 
@@ -42,8 +56,13 @@ layout(std140) uniform Camera {
 
 layout(std140) uniform Lights {  // set = 1, binding = 0
     vec3 AmbientColor;
+    // x: number of directional lights, y: number of point lights
     uvec4 NumLights;
-    Light SceneLights[MAX_LIGHTS];
+    DirectionalLight DirectionalLights[MAX_DIRECTIONAL_LIGHTS];
+};
+
+layout(std140) uniform PointLights {  // set = 1, binding = 5
+    PointLight ScenePointLights[MAX_POINT_LIGHTS];
 };
 
 layout(std140) uniform StandardMaterial_albedo { // set = 3, binding = 0
@@ -54,8 +73,26 @@ layout(std140) uniform YLevel { // set = 4, binding = 4
     vec3 YLevelValue;
 };
 
+// How much of a point light reaches a point at a distance from it. It falls off with the
+// square of the distance, and smoothly down to nothing at the light's range.
+float attenuation(float distance, float range) {
+    float falloff = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return falloff * falloff / (distance * distance + 1.0);
+}
+
 #ifdef STANDARDMATERIAL_ALBEDO_TEXTURE
 uniform sampler2D StandardMaterial_albedo_texture;  // set = 3, binding = 1
+
+// Projects the albedo texture onto the surface along each axis, and blends the projections by
+// how squarely the surface faces along that axis, so that terrain doesn't need UVs.
+vec4 triplanar_albedo(vec3 position, vec3 normal) {
+    vec3 weights = pow(abs(normal), vec3(4.0));
+    weights /= max(weights.x + weights.y + weights.z, 0.0001);
+    vec3 uvw = fract(position * TEXTURE_SCALE);
+    return texture(StandardMaterial_albedo_texture, uvw.zy) * weights.x
+        + texture(StandardMaterial_albedo_texture, uvw.xz) * weights.y
+        + texture(StandardMaterial_albedo_texture, uvw.xy) * weights.z;
+}
 #endif
 
 vec4 encodeSRGB(vec4 linearRGB_in)
@@ -71,25 +108,31 @@ void main() {
     vec4 output_color = Albedo;
 
 #ifdef STANDARDMATERIAL_ALBEDO_TEXTURE
-    output_color *= texture(
-        StandardMaterial_albedo_texture,
-        v_Uv
-    );
+    output_color *= triplanar_albedo(v_Position, normalize(v_Normal));
 #endif
 
 #ifdef STANDARDMATERIAL_SHADED
     vec3 normal = normalize(v_Normal);
     // accumulate color
     vec3 color = AmbientColor;
-    for (int i=0; i<int(NumLights.x) && i<MAX_LIGHTS; ++i) {
-        Light light = SceneLights[i];
+    for (int i=0; i<int(NumLights.x) && i<MAX_DIRECTIONAL_LIGHTS; ++i) {
+        DirectionalLight light = DirectionalLights[i];
         // compute Lambertian diffuse term
-        vec3 light_dir = normalize(light.pos.xyz - v_Position);
-        float diffuse = max(0.0, dot(normal, light_dir));
+        float diffuse = max(0.0, dot(normal, normalize(light.direction.xyz)));
         // add light contribution
         color += diffuse * light.color.xyz;
     }
+    for (int i=0; i<int(NumLights.y) && i<MAX_POINT_LIGHTS; ++i) {
+        PointLight light = ScenePointLights[i];
+        vec3 to_light = light.pos.xyz - v_Position;
+        float distance = length(to_light);
+        float diffuse = max(0.0, dot(normal, to_light / max(distance, 0.0001)));
+        color += diffuse * attenuation(distance, light.pos.w) * light.color.xyz;
+    }
     output_color.xyz *= color;
+
+    // darken crevices, tunnels and the corners of rooms
+    output_color.xyz *= 1.0 - OCCLUSION_STRENGTH * v_Occlusion;
 #endif
     // multiply the light by material color
     o_Target = encodeSRGB(output_color);
@@ -24,6 +24,8 @@ precision highp float;
 
 in vec3 Vertex_Position;
 in vec3 Vertex_Normal;
+// How occluded the vertex is by the terrain around it. Meshes without it are left unoccluded.
+in float Vertex_Occlusion;
 #ifdef STANDARDMATERIAL_ALBEDO_TEXTURE
 in vec2 Vertex_Uv;
 #endif
@@ -31,6 +33,7 @@ in vec2 Vertex_Uv;
 out vec3 v_Position;
 out vec3 v_Normal;
 out vec2 v_Uv;
+out float v_Occlusion;
 
 layout(std140) uniform Camera {
     mat4 ViewProj;
@@ -44,6 +47,7 @@ void main() {
     v_Normal = (Model * vec4(Vertex_Normal, 1.0)).xyz;
     v_Normal = mat3(Model) * Vertex_Normal;
     v_Position = (Model * vec4(Vertex_Position, 1.0)).xyz;
+    v_Occlusion = Vertex_Occlusion;
 #ifdef STANDARDMATERIAL_ALBEDO_TEXTURE
     v_Uv = Vertex_Uv;
 #endif
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in vec3 v_Position;
+
+layout(set = 1, binding = 0) uniform YLevel {
+    vec3 YLevelValue;
+};
+
+void main() {
+    // Terrain above the y-level isn't drawn, so it mustn't cast shadows either.
+    if (v_Position.y > YLevelValue.x + 0.00001) {
+        discard;
+    }
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec3 Vertex_Position;
+
+layout(location = 0) out vec3 v_Position;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 ViewProj;
+};
+
+layout(set = 2, binding = 0) uniform Transform {
+    mat4 Model;
+};
+
+void main() {
+    v_Position = (Model * vec4(Vertex_Position, 1.0)).xyz;
+    gl_Position = ViewProj * vec4(v_Position, 1.0);
+}
@@ -3,13 +3,14 @@ use building_blocks::{
     mesh::{IsOpaque, MergeVoxel, SignedDistance},
     storage::IsEmpty,
 };
+use serde::Deserialize;
 
 pub const EMPTY_VOXEL: Voxel = Voxel {
     voxel_type: VoxelType::Air,
     distance: VoxelDistance(1),
 };
 
-pub const NUM_VOXEL_TYPES: usize = 5;
+pub const NUM_VOXEL_TYPES: usize = 15;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Voxel {
@@ -40,16 +41,51 @@ impl SignedDistance for Voxel {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
 pub enum VoxelType {
     Air,
+    /// Generic stone, for terrain which isn't generated with any strata.
     Stone,
     Grass,
     Gold,
     Water,
+    /// The loose earth between the grass and the bedrock.
+    Soil,
+    // Sedimentary rock.
+    Sandstone,
+    Limestone,
+    // Igneous rock.
+    Granite,
+    Basalt,
+    // Metamorphic rock.
+    Marble,
+    Slate,
+    // Ores, other than gold.
+    Iron,
+    Copper,
+    Coal,
 }
 
 impl VoxelType {
+    /// Every voxel type, in the order of their indices.
+    pub const ALL: [VoxelType; NUM_VOXEL_TYPES] = [
+        VoxelType::Air,
+        VoxelType::Stone,
+        VoxelType::Grass,
+        VoxelType::Gold,
+        VoxelType::Water,
+        VoxelType::Soil,
+        VoxelType::Sandstone,
+        VoxelType::Limestone,
+        VoxelType::Granite,
+        VoxelType::Basalt,
+        VoxelType::Marble,
+        VoxelType::Slate,
+        VoxelType::Iron,
+        VoxelType::Copper,
+        VoxelType::Coal,
+    ];
+
     pub fn index(&self) -> usize {
         match self {
             VoxelType::Air => 0,
@@ -57,13 +93,63 @@ impl VoxelType {
             VoxelType::Grass => 2,
             VoxelType::Gold => 3,
             VoxelType::Water => 4,
+            VoxelType::Soil => 5,
+            VoxelType::Sandstone => 6,
+            VoxelType::Limestone => 7,
+            VoxelType::Granite => 8,
+            VoxelType::Basalt => 9,
+            VoxelType::Marble => 10,
+            VoxelType::Slate => 11,
+            VoxelType::Iron => 12,
+            VoxelType::Copper => 13,
+            VoxelType::Coal => 14,
         }
     }
 
     pub fn collidable(&self) -> bool {
+        !matches!(self, VoxelType::Air | VoxelType::Water)
+    }
+
+    /// Whether this is one of the kinds of rock which make up the bedrock.
+    pub fn is_stone(&self) -> bool {
+        matches!(
+            self,
+            VoxelType::Stone
+                | VoxelType::Sandstone
+                | VoxelType::Limestone
+                | VoxelType::Granite
+                | VoxelType::Basalt
+                | VoxelType::Marble
+                | VoxelType::Slate
+        )
+    }
+
+    pub fn is_ore(&self) -> bool {
+        matches!(
+            self,
+            VoxelType::Gold | VoxelType::Iron | VoxelType::Copper | VoxelType::Coal
+        )
+    }
+
+    /// The color this material is rendered with, as RGBA from 0 to 1.
+    pub fn color(&self) -> [f32; 4] {
         match self {
-            VoxelType::Air | VoxelType::Water => false,
-            VoxelType::Stone | VoxelType::Grass | VoxelType::Gold => true,
+            // Air is never rendered, but every voxel type needs a material.
+            VoxelType::Air => [0.5, 0.5, 0.5, 1.0],
+            VoxelType::Stone => [0.5, 0.5, 0.5, 1.0],
+            VoxelType::Grass => [0.376, 0.502, 0.22, 1.0],
+            VoxelType::Gold => [1.0, 0.843, 0.0, 1.0],
+            VoxelType::Water => [0.0, 0.0, 0.5, 0.5],
+            VoxelType::Soil => [0.45, 0.33, 0.2, 1.0],
+            VoxelType::Sandstone => [0.82, 0.7, 0.5, 1.0],
+            VoxelType::Limestone => [0.8, 0.78, 0.7, 1.0],
+            VoxelType::Granite => [0.6, 0.52, 0.5, 1.0],
+            VoxelType::Basalt => [0.25, 0.25, 0.27, 1.0],
+            VoxelType::Marble => [0.92, 0.92, 0.9, 1.0],
+            VoxelType::Slate => [0.35, 0.4, 0.45, 1.0],
+            VoxelType::Iron => [0.55, 0.35, 0.3, 1.0],
+            VoxelType::Copper => [0.72, 0.45, 0.2, 1.0],
+            VoxelType::Coal => [0.1, 0.1, 0.1, 1.0],
         }
     }
 }
@@ -78,19 +164,13 @@ impl MergeVoxel for VoxelType {
 
 impl IsOpaque for VoxelType {
     fn is_opaque(&self) -> bool {
-        match self {
-            VoxelType::Air | VoxelType::Water => false,
-            VoxelType::Stone | VoxelType::Grass | VoxelType::Gold => true,
-        }
+        !matches!(self, VoxelType::Air | VoxelType::Water)
     }
 }
 
 impl IsEmpty for VoxelType {
     fn is_empty(&self) -> bool {
-        match self {
-            VoxelType::Air => true,
-            VoxelType::Stone | VoxelType::Grass | VoxelType::Gold | VoxelType::Water => false,
-        }
+        *self == VoxelType::Air
     }
 }
 
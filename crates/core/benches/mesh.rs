@@ -0,0 +1,60 @@
+//! Measures how long it takes to mesh one chunk of hilly terrain with each method, with the
+//! buffers reused between chunks as the game does and with fresh buffers every time.
+//!
+//! Run with `cargo bench -p colonize_core`.
+use building_blocks::{
+    core::{Extent3i, Point3i, PointN},
+    prelude::copy_extent,
+    storage::{Array, Array3},
+};
+use colonize_common::{Voxel, VoxelDistance, VoxelType};
+use colonize_core::{MeshBuffers, MeshGenerationMethod, MesherBuffers};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const CHUNK_SIZE: i32 = 128;
+
+/// Rolling hills of soil over stone, which cross the chunk a few times in each direction.
+fn hilly_chunk(method: MeshGenerationMethod) -> Array3<Voxel> {
+    let chunk_extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([CHUNK_SIZE; 3]));
+    let padded_extent = method.padded_chunk_extent(&chunk_extent);
+    Array3::fill_with(padded_extent, |p: &Point3i| {
+        let height =
+            CHUNK_SIZE as f32 / 2. + 16. * (p.x() as f32 / 20.).sin() * (p.z() as f32 / 30.).cos();
+        let distance = (p.y() as f32 - height).max(-127.).min(127.);
+        let voxel_type = if distance > 0. {
+            VoxelType::Air
+        } else if distance > -4. {
+            VoxelType::Soil
+        } else {
+            VoxelType::Stone
+        };
+        Voxel::new(voxel_type, VoxelDistance(distance as i8))
+    })
+}
+
+fn mesh_chunk(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mesh_chunk");
+    group.sample_size(10);
+    for method in MeshGenerationMethod::ALL.iter() {
+        let chunk = hilly_chunk(*method);
+        group.bench_with_input(BenchmarkId::new("pooled", method), &chunk, |b, chunk| {
+            b.iter(|| {
+                MeshBuffers::with(|buffers| {
+                    let (array, meshers) = buffers.array_and_meshers(*chunk.extent());
+                    copy_extent(chunk.extent(), chunk, array);
+                    method.generate_mesh(array, meshers)
+                })
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("fresh", method), &chunk, |b, chunk| {
+            b.iter(|| {
+                let array = chunk.clone();
+                method.generate_mesh(&array, &mut MesherBuffers::default())
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, mesh_chunk);
+criterion_main!(benches);
@@ -0,0 +1,359 @@
+use building_blocks::{
+    core::{Extent3i, Point3i, PointN},
+    storage::{Array, Array3, ForEach, Get},
+};
+use colonize_common::Voxel;
+
+/// Covers the solid voxels in an extent with as few boxes as it can, for use as colliders.
+///
+/// Runs of voxels are merged vertically first, so that a column of solid ground becomes a
+/// single tall box, and then columns are merged with their neighbours wherever those are solid
+/// over the same span. Boxes never overlap, and never leave the extent.
+pub fn greedy_cuboids(voxels: &Array3<Voxel>, extent: &Extent3i) -> Vec<Extent3i> {
+    let shape = extent.shape;
+    let (width, height, depth) = (shape.x() as usize, shape.y() as usize, shape.z() as usize);
+    let index = |x: usize, y: usize, z: usize| (z * width + x) * height + y;
+
+    // The solid voxels which aren't in a box yet.
+    let mut remaining = vec![false; width * height * depth];
+    voxels.for_each(extent, |p: Point3i, voxel: Voxel| {
+        let local = p - extent.minimum;
+        remaining[index(local.x() as usize, local.y() as usize, local.z() as usize)] =
+            voxel.voxel_type().collidable();
+    });
+
+    let mut cuboids = Vec::new();
+    for z in 0..depth {
+        for x in 0..width {
+            for y in 0..height {
+                if !remaining[index(x, y, z)] {
+                    continue;
+                }
+
+                let mut rise = 1;
+                while y + rise < height && remaining[index(x, y + rise, z)] {
+                    rise += 1;
+                }
+                let column = |remaining: &[bool], x: usize, z: usize| {
+                    (y..y + rise).all(|y| remaining[index(x, y, z)])
+                };
+                let mut run = 1;
+                while x + run < width && column(&remaining, x + run, z) {
+                    run += 1;
+                }
+                let mut reach = 1;
+                while z + reach < depth && (x..x + run).all(|x| column(&remaining, x, z + reach)) {
+                    reach += 1;
+                }
+
+                for cz in z..z + reach {
+                    for cx in x..x + run {
+                        for cy in y..y + rise {
+                            remaining[index(cx, cy, cz)] = false;
+                        }
+                    }
+                }
+                cuboids.push(Extent3i::from_min_and_shape(
+                    extent.minimum + PointN([x as i32, y as i32, z as i32]),
+                    PointN([run as i32, rise as i32, reach as i32]),
+                ));
+            }
+        }
+    }
+
+    cuboids
+}
+
+/// The corners of each face of a box, as bit masks of which of its axes are at their maximum,
+/// wound counter-clockwise when seen from outside of the box. The faces come in the order -X,
+/// +X, -Y, +Y, -Z, +Z.
+const CUBOID_FACES: [[u32; 4]; 6] = [
+    [0b000, 0b100, 0b110, 0b010],
+    [0b001, 0b011, 0b111, 0b101],
+    [0b000, 0b001, 0b101, 0b100],
+    [0b010, 0b110, 0b111, 0b011],
+    [0b000, 0b010, 0b011, 0b001],
+    [0b100, 0b101, 0b111, 0b110],
+];
+
+/// Joins the surfaces of a set of boxes into one triangle mesh, so that all of the boxes can
+/// be collided with as a single shape. Returns the vertices and the triangles.
+///
+/// Only the parts of the boxes' faces which are open to the air are kept. Where a box touches
+/// another, or a solid voxel outside of the boxes (such as in the padding from a neighbouring
+/// chunk), the faces would be hidden inside the ground, and their edges would catch whatever
+/// slides across the seam.
+pub fn cuboid_trimesh(
+    voxels: &Array3<Voxel>,
+    cuboids: &[Extent3i],
+) -> (Vec<[f32; 3]>, Vec<[u32; 3]>) {
+    let extent = *voxels.extent();
+    let is_solid = |p: Point3i| extent.contains(&p) && voxels.get(&p).voxel_type().collidable();
+
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut open = Vec::new();
+    for cuboid in cuboids {
+        for (face, corners) in CUBOID_FACES.iter().enumerate() {
+            // The face lies across the other two axes, and is split into a grid of one cell
+            // per voxel, each of which is open if the voxel just outside of it isn't solid.
+            let axis = face / 2;
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let mut outside = cuboid.minimum;
+            if face % 2 == 0 {
+                outside.0[axis] -= 1;
+            } else {
+                outside.0[axis] += cuboid.shape.0[axis];
+            }
+            let (width, height) = (cuboid.shape.0[u] as usize, cuboid.shape.0[v] as usize);
+            open.clear();
+            for j in 0..height {
+                for i in 0..width {
+                    let mut p = outside;
+                    p.0[u] += i as i32;
+                    p.0[v] += j as i32;
+                    open.push(!is_solid(p));
+                }
+            }
+
+            // Merge the open cells into as few rectangles as possible, the same way the boxes
+            // themselves are merged.
+            for j in 0..height {
+                for i in 0..width {
+                    if !open[j * width + i] {
+                        continue;
+                    }
+                    let mut run = 1;
+                    while i + run < width && open[j * width + i + run] {
+                        run += 1;
+                    }
+                    let mut rows = 1;
+                    while j + rows < height && (i..i + run).all(|i| open[(j + rows) * width + i]) {
+                        rows += 1;
+                    }
+                    for row in j..j + rows {
+                        for cell in &mut open[row * width + i..row * width + i + run] {
+                            *cell = false;
+                        }
+                    }
+
+                    // The rectangle is the face of a box as thick as the original, but only
+                    // as wide and high as the rectangle, so it has the same corners.
+                    let mut minimum = cuboid.minimum;
+                    minimum.0[u] += i as i32;
+                    minimum.0[v] += j as i32;
+                    let mut shape = cuboid.shape;
+                    shape.0[u] = run as i32;
+                    shape.0[v] = rows as i32;
+                    let first = vertices.len() as u32;
+                    for corner in corners {
+                        let coordinate = |bit: u32, min: i32, size: i32| {
+                            (min + size * ((corner >> bit) & 1) as i32) as f32
+                        };
+                        vertices.push([
+                            coordinate(0, minimum.x(), shape.x()),
+                            coordinate(1, minimum.y(), shape.y()),
+                            coordinate(2, minimum.z(), shape.z()),
+                        ]);
+                    }
+                    triangles.push([first, first + 1, first + 2]);
+                    triangles.push([first, first + 2, first + 3]);
+                }
+            }
+        }
+    }
+    (vertices, triangles)
+}
+
+#[cfg(test)]
+mod test {
+    use building_blocks::storage::GetMut;
+    use colonize_common::{VoxelDistance, VoxelType, EMPTY_VOXEL};
+
+    use super::*;
+
+    fn solid() -> Voxel {
+        Voxel::new(VoxelType::Stone, VoxelDistance(-1))
+    }
+
+    fn extent(minimum: [i32; 3], shape: [i32; 3]) -> Extent3i {
+        Extent3i::from_min_and_shape(PointN(minimum), PointN(shape))
+    }
+
+    type Triangle = [[f32; 3]; 3];
+
+    fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+        [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+    }
+
+    fn cross(u: [f32; 3], v: [f32; 3]) -> [f32; 3] {
+        [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ]
+    }
+
+    fn triangles_of(voxels: &Array3<Voxel>, cuboids: &[Extent3i]) -> Vec<Triangle> {
+        let (vertices, triangles) = cuboid_trimesh(voxels, cuboids);
+        triangles
+            .iter()
+            .map(|[a, b, c]| {
+                [
+                    vertices[*a as usize],
+                    vertices[*b as usize],
+                    vertices[*c as usize],
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn solid_block_is_one_cuboid() {
+        let block = extent([-2, 0, 3], [4, 5, 6]);
+        let voxels = Array3::fill(block, solid());
+        assert_eq!(greedy_cuboids(&voxels, &block), vec![block]);
+    }
+
+    #[test]
+    fn air_and_water_have_no_cuboids() {
+        let block = extent([0; 3], [4; 3]);
+        let mut voxels = Array3::fill(block, EMPTY_VOXEL);
+        *voxels.get_mut(&PointN([1, 1, 1])) = Voxel::new(VoxelType::Water, VoxelDistance(-1));
+        assert!(greedy_cuboids(&voxels, &block).is_empty());
+    }
+
+    #[test]
+    fn steps_are_split_into_layers() {
+        let block = extent([0; 3], [4, 4, 4]);
+        let voxels = Array3::fill_with(block, |p: &Point3i| {
+            if p.y() <= p.x() / 2 {
+                solid()
+            } else {
+                EMPTY_VOXEL
+            }
+        });
+        let cuboids = greedy_cuboids(&voxels, &block);
+        assert_eq!(
+            cuboids,
+            vec![extent([0, 0, 0], [4, 1, 4]), extent([2, 1, 0], [2, 1, 4])]
+        );
+    }
+
+    #[test]
+    fn cuboids_cover_each_solid_voxel_once() {
+        let block = extent([0; 3], [8; 3]);
+        let voxels = Array3::fill_with(block, |p: &Point3i| {
+            if (p.x() * 7 + p.y() * 3 + p.z() * 5) % 4 != 0 {
+                solid()
+            } else {
+                EMPTY_VOXEL
+            }
+        });
+        let cuboids = greedy_cuboids(&voxels, &block);
+        voxels.for_each(&block, |p: Point3i, voxel: Voxel| {
+            let covering = cuboids.iter().filter(|c| c.contains(&p)).count();
+            let expected = if voxel.voxel_type().collidable() {
+                1
+            } else {
+                0
+            };
+            assert_eq!(covering, expected, "{:?} is covered {} times", p, covering);
+        });
+    }
+
+    #[test]
+    fn trimesh_faces_point_out_of_their_boxes() {
+        let cuboids = vec![extent([0; 3], [1, 2, 3]), extent([5, -1, 0], [2, 2, 2])];
+        let voxels = Array3::fill_with(extent([-1; 3], [9, 5, 5]), |p: &Point3i| {
+            if cuboids.iter().any(|c| c.contains(p)) {
+                solid()
+            } else {
+                EMPTY_VOXEL
+            }
+        });
+        let triangles = triangles_of(&voxels, &cuboids);
+        // Boxes on their own are open to the air all over, so each face is a single quad.
+        assert_eq!(triangles.len(), 24);
+
+        let dot = |a: [f32; 3], b: [f32; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        for (cuboid, triangles) in cuboids.iter().zip(triangles.chunks(12)) {
+            let (min, shape) = (cuboid.minimum, cuboid.shape);
+            let center = [
+                min.x() as f32 + shape.x() as f32 / 2.,
+                min.y() as f32 + shape.y() as f32 / 2.,
+                min.z() as f32 + shape.z() as f32 / 2.,
+            ];
+            for [a, b, c] in triangles {
+                // Every vertex is a corner of its box.
+                let is_corner = |v: f32, min: i32, size: i32| {
+                    (v - min as f32).abs() < 1e-6 || (v - (min + size) as f32).abs() < 1e-6
+                };
+                for vertex in [a, b, c].iter() {
+                    assert!(is_corner(vertex[0], min.x(), shape.x()));
+                    assert!(is_corner(vertex[1], min.y(), shape.y()));
+                    assert!(is_corner(vertex[2], min.z(), shape.z()));
+                }
+                let normal = cross(sub(*b, *a), sub(*c, *a));
+                assert!(dot(normal, sub(*a, center)) > 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn seams_between_boxes_have_no_triangles() {
+        // A wall standing on a floor, which are merged into two boxes touching along the
+        // plane x = 1.
+        let block = extent([0; 3], [4; 3]);
+        let voxels = Array3::fill_with(block, |p: &Point3i| {
+            if p.x() == 0 || p.y() == 0 {
+                solid()
+            } else {
+                EMPTY_VOXEL
+            }
+        });
+        let cuboids = greedy_cuboids(&voxels, &block);
+        assert_eq!(
+            cuboids,
+            vec![extent([0, 0, 0], [1, 4, 4]), extent([1, 0, 0], [3, 1, 4])]
+        );
+
+        for triangle in triangles_of(&voxels, &cuboids) {
+            let on_seam = triangle.iter().all(|v| v[0] == 1.0 && v[1] <= 1.0);
+            assert!(!on_seam, "{:?} is inside the seam", triangle);
+        }
+    }
+
+    #[test]
+    fn trimesh_covers_exactly_the_open_faces_of_the_voxels() {
+        let block = extent([0; 3], [8; 3]);
+        let voxels = Array3::fill_with(block, |p: &Point3i| {
+            if (p.x() * 7 + p.y() * 3 + p.z() * 5) % 4 != 0 {
+                solid()
+            } else {
+                EMPTY_VOXEL
+            }
+        });
+        let is_solid = |p: Point3i| block.contains(&p) && voxels.get(&p).voxel_type().collidable();
+        let mut open_faces = 0;
+        voxels.for_each(&block, |p: Point3i, voxel: Voxel| {
+            if !voxel.voxel_type().collidable() {
+                return;
+            }
+            for offset in [[1, 0, 0], [0, 1, 0], [0, 0, 1]].iter() {
+                let offset = PointN(*offset);
+                open_faces += !is_solid(p + offset) as usize + !is_solid(p - offset) as usize;
+            }
+        });
+
+        let cuboids = greedy_cuboids(&voxels, &block);
+        let area: f32 = triangles_of(&voxels, &cuboids)
+            .iter()
+            .map(|[a, b, c]| {
+                let [x, y, z] = cross(sub(*b, *a), sub(*c, *a));
+                (x * x + y * y + z * z).sqrt() / 2.
+            })
+            .sum();
+        assert!((area - open_faces as f32).abs() < 1e-3);
+    }
+}
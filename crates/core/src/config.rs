@@ -0,0 +1,313 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use building_blocks::core::{Extent2i, Extent3i, PointN};
+use colonize_common::VoxelType;
+use serde::Deserialize;
+
+use crate::{HeightMap, HeightMapError, MaterialMask, Prefab, PrefabError};
+
+/// Parameters for a fractal noise function.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct NoiseConfig {
+    pub frequency: f64,
+    pub lacunarity: f64,
+    pub persistence: f64,
+    pub octaves: usize,
+}
+
+/// Everything needed to generate a world. The game and the worldgen tool both read these
+/// from the same preset file, so that a world can be tuned offline and then played.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct WorldGenConfig {
+    /// Seed for the noise functions. A new seed is picked every time if this isn't set.
+    #[serde(default)]
+    pub seed: Option<u32>,
+    pub elevation: NoiseConfig,
+    /// The lowest and highest the surface can be, in voxels.
+    pub min_elevation: f64,
+    pub max_elevation: f64,
+    /// Open air below this Y-level is flooded with water.
+    pub sea_level: i32,
+    /// Width and depth of the region, in voxels.
+    pub region_size: i32,
+    /// Height of the region, in voxels.
+    pub region_height: i32,
+    /// A grayscale PNG or 16-bit RAW image to use in place of the elevation noise, relative to
+    /// the asset root. It's stretched over the whole region, with black at `min_elevation` and
+    /// white at `max_elevation`.
+    #[serde(default)]
+    pub height_map: Option<PathBuf>,
+    /// Images which override the material of the surface, in the same format and relative to
+    /// the same directory as the height map. Later masks take priority over earlier ones.
+    #[serde(default)]
+    pub material_masks: Vec<MaterialMaskConfig>,
+    /// The layers of rock under the soil, from the top down. If a preset doesn't list any, it
+    /// uses the ones shared by all presets.
+    #[serde(default)]
+    pub strata: Vec<StratumConfig>,
+    /// Structures to place on the surface. If a preset doesn't list any, it uses the ones
+    /// shared by all presets.
+    #[serde(default)]
+    pub prefabs: Vec<Prefab>,
+}
+
+/// A layer of rock, such as sedimentary or igneous rock.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct StratumConfig {
+    pub name: String,
+    /// Average thickness, in voxels.
+    pub thickness: f64,
+    /// How far noise moves the bottom of the layer up or down, in voxels.
+    #[serde(default)]
+    pub variation: f64,
+    /// The kinds of stone the layer is made of.
+    pub stones: Vec<VoxelType>,
+    /// The ores which can be found in the layer.
+    #[serde(default)]
+    pub ores: Vec<OreConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OreConfig {
+    pub ore: VoxelType,
+    /// Roughly how much of the layer is made of the ore, from 0 (none of it) to 1 (all of it).
+    pub frequency: f64,
+}
+
+impl OreConfig {
+    /// Veins form where noise, which runs from -1 to 1, is above this.
+    pub fn threshold(&self) -> f64 {
+        1. - 2. * self.frequency
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MaterialMaskConfig {
+    pub material: VoxelType,
+    pub path: PathBuf,
+}
+
+impl WorldGenConfig {
+    /// The extent of the region. The origin is in the middle of the region.
+    pub fn extent(&self) -> Extent3i {
+        let minimum = PointN([
+            -self.region_size / 2,
+            -self.region_height / 2,
+            -self.region_size / 2,
+        ]);
+        let shape = PointN([self.region_size, self.region_height, self.region_size]);
+        Extent3i::from_min_and_shape(minimum, shape)
+    }
+
+    /// Loads the height map, if one is set, stretched over the region. Relative paths are
+    /// resolved against the asset root; absolute ones are used as they are.
+    pub fn load_height_map(&self, asset_root: &Path) -> Result<Option<HeightMap>, HeightMapError> {
+        let region = self.region_2d();
+        match &self.height_map {
+            Some(path) => Ok(Some(
+                HeightMap::open(&asset_root.join(path))?.stretch_over(region),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Loads all of the material masks, stretched over the region. Paths are resolved the same
+    /// way as the height map's.
+    pub fn load_material_masks(
+        &self,
+        asset_root: &Path,
+    ) -> Result<Vec<MaterialMask>, HeightMapError> {
+        let region = self.region_2d();
+        self.material_masks
+            .iter()
+            .map(|mask| {
+                HeightMap::open(&asset_root.join(&mask.path)).map(|image| MaterialMask {
+                    voxel_type: mask.material,
+                    mask: image.stretch_over(region),
+                })
+            })
+            .collect()
+    }
+
+    fn region_2d(&self) -> Extent2i {
+        let extent = self.extent();
+        Extent2i::from_min_and_shape(extent.minimum.xz(), extent.shape.xz())
+    }
+}
+
+#[derive(Debug)]
+pub enum PresetError {
+    Parse(ron::Error),
+    /// The preset named as the default doesn't exist.
+    MissingDefault(String),
+    /// A prefab whose layout is invalid.
+    Prefab(String, PrefabError),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresetError::Parse(e) => write!(f, "failed to parse presets: {}", e),
+            PresetError::MissingDefault(name) => {
+                write!(f, "default preset {:?} is not defined", name)
+            }
+            PresetError::Prefab(name, e) => write!(f, "invalid prefab {:?}: {}", name, e),
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+
+/// A set of named world generation presets, as loaded from a RON file.
+#[derive(Debug, Deserialize)]
+pub struct WorldGenPresets {
+    /// The name of the preset used when none is chosen explicitly.
+    pub default: String,
+    /// The strata used by presets which don't define their own.
+    #[serde(default)]
+    strata: Vec<StratumConfig>,
+    /// The prefabs used by presets which don't define their own.
+    #[serde(default)]
+    prefabs: Vec<Prefab>,
+    presets: BTreeMap<String, WorldGenConfig>,
+}
+
+impl WorldGenPresets {
+    pub fn from_ron(data: &str) -> Result<Self, PresetError> {
+        let mut presets: Self = ron::de::from_str(data).map_err(PresetError::Parse)?;
+        if !presets.presets.contains_key(&presets.default) {
+            return Err(PresetError::MissingDefault(presets.default));
+        }
+        for config in presets.presets.values_mut() {
+            if config.strata.is_empty() {
+                config.strata = presets.strata.clone();
+            }
+            if config.prefabs.is_empty() {
+                config.prefabs = presets.prefabs.clone();
+            }
+            for prefab in &config.prefabs {
+                prefab
+                    .validate()
+                    .map_err(|e| PresetError::Prefab(prefab.name.clone(), e))?;
+            }
+        }
+        Ok(presets)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WorldGenConfig> {
+        self.presets.get(name)
+    }
+
+    /// The names of all of the presets, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.presets.keys()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// The settings of the bundled "temperate" preset.
+    pub(crate) fn config() -> WorldGenConfig {
+        WorldGenConfig {
+            seed: None,
+            elevation: NoiseConfig {
+                frequency: 0.001,
+                lacunarity: 4.0,
+                persistence: 0.7,
+                octaves: 8,
+            },
+            min_elevation: -128.,
+            max_elevation: 128.,
+            sea_level: 0,
+            region_size: 512,
+            region_height: 512,
+            height_map: None,
+            material_masks: Vec::new(),
+            strata: Vec::new(),
+            prefabs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn bundled_presets_parse() {
+        let data = include_str!("../../../assets/data/worldgen.ron");
+        let presets = WorldGenPresets::from_ron(data).unwrap();
+        assert!(presets.get(&presets.default).is_some());
+        assert!(presets.names().count() > 1);
+        // Every preset gets the shared strata and prefabs.
+        assert!(presets
+            .names()
+            .all(|name| !presets.get(name).unwrap().strata.is_empty()));
+        assert!(presets
+            .names()
+            .all(|name| !presets.get(name).unwrap().prefabs.is_empty()));
+    }
+
+    #[test]
+    fn missing_default_preset_is_rejected() {
+        let data = r#"(default: "missing", presets: {})"#;
+        assert!(WorldGenPresets::from_ron(data).is_err());
+    }
+
+    #[test]
+    fn invalid_prefabs_are_rejected() {
+        let data = r##"(
+            default: "flat",
+            prefabs: [
+                (
+                    name: "broken",
+                    count: 1,
+                    max_roughness: 1.0,
+                    palette: {'#': Stone},
+                    layers: [["#", "?"]],
+                ),
+            ],
+            presets: {"flat": (
+                elevation: (frequency: 1.0, lacunarity: 1.0, persistence: 1.0, octaves: 1),
+                min_elevation: 0.0,
+                max_elevation: 0.0,
+                sea_level: 0,
+                region_size: 8,
+                region_height: 8,
+            )},
+        )"##;
+        match WorldGenPresets::from_ron(data) {
+            Err(PresetError::Prefab(name, PrefabError::UnknownSymbol('?'))) => {
+                assert_eq!(name, "broken")
+            }
+            result => panic!("expected an invalid prefab, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn height_maps_are_found_in_the_asset_root() {
+        let asset_root =
+            std::env::temp_dir().join(format!("colonize-assets-{}", std::process::id()));
+        std::fs::create_dir_all(asset_root.join("maps")).unwrap();
+        let pixels: Vec<u8> = [0u16, 1000, 2000, u16::MAX]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        std::fs::write(asset_root.join("maps/island.r16"), pixels).unwrap();
+
+        let config = WorldGenConfig {
+            height_map: Some(PathBuf::from("maps/island.r16")),
+            ..config()
+        };
+        let loaded = config.load_height_map(&asset_root);
+        std::fs::remove_dir_all(&asset_root).unwrap();
+        assert!(loaded.unwrap().is_some());
+        assert!(config.load_height_map(Path::new("elsewhere")).is_err());
+    }
+
+    #[test]
+    fn extent_is_centered_on_origin() {
+        let extent = config().extent();
+        assert_eq!(extent.minimum, PointN([-256, -256, -256]));
+        assert_eq!(extent.max(), PointN([255, 255, 255]));
+    }
+}
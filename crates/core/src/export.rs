@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use building_blocks::mesh::PosNormMesh;
+use colonize_common::VoxelType;
+
+/// Writes meshes to a Wavefront OBJ file, which Blender and most other 3D tools can import.
+///
+/// Each mesh becomes an object of its own, using the material of its voxel type. The
+/// materials themselves go in a separate MTL file, written with [`ObjWriter::write_materials`]
+/// once all of the meshes have been added.
+pub struct ObjWriter<W> {
+    obj: W,
+    /// The number of vertices written so far. OBJ indices count from 1 across the whole file.
+    vertices: u32,
+    materials: BTreeSet<usize>,
+}
+
+impl<W: Write> ObjWriter<W> {
+    /// Starts an OBJ file whose materials are in the MTL file with the given name, which should
+    /// be next to the OBJ file.
+    pub fn new(mut obj: W, mtl_file_name: &str) -> io::Result<Self> {
+        writeln!(obj, "# Exported from Colonize")?;
+        writeln!(obj, "mtllib {}", mtl_file_name)?;
+        Ok(Self {
+            obj,
+            vertices: 0,
+            materials: BTreeSet::new(),
+        })
+    }
+
+    pub fn add_mesh(
+        &mut self,
+        name: &str,
+        material: VoxelType,
+        mesh: &PosNormMesh,
+    ) -> io::Result<()> {
+        if mesh.is_empty() {
+            return Ok(());
+        }
+        self.materials.insert(material.index());
+
+        writeln!(self.obj, "o {}", name)?;
+        writeln!(self.obj, "usemtl {:?}", material)?;
+        for [x, y, z] in mesh.positions.iter() {
+            writeln!(self.obj, "v {} {} {}", x, y, z)?;
+        }
+        for [x, y, z] in mesh.normals.iter() {
+            writeln!(self.obj, "vn {} {} {}", x, y, z)?;
+        }
+        for triangle in mesh.indices.chunks(3) {
+            write!(self.obj, "f")?;
+            for index in triangle {
+                let index = self.vertices + index + 1;
+                write!(self.obj, " {}//{}", index, index)?;
+            }
+            writeln!(self.obj)?;
+        }
+        self.vertices += mesh.positions.len() as u32;
+        Ok(())
+    }
+
+    /// Writes the materials of all of the meshes added so far to an MTL file, given the color
+    /// of each as RGBA from 0 to 1. Returns the OBJ writer, flushed.
+    pub fn write_materials(
+        mut self,
+        mut mtl: impl Write,
+        color: impl Fn(VoxelType) -> [f32; 4],
+    ) -> io::Result<W> {
+        writeln!(mtl, "# Exported from Colonize")?;
+        for index in self.materials.iter() {
+            let material = VoxelType::ALL[*index];
+            let [r, g, b, a] = color(material);
+            writeln!(mtl, "newmtl {:?}", material)?;
+            writeln!(mtl, "Kd {} {} {}", r, g, b)?;
+            writeln!(mtl, "d {}", a)?;
+        }
+        mtl.flush()?;
+        self.obj.flush()?;
+        Ok(self.obj)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn triangle(offset: f32) -> PosNormMesh {
+        PosNormMesh {
+            positions: vec![[offset, 0., 0.], [offset + 1., 0., 0.], [offset, 1., 0.]],
+            normals: vec![[0., 0., 1.]; 3],
+            indices: vec![0, 1, 2],
+        }
+    }
+
+    #[test]
+    fn indices_count_across_meshes() {
+        let mut writer = ObjWriter::new(Vec::new(), "world.mtl").unwrap();
+        writer
+            .add_mesh("first", VoxelType::Stone, &triangle(0.))
+            .unwrap();
+        writer
+            .add_mesh("second", VoxelType::Grass, &triangle(2.))
+            .unwrap();
+        let mut mtl = Vec::new();
+        let obj = writer
+            .write_materials(&mut mtl, |material| material.color())
+            .unwrap();
+
+        let obj = String::from_utf8(obj).unwrap();
+        assert!(obj.contains("mtllib world.mtl\n"));
+        assert!(obj.contains("o first\nusemtl Stone\nv 0 0 0\n"));
+        assert!(obj.contains("f 1//1 2//2 3//3\n"));
+        assert!(obj.contains("o second\nusemtl Grass\nv 2 0 0\n"));
+        assert!(obj.contains("f 4//4 5//5 6//6\n"));
+
+        let mtl = String::from_utf8(mtl).unwrap();
+        assert!(mtl.contains("newmtl Stone\nKd 0.5 0.5 0.5\nd 1\n"));
+        assert!(mtl.contains("newmtl Grass\n"));
+        assert!(!mtl.contains("newmtl Water\n"));
+    }
+
+    #[test]
+    fn empty_meshes_are_skipped() {
+        let mut writer = ObjWriter::new(Vec::new(), "world.mtl").unwrap();
+        writer
+            .add_mesh("empty", VoxelType::Water, &PosNormMesh::default())
+            .unwrap();
+        let mut mtl = Vec::new();
+        let obj = writer
+            .write_materials(&mut mtl, |material| material.color())
+            .unwrap();
+        assert!(!String::from_utf8(obj).unwrap().contains("o empty"));
+        assert!(!String::from_utf8(mtl).unwrap().contains("newmtl"));
+    }
+}
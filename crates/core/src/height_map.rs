@@ -0,0 +1,215 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use building_blocks::core::Extent2i;
+use colonize_common::VoxelType;
+use image::ImageFormat;
+
+use crate::Sample;
+
+#[derive(Debug)]
+pub enum HeightMapError {
+    Io(io::Error),
+    Image(image::ImageError),
+    /// A RAW height map whose size isn't that of a square of 16-bit samples.
+    NotSquare(usize),
+}
+
+impl fmt::Display for HeightMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightMapError::Io(e) => write!(f, "failed to read height map: {}", e),
+            HeightMapError::Image(e) => write!(f, "failed to decode height map: {}", e),
+            HeightMapError::NotSquare(len) => write!(
+                f,
+                "a RAW height map must be a square of 16-bit samples, but it is {} bytes long",
+                len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeightMapError {}
+
+/// A hand-authored height map, loaded from an image.
+///
+/// As a [`Sample`], this stands in for elevation noise: it returns values from -1 (black) to
+/// 1 (white), interpolated bilinearly between pixels. By default each pixel covers one
+/// voxel, starting at the origin; use [`HeightMap::stretch_over`] to fit the image to a
+/// region instead.
+#[derive(Clone, Debug)]
+pub struct HeightMap {
+    width: usize,
+    depth: usize,
+    /// Brightness of each pixel from 0 to 1, row by row along the Z axis.
+    values: Vec<f64>,
+    minimum: [f64; 2],
+    pixels_per_voxel: [f64; 2],
+}
+
+impl HeightMap {
+    /// Creates a height map from the brightness of each pixel, from 0 to 1.
+    pub fn new(width: usize, depth: usize, values: Vec<f64>) -> Self {
+        assert!(width > 0 && depth > 0, "height map must not be empty");
+        assert_eq!(
+            values.len(),
+            width * depth,
+            "height map must have one value per pixel"
+        );
+        Self {
+            width,
+            depth,
+            values,
+            minimum: [0., 0.],
+            pixels_per_voxel: [1., 1.],
+        }
+    }
+
+    /// Loads a height map from a file. Files ending in `.raw` or `.r16` are read as RAW height
+    /// maps; anything else is decoded as a PNG.
+    pub fn open(path: &Path) -> Result<Self, HeightMapError> {
+        let bytes = fs::read(path).map_err(HeightMapError::Io)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("raw") | Some("r16") => Self::from_raw16(&bytes),
+            _ => Self::from_png(&bytes),
+        }
+    }
+
+    /// Decodes a grayscale PNG, at either 8 or 16 bits per pixel. Colored images are converted
+    /// to grayscale.
+    pub fn from_png(bytes: &[u8]) -> Result<Self, HeightMapError> {
+        let image = image::load_from_memory_with_format(bytes, ImageFormat::Png)
+            .map_err(HeightMapError::Image)?
+            .to_luma16();
+        let values = image
+            .pixels()
+            .map(|p| p.0[0] as f64 / u16::MAX as f64)
+            .collect();
+        Ok(Self::new(
+            image.width() as usize,
+            image.height() as usize,
+            values,
+        ))
+    }
+
+    /// Reads a square RAW height map of little-endian 16-bit samples, as exported by most
+    /// terrain editors.
+    pub fn from_raw16(bytes: &[u8]) -> Result<Self, HeightMapError> {
+        let samples = bytes.len() / 2;
+        let side = (samples as f64).sqrt().round() as usize;
+        if bytes.len() % 2 != 0 || side == 0 || side * side != samples {
+            return Err(HeightMapError::NotSquare(bytes.len()));
+        }
+        let values = bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as f64 / u16::MAX as f64)
+            .collect();
+        Ok(Self::new(side, side, values))
+    }
+
+    /// Scales the height map so that the whole image covers the given region.
+    pub fn stretch_over(mut self, extent: Extent2i) -> Self {
+        let scale = |pixels: usize, voxels: i32| {
+            if voxels > 1 {
+                (pixels - 1) as f64 / (voxels - 1) as f64
+            } else {
+                0.
+            }
+        };
+        self.minimum = [extent.minimum.x() as f64, extent.minimum.y() as f64];
+        self.pixels_per_voxel = [
+            scale(self.width, extent.shape.x()),
+            scale(self.depth, extent.shape.y()),
+        ];
+        self
+    }
+
+    fn pixel(&self, x: usize, z: usize) -> f64 {
+        self.values[z * self.width + x]
+    }
+
+    /// The brightness at the given point, from 0 to 1.
+    fn brightness(&self, point: [f64; 2]) -> f64 {
+        let to_pixel = |axis: usize, size: usize| {
+            let pixel = (point[axis] - self.minimum[axis]) * self.pixels_per_voxel[axis];
+            pixel.max(0.).min((size - 1) as f64)
+        };
+        let u = to_pixel(0, self.width);
+        let v = to_pixel(1, self.depth);
+        let (x0, z0) = (u.floor() as usize, v.floor() as usize);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.depth - 1));
+        let (tx, tz) = (u - x0 as f64, v - z0 as f64);
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let near = lerp(self.pixel(x0, z0), self.pixel(x1, z0), tx);
+        let far = lerp(self.pixel(x0, z1), self.pixel(x1, z1), tx);
+        lerp(near, far, tz)
+    }
+}
+
+impl Sample<[f64; 2], f64> for HeightMap {
+    fn get(&self, point: [f64; 2]) -> f64 {
+        self.brightness(point) * 2. - 1.
+    }
+}
+
+/// Overrides the material of the surface layer wherever a mask image is brighter than mid-grey.
+pub struct MaterialMask {
+    pub voxel_type: VoxelType,
+    pub mask: HeightMap,
+}
+
+impl MaterialMask {
+    pub(crate) fn covers(&self, point: [f64; 2]) -> bool {
+        self.mask.brightness(point) > 0.5
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use building_blocks::core::PointN;
+
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn interpolates_bilinearly() {
+        let height_map = HeightMap::new(2, 2, vec![0., 1., 0., 1.]);
+        assert_close(height_map.get([0., 0.]), -1.);
+        assert_close(height_map.get([1., 0.]), 1.);
+        assert_close(height_map.get([0.5, 0.5]), 0.);
+        assert_close(height_map.get([0.25, 1.]), -0.5);
+        // Points outside the image take the value of the nearest edge.
+        assert_close(height_map.get([5., -5.]), 1.);
+    }
+
+    #[test]
+    fn stretches_over_region() {
+        let height_map = HeightMap::new(2, 2, vec![0., 1., 0., 1.]).stretch_over(
+            Extent2i::from_min_and_shape(PointN([-5, -5]), PointN([11, 11])),
+        );
+        assert_close(height_map.get([-5., 0.]), -1.);
+        assert_close(height_map.get([0., 0.]), 0.);
+        assert_close(height_map.get([5., 0.]), 1.);
+    }
+
+    #[test]
+    fn reads_raw16() {
+        let bytes = [0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00];
+        let height_map = HeightMap::from_raw16(&bytes).unwrap();
+        assert_close(height_map.get([1., 0.]), 1.);
+        assert_close(height_map.get([1., 1.]), -1.);
+
+        assert!(HeightMap::from_raw16(&bytes[..6]).is_err());
+    }
+}
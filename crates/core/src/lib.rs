@@ -1,5 +1,25 @@
+mod collider;
+mod config;
+mod export;
+mod height_map;
+mod mesh;
+mod prefab;
+mod strata;
 mod terrain;
+mod texture;
 mod util;
 
-pub use terrain::{generate_map, generate_precise_map, NoiseSample, Sample};
+pub use collider::{cuboid_trimesh, greedy_cuboids};
+pub use config::{
+    MaterialMaskConfig, NoiseConfig, OreConfig, PresetError, StratumConfig, WorldGenConfig,
+    WorldGenPresets,
+};
+pub use export::ObjWriter;
+pub use height_map::{HeightMap, HeightMapError, MaterialMask};
+pub use mesh::{add_skirts, MeshBuffers, MeshGenerationMethod, MesherBuffers};
+pub use prefab::{Prefab, PrefabError};
+pub use terrain::{
+    generate_map, generate_precise_map, generate_precise_map_with_masks, NoiseSample, Sample,
+};
+pub use texture::{material_texture, TEXTURE_SIZE};
 pub use util::array_int_to_float;
@@ -0,0 +1,736 @@
+//! Turns voxels into meshes, split up by material so that each can be drawn differently.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+use bevy::log::trace;
+use building_blocks::{
+    core::{Extent3i, Neighborhoods, Point3i, PointN},
+    mesh::{
+        adf_dual_contour, greedy_quads, padded_greedy_quads_chunk_extent, surface_nets,
+        AdfDualContourBuffer, GreedyQuadsBuffer, PosNormMesh, SignedDistance, SurfaceNetsBuffer,
+    },
+    storage::{
+        padded_adf_chunk_extent, Adf, Array, Array3, ForEachMut, Get, GetUncheckedRelease, Local,
+        Stride, TransformMap,
+    },
+};
+use colonize_common::{Voxel, VoxelDistance, VoxelType, EMPTY_VOXEL, NUM_VOXEL_TYPES};
+
+/// How close to the side of a chunk a mesh's edge has to be to get a skirt.
+const SKIRT_MARGIN: f32 = 1.;
+/// How far skirts hang down below the edges of meshes, in voxels. This needs to be at least as
+/// big as the cracks between levels of detail.
+const SKIRT_DEPTH: f32 = 8.;
+/// How far in front of each vertex the voxels are checked for ambient occlusion, in voxels.
+const OCCLUSION_DISTANCES: [f32; 2] = [1., 2.];
+
+/// How the terrain is turned into meshes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshGenerationMethod {
+    /// Blocky voxels, with faces merged into as few quads as possible.
+    GreedyQuads,
+    /// Smooth terrain, with a vertex in every voxel on the surface.
+    SurfaceNets,
+    /// Smooth terrain, simplified where it's flat.
+    AdfDualContour,
+}
+
+impl Default for MeshGenerationMethod {
+    fn default() -> Self {
+        MeshGenerationMethod::AdfDualContour
+    }
+}
+
+impl fmt::Display for MeshGenerationMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshGenerationMethod::GreedyQuads => write!(f, "greedy quads"),
+            MeshGenerationMethod::SurfaceNets => write!(f, "surface nets"),
+            MeshGenerationMethod::AdfDualContour => write!(f, "ADF dual contouring"),
+        }
+    }
+}
+
+impl MeshGenerationMethod {
+    pub const ALL: [MeshGenerationMethod; 3] = [
+        MeshGenerationMethod::GreedyQuads,
+        MeshGenerationMethod::SurfaceNets,
+        MeshGenerationMethod::AdfDualContour,
+    ];
+
+    /// The extent of voxels needed to mesh a chunk, including the padding around it.
+    pub fn padded_chunk_extent(&self, chunk_extent: &Extent3i) -> Extent3i {
+        match self {
+            MeshGenerationMethod::AdfDualContour => padded_adf_chunk_extent(chunk_extent),
+            MeshGenerationMethod::GreedyQuads | MeshGenerationMethod::SurfaceNets => {
+                padded_greedy_quads_chunk_extent(chunk_extent)
+            }
+        }
+    }
+
+    /// The extent of voxels needed to mesh a slice through a chunk at a Y-level: a layer three
+    /// voxels thick around it, with the top layer left empty so that the slice is capped.
+    pub fn padded_slice_extent(&self, chunk_extent: &Extent3i, y: i32) -> Extent3i {
+        let mut padded_layer_extent = self.padded_chunk_extent(chunk_extent);
+        *padded_layer_extent.shape.y_mut() = 3;
+        *padded_layer_extent.minimum.y_mut() = y - 1;
+        padded_layer_extent
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            MeshGenerationMethod::GreedyQuads => MeshGenerationMethod::SurfaceNets,
+            MeshGenerationMethod::SurfaceNets => MeshGenerationMethod::AdfDualContour,
+            MeshGenerationMethod::AdfDualContour => MeshGenerationMethod::GreedyQuads,
+        }
+    }
+
+    /// Meshes the voxels in a padded array. Returns `None` if there's nothing to draw.
+    pub fn generate_mesh(
+        &self,
+        padded_array: &Array3<Voxel>,
+        buffers: &mut MesherBuffers,
+    ) -> Option<HashMap<VoxelType, PosNormMesh>> {
+        match *self {
+            MeshGenerationMethod::GreedyQuads => {
+                generate_mesh_with_greedy_quads(padded_array, buffers)
+            }
+            MeshGenerationMethod::SurfaceNets => {
+                generate_mesh_with_surface_nets(padded_array, buffers)
+            }
+            MeshGenerationMethod::AdfDualContour => {
+                generate_mesh_with_adf_dual_contour(padded_array, buffers)
+            }
+        }
+    }
+
+    /// The extent of voxels needed to mesh a chunk at a level of detail with
+    /// [`MeshGenerationMethod::generate_lod_mesh`].
+    pub fn lod_extent(&self, chunk_extent: &Extent3i, lod: u8) -> Extent3i {
+        let factor = 1 << lod;
+        let coarse_chunk_extent = Extent3i::from_min_and_shape(
+            map_axes(chunk_extent.minimum, |v| v / factor),
+            map_axes(chunk_extent.shape, |v| v / factor),
+        );
+        let coarse_padded_extent = self.padded_chunk_extent(&coarse_chunk_extent);
+        Extent3i::from_min_and_shape(
+            map_axes(coarse_padded_extent.minimum, |v| v * factor),
+            map_axes(coarse_padded_extent.shape, |v| v * factor),
+        )
+    }
+
+    /// Meshes a chunk at a lower level of detail, by downsampling its voxels by a factor of
+    /// `2^lod` and scaling the mesh back up. The array must cover the chunk's
+    /// [`MeshGenerationMethod::lod_extent`].
+    pub fn generate_lod_mesh(
+        &self,
+        array: &Array3<Voxel>,
+        lod: u8,
+        buffers: &mut MesherBuffers,
+    ) -> Option<HashMap<VoxelType, PosNormMesh>> {
+        let factor = 1 << lod;
+        let extent = array.extent();
+        let coarse_extent = Extent3i::from_min_and_shape(
+            map_axes(extent.minimum, |v| v / factor),
+            map_axes(extent.shape, |v| v / factor),
+        );
+
+        // Sample every `factor`th voxel. Distances are measured in voxels, so they shrink by
+        // the same factor, rounding away from zero so that no voxel changes sides of the surface.
+        let coarse_array = Array3::fill_with(coarse_extent, |p: &Point3i| {
+            let voxel = array.get(&map_axes(*p, |v| v * factor));
+            let distance = voxel.distance().0 as f32 / factor as f32;
+            let distance = if distance > 0. {
+                distance.ceil()
+            } else {
+                distance.floor()
+            };
+            Voxel::new(*voxel.voxel_type(), VoxelDistance(distance as i8))
+        });
+
+        let mut meshes = self.generate_mesh(&coarse_array, buffers)?;
+        for mesh in meshes.values_mut() {
+            for position in mesh.positions.iter_mut() {
+                for axis in position.iter_mut() {
+                    *axis *= factor as f32;
+                }
+            }
+        }
+        Some(meshes)
+    }
+
+    /// How occluded each vertex of a mesh is, from 0 (out in the open) to 1 (buried), by the
+    /// proportion of the voxels just in front of it which are solid. Vertices in crevices,
+    /// tunnels and the corners of rooms are darkened by this.
+    pub fn ambient_occlusion(&self, voxels: &Array3<Voxel>, mesh: &PosNormMesh) -> Vec<f32> {
+        // Blocky meshes cover the whole of each voxel, so a voxel's middle is half a voxel past
+        // its coordinates, while smooth meshes put the middle of each voxel at its coordinates.
+        let middle = match self {
+            MeshGenerationMethod::GreedyQuads => 0.5,
+            MeshGenerationMethod::SurfaceNets | MeshGenerationMethod::AdfDualContour => 0.,
+        };
+        let extent = *voxels.extent();
+        let corner_offsets = Point3i::corner_offsets();
+        mesh.positions
+            .iter()
+            .zip(mesh.normals.iter())
+            .map(|(position, normal)| {
+                let length = normal.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-6);
+                let (mut solid, mut total) = (0, 0);
+                for distance in OCCLUSION_DISTANCES.iter() {
+                    let sample = |axis: usize| {
+                        (position[axis] + normal[axis] / length * distance - middle).floor() as i32
+                    };
+                    let cell = PointN([sample(0), sample(1), sample(2)]);
+                    for offset in corner_offsets.iter() {
+                        let point = cell + *offset;
+                        if !extent.contains(&point) {
+                            continue;
+                        }
+                        total += 1;
+                        if voxels.get(&point).voxel_type().collidable() {
+                            solid += 1;
+                        }
+                    }
+                }
+                if total == 0 {
+                    0.
+                } else {
+                    solid as f32 / total as f32
+                }
+            })
+            .collect()
+    }
+}
+
+/// Scratch space for meshing, which is kept between chunks so that remeshing the world doesn't
+/// allocate it all over again for every chunk. Each thread has its own, so that the tasks
+/// meshing chunks in parallel don't contend for them; see [`MeshBuffers::with`].
+#[derive(Default)]
+pub struct MeshBuffers {
+    /// The voxels of the chunk being meshed, along with its padding.
+    array: Option<Array3<Voxel>>,
+    meshers: MesherBuffers,
+}
+
+impl MeshBuffers {
+    /// Runs `f` with the current thread's buffers.
+    pub fn with<T>(f: impl FnOnce(&mut MeshBuffers) -> T) -> T {
+        thread_local! {
+            static BUFFERS: RefCell<MeshBuffers> = RefCell::new(MeshBuffers::default());
+        }
+        BUFFERS.with(|buffers| f(&mut buffers.borrow_mut()))
+    }
+
+    /// An array of empty voxels covering the extent, along with the buffers to mesh it with.
+    /// The array reuses the last one's memory if it's the same shape.
+    pub fn array_and_meshers(
+        &mut self,
+        extent: Extent3i,
+    ) -> (&mut Array3<Voxel>, &mut MesherBuffers) {
+        let reusable = matches!(&self.array, Some(array) if array.extent().shape == extent.shape);
+        if reusable {
+            let array = self.array.as_mut().unwrap();
+            array.set_minimum(extent.minimum);
+            array.for_each_mut(&extent, |_p: Point3i, voxel: &mut Voxel| {
+                *voxel = EMPTY_VOXEL
+            });
+        } else {
+            self.array = Some(Array3::fill(extent, EMPTY_VOXEL));
+        }
+        (self.array.as_mut().unwrap(), &mut self.meshers)
+    }
+}
+
+/// The buffers each of the meshers writes into. The meshers reset them before use, so they
+/// can be reused as they are.
+#[derive(Default)]
+pub struct MesherBuffers {
+    greedy_quads: Option<GreedyQuadsBuffer>,
+    surface_nets: SurfaceNetsBuffer,
+    adf_dual_contour: AdfDualContourBuffer,
+}
+
+/// Hangs a strip of triangles down from the edges of a mesh which lie along the sides of its
+/// chunk. Neighbouring chunks at different levels of detail don't quite meet, and the skirts
+/// cover the cracks between them.
+pub fn add_skirts(mesh: &mut PosNormMesh, chunk_extent: &Extent3i) {
+    let minimum = chunk_extent.minimum;
+    let maximum = chunk_extent.least_upper_bound();
+    let near = |value: f32, bound: i32| (value - bound as f32).abs() <= SKIRT_MARGIN;
+    let on_side = |[x, _, z]: [f32; 3]| {
+        near(x, minimum.x()) || near(x, maximum.x()) || near(z, minimum.z()) || near(z, maximum.z())
+    };
+
+    // Edges which are only part of one triangle are on the edge of the mesh.
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for triangle in mesh.indices.chunks(3) {
+        for (a, b) in [(0, 1), (1, 2), (2, 0)].iter() {
+            let (a, b) = (triangle[*a], triangle[*b]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+
+    let mut lowered: HashMap<u32, u32> = HashMap::new();
+    for ((a, b), count) in edges {
+        if count != 1
+            || !on_side(mesh.positions[a as usize])
+            || !on_side(mesh.positions[b as usize])
+        {
+            continue;
+        }
+        let mut lower = |vertex: u32| {
+            *lowered.entry(vertex).or_insert_with(|| {
+                let [x, y, z] = mesh.positions[vertex as usize];
+                mesh.positions.push([x, y - SKIRT_DEPTH, z]);
+                mesh.normals.push(mesh.normals[vertex as usize]);
+                mesh.positions.len() as u32 - 1
+            })
+        };
+        let (lower_a, lower_b) = (lower(a), lower(b));
+        // Add the skirt with both windings, so that it can be seen from either side.
+        mesh.indices
+            .extend_from_slice(&[a, b, lower_b, a, lower_b, lower_a]);
+        mesh.indices
+            .extend_from_slice(&[a, lower_b, b, a, lower_a, lower_b]);
+    }
+}
+
+fn generate_mesh_with_greedy_quads(
+    padded_array: &Array3<Voxel>,
+    buffers: &mut MesherBuffers,
+) -> Option<HashMap<VoxelType, PosNormMesh>> {
+    let padded_extent = *padded_array.extent();
+    let lookup = |v: Voxel| *v.voxel_type();
+    let voxel_types = TransformMap::new(padded_array, lookup);
+
+    let buffer = buffers
+        .greedy_quads
+        .get_or_insert_with(|| GreedyQuadsBuffer::new(padded_extent));
+    greedy_quads(&voxel_types, &padded_extent, buffer);
+
+    // Separate the meshes by material, so that we can render each voxel type with a different color.
+    let mut meshes: HashMap<VoxelType, PosNormMesh> = HashMap::new();
+    for group in buffer.quad_groups.iter() {
+        for quad in group.quads.iter() {
+            let material = *padded_array.get(&quad.minimum).voxel_type();
+            let mesh = meshes.entry(material).or_insert_with(PosNormMesh::default);
+            group.face.add_quad_to_pos_norm_mesh(quad, mesh);
+        }
+    }
+
+    // If all the meshes are empty, don't return anything.
+    let layer_is_empty = meshes.iter().fold(
+        false,
+        |acc, (_material, mesh)| if acc { acc } else { mesh.is_empty() },
+    );
+
+    if layer_is_empty {
+        None
+    } else {
+        Some(meshes)
+    }
+}
+
+fn generate_mesh_with_adf_dual_contour(
+    padded_array: &Array3<Voxel>,
+    buffers: &mut MesherBuffers,
+) -> Option<HashMap<VoxelType, PosNormMesh>> {
+    let padded_extent = *padded_array.extent();
+
+    let lookup = |v: Voxel| SignedDistance::distance(&v);
+    let voxel_types = TransformMap::new(padded_array, lookup);
+
+    trace!(
+        "Creating ADF from Array3 with extent: {:?}",
+        voxel_types.extent()
+    );
+    let iter_extent = *voxel_types.extent();
+    let adf = Adf::from_array3(&voxel_types, iter_extent, 1.0, 0.2);
+    adf_dual_contour(&adf, &mut buffers.adf_dual_contour);
+
+    let water_mesh = generate_water_mesh(padded_array, &padded_extent, buffers);
+    if buffers.adf_dual_contour.mesh.is_empty() && water_mesh.is_none() {
+        return None;
+    }
+
+    // The mesh is copied out rather than taken, so that the buffer keeps its memory for the
+    // next chunk.
+    let mesh = &buffers.adf_dual_contour.mesh;
+
+    // Separate the meshes by material, so that we can render each voxel type with a different color.
+    let material_counts = count_materials_around(padded_array, &mesh.positions);
+    let mut meshes = split_mesh_by_material(mesh, &material_counts);
+    if let Some(water_mesh) = water_mesh {
+        meshes.insert(VoxelType::Water, water_mesh);
+    }
+
+    Some(meshes)
+}
+
+fn generate_mesh_with_surface_nets(
+    padded_array: &Array3<Voxel>,
+    buffers: &mut MesherBuffers,
+) -> Option<HashMap<VoxelType, PosNormMesh>> {
+    let padded_extent = *padded_array.extent();
+
+    surface_nets(padded_array, &padded_extent, &mut buffers.surface_nets);
+
+    let water_mesh = generate_water_mesh(padded_array, &padded_extent, buffers);
+    if buffers.surface_nets.mesh.is_empty() && water_mesh.is_none() {
+        return None;
+    }
+
+    let lookup = |v: Voxel| *v.voxel_type();
+    let voxel_types = TransformMap::new(padded_array, lookup);
+    let material_counts =
+        count_adjacent_materials(&voxel_types, &buffers.surface_nets.surface_strides);
+    // The mesh is copied out rather than taken, so that the buffer keeps its memory for the
+    // next chunk.
+    let mesh = &buffers.surface_nets.mesh;
+
+    // Separate the meshes by material, so that we can render each voxel type with a different color.
+    let mut meshes = split_mesh_by_material(mesh, &material_counts);
+    if let Some(water_mesh) = water_mesh {
+        meshes.insert(VoxelType::Water, water_mesh);
+    }
+
+    Some(meshes)
+}
+
+/// The smooth meshers only find the surface of solid ground, so water is meshed separately
+/// with greedy quads. Water is flat, so it looks the same either way.
+fn generate_water_mesh(
+    padded_array: &Array3<Voxel>,
+    padded_extent: &Extent3i,
+    buffers: &mut MesherBuffers,
+) -> Option<PosNormMesh> {
+    // Solid voxels keep their types, so that they're opaque and hide the water faces against
+    // them. The ground has its own mesh, and a water face there would just z-fight with it.
+    let lookup = |v: Voxel| *v.voxel_type();
+    let voxel_types = TransformMap::new(padded_array, lookup);
+
+    let buffer = buffers
+        .greedy_quads
+        .get_or_insert_with(|| GreedyQuadsBuffer::new(*padded_extent));
+    greedy_quads(&voxel_types, padded_extent, buffer);
+
+    // The faces of the ground against the water come out too, so only keep the water's own.
+    let mut mesh = PosNormMesh::default();
+    for group in buffer.quad_groups.iter() {
+        for quad in group.quads.iter() {
+            if *padded_array.get(&quad.minimum).voxel_type() == VoxelType::Water {
+                group.face.add_quad_to_pos_norm_mesh(quad, &mut mesh);
+            }
+        }
+    }
+
+    if mesh.is_empty() {
+        None
+    } else {
+        Some(mesh)
+    }
+}
+
+/// Splits a smooth mesh into a mesh for each material. Each triangle goes to whichever
+/// material is most common among the voxels around its vertices, so that boundaries between
+/// materials follow the edges of triangles.
+fn split_mesh_by_material(
+    mesh: &PosNormMesh,
+    material_counts: &[[u8; NUM_VOXEL_TYPES]],
+) -> HashMap<VoxelType, PosNormMesh> {
+    let mut meshes: HashMap<VoxelType, PosNormMesh> = HashMap::new();
+    // The index of each vertex of the original mesh in the mesh of each material it's used by.
+    let mut indices: HashMap<(VoxelType, u32), u32> = HashMap::new();
+    for triangle in mesh.indices.chunks(3) {
+        let mut counts = [0u32; NUM_VOXEL_TYPES];
+        for vertex in triangle {
+            let vertex_counts = &material_counts[*vertex as usize];
+            for (total, count) in counts.iter_mut().zip(vertex_counts.iter()) {
+                *total += *count as u32;
+            }
+        }
+        let material = dominant_material(&counts);
+
+        let material_mesh = meshes.entry(material).or_insert_with(PosNormMesh::default);
+        for vertex in triangle {
+            let index = *indices.entry((material, *vertex)).or_insert_with(|| {
+                material_mesh
+                    .positions
+                    .push(mesh.positions[*vertex as usize]);
+                material_mesh.normals.push(mesh.normals[*vertex as usize]);
+                material_mesh.positions.len() as u32 - 1
+            });
+            material_mesh.indices.push(index);
+        }
+    }
+
+    meshes
+}
+
+/// The material with the highest count. Vertices which somehow aren't next to any solid
+/// voxels are drawn as stone.
+fn dominant_material(counts: &[u32; NUM_VOXEL_TYPES]) -> VoxelType {
+    VoxelType::ALL
+        .iter()
+        .zip(counts.iter())
+        .filter(|(_, count)| **count > 0)
+        .max_by_key(|(_, count)| **count)
+        .map_or(VoxelType::Stone, |(material, _)| *material)
+}
+
+trait TypedVoxel {
+    fn voxel_type(&self) -> VoxelType;
+}
+
+impl TypedVoxel for VoxelType {
+    fn voxel_type(&self) -> VoxelType {
+        *self
+    }
+}
+
+/// Uses a kernel to count the adjacent materials for each surface point. This is necessary because we used dual contouring to
+/// construct the mesh, so a given vertex has 8 adjacent voxels, some of which may be empty. This also assumes that the material
+/// layer can only be one of 0..NUM_VOXEL_TYPES. Only solid voxels are counted, since water is meshed separately.
+fn count_adjacent_materials<A, V>(
+    voxels: &A,
+    surface_strides: &[Stride],
+) -> Vec<[u8; NUM_VOXEL_TYPES]>
+where
+    A: Array<[i32; 3]> + GetUncheckedRelease<Stride, V>,
+    V: TypedVoxel,
+{
+    let mut corner_offsets = [Stride(0); 8];
+    voxels.strides_from_local_points(
+        &Local::localize_points(&Point3i::corner_offsets()),
+        &mut corner_offsets,
+    );
+    let mut material_counts = vec![[0; NUM_VOXEL_TYPES]; surface_strides.len()];
+    for (stride, counts) in surface_strides.iter().zip(material_counts.iter_mut()) {
+        for corner in corner_offsets.iter() {
+            let material = voxels.get(*stride + *corner).voxel_type();
+            // Only add weights from solid voxels.
+            if material.collidable() {
+                counts[material.index()] += 1;
+            }
+        }
+    }
+
+    material_counts
+}
+
+/// Like [`count_adjacent_materials`], but for meshes whose vertices don't line up with the
+/// voxel grid, such as ADF meshes. Counts the solid materials at the corners of the cell
+/// each vertex is in.
+fn count_materials_around(
+    voxels: &Array3<Voxel>,
+    positions: &[[f32; 3]],
+) -> Vec<[u8; NUM_VOXEL_TYPES]> {
+    let extent = *voxels.extent();
+    let corner_offsets = Point3i::corner_offsets();
+    positions
+        .iter()
+        .map(|[x, y, z]| {
+            let cell = PointN([x.floor() as i32, y.floor() as i32, z.floor() as i32]);
+            let mut counts = [0; NUM_VOXEL_TYPES];
+            for offset in corner_offsets.iter() {
+                let point = cell + *offset;
+                if !extent.contains(&point) {
+                    continue;
+                }
+                let material = *voxels.get(&point).voxel_type();
+                if material.collidable() {
+                    counts[material.index()] += 1;
+                }
+            }
+            counts
+        })
+        .collect()
+}
+
+fn map_axes(point: Point3i, f: impl Fn(i32) -> i32) -> Point3i {
+    PointN([f(point.x()), f(point.y()), f(point.z())])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A floor along the bottom of the extent, with a wall along one side.
+    fn room() -> Array3<Voxel> {
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([8; 3]));
+        Array3::fill_with(extent, |p: &Point3i| {
+            if p.y() < 2 || p.x() >= 6 {
+                Voxel::new(VoxelType::Stone, VoxelDistance(-1))
+            } else {
+                EMPTY_VOXEL
+            }
+        })
+    }
+
+    fn floor_vertices(positions: Vec<[f32; 3]>) -> PosNormMesh {
+        PosNormMesh {
+            normals: vec![[0., 1., 0.]; positions.len()],
+            indices: Vec::new(),
+            positions,
+        }
+    }
+
+    #[test]
+    fn corners_are_more_occluded_than_open_floor() {
+        let voxels = room();
+        // Greedy quads put the top of the floor at the top of its voxels.
+        let mesh = floor_vertices(vec![[2., 2., 4.], [6., 2., 4.], [7., 0., 4.]]);
+        let occlusion = MeshGenerationMethod::GreedyQuads.ambient_occlusion(&voxels, &mesh);
+        assert!(occlusion[0] < 1e-6);
+        assert!(occlusion[1] > 0. && occlusion[1] < 1.);
+        assert!((occlusion[2] - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn smooth_meshes_are_offset_by_half_a_voxel() {
+        let voxels = room();
+        // Smooth meshes put the top of the floor between the solid and empty voxels.
+        let mesh = floor_vertices(vec![[2., 1.5, 4.], [5.5, 1.5, 4.]]);
+        let occlusion = MeshGenerationMethod::SurfaceNets.ambient_occlusion(&voxels, &mesh);
+        assert!(occlusion[0] < 1e-6);
+        assert!(occlusion[1] > 0.);
+    }
+
+    /// A square of floor at a height, made of two triangles.
+    fn square(min: f32, max: f32, y: f32) -> PosNormMesh {
+        PosNormMesh {
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..floor_vertices(vec![
+                [min, y, min],
+                [max, y, min],
+                [max, y, max],
+                [min, y, max],
+            ])
+        }
+    }
+
+    #[test]
+    fn skirts_hang_from_the_sides_of_the_chunk() {
+        let chunk_extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([8; 3]));
+        let mut mesh = square(0., 8., 2.);
+        add_skirts(&mut mesh, &chunk_extent);
+        // Each corner is lowered once, and each of the four sides gets a skirt of two
+        // triangles in both windings. The diagonal is shared by both triangles, so it's left be.
+        assert_eq!(mesh.positions.len(), 8);
+        assert_eq!(mesh.normals.len(), 8);
+        assert_eq!(mesh.indices.len(), 6 + 4 * 12);
+        for [_, y, _] in &mesh.positions[4..] {
+            assert!((*y - (2. - SKIRT_DEPTH)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn no_skirts_away_from_the_sides_of_the_chunk() {
+        let chunk_extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([8; 3]));
+        let mut mesh = square(3., 5., 2.);
+        add_skirts(&mut mesh, &chunk_extent);
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices.len(), 6);
+    }
+
+    #[test]
+    fn lod_meshes_are_scaled_back_up() {
+        let method = MeshGenerationMethod::GreedyQuads;
+        let chunk_extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([16; 3]));
+        let extent = method.lod_extent(&chunk_extent, 1);
+        let voxels = Array3::fill_with(extent, |p: &Point3i| {
+            if p.y() < 4 {
+                Voxel::new(VoxelType::Stone, VoxelDistance(-1))
+            } else {
+                EMPTY_VOXEL
+            }
+        });
+        let meshes = method
+            .generate_lod_mesh(&voxels, 1, &mut MesherBuffers::default())
+            .expect("the floor has a surface");
+        let mesh = &meshes[&VoxelType::Stone];
+        // The floor is meshed from half as many voxels, but still ends up at the same height
+        // and across the whole chunk.
+        assert!(mesh
+            .positions
+            .iter()
+            .all(|[_, y, _]| (*y - 4.).abs() < 1e-6));
+        assert!(mesh
+            .positions
+            .iter()
+            .any(|[x, _, _]| (*x - 16.).abs() < 1e-6));
+        assert!(mesh.normals.iter().all(|normal| *normal == [0., 1., 0.]));
+    }
+
+    #[test]
+    fn water_only_has_faces_against_air() {
+        // Ground at the bottom, then a layer of water, then air.
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([4; 3]));
+        let voxels = Array3::fill_with(extent, |p: &Point3i| match p.y() {
+            0 | 1 => Voxel::new(VoxelType::Stone, VoxelDistance(-1)),
+            2 => Voxel::new(VoxelType::Water, VoxelDistance(-1)),
+            _ => EMPTY_VOXEL,
+        });
+        let mesh = generate_water_mesh(&voxels, &extent, &mut MesherBuffers::default())
+            .expect("the water has a surface");
+        assert!(mesh.normals.iter().all(|normal| *normal == [0., 1., 0.]));
+    }
+
+    #[test]
+    fn dominant_material_is_the_most_common() {
+        let mut counts = [0; NUM_VOXEL_TYPES];
+        counts[VoxelType::Soil.index()] = 3;
+        counts[VoxelType::Sandstone.index()] = 5;
+        assert_eq!(dominant_material(&counts), VoxelType::Sandstone);
+        assert_eq!(dominant_material(&[0; NUM_VOXEL_TYPES]), VoxelType::Stone);
+    }
+
+    #[test]
+    fn triangles_go_to_their_dominant_material() {
+        // Two triangles sharing an edge, with the last vertex of each in a different material.
+        let mesh = PosNormMesh {
+            positions: vec![[0., 0., 0.], [1., 0., 0.], [0., 0., 1.], [1., 0., 1.]],
+            normals: vec![[0., 1., 0.]; 4],
+            indices: vec![0, 1, 2, 2, 1, 3],
+        };
+        let mut counts = vec![[0; NUM_VOXEL_TYPES]; 4];
+        counts[0][VoxelType::Soil.index()] = 4;
+        counts[1][VoxelType::Soil.index()] = 1;
+        counts[1][VoxelType::Sandstone.index()] = 1;
+        counts[2][VoxelType::Soil.index()] = 1;
+        counts[2][VoxelType::Sandstone.index()] = 1;
+        counts[3][VoxelType::Sandstone.index()] = 4;
+
+        let meshes = split_mesh_by_material(&mesh, &counts);
+        assert_eq!(meshes.len(), 2);
+        let soil = &meshes[&VoxelType::Soil];
+        assert_eq!(soil.indices, vec![0, 1, 2]);
+        assert_eq!(
+            soil.positions,
+            vec![[0., 0., 0.], [1., 0., 0.], [0., 0., 1.]]
+        );
+        let sandstone = &meshes[&VoxelType::Sandstone];
+        assert_eq!(sandstone.indices, vec![0, 1, 2]);
+        assert_eq!(
+            sandstone.positions,
+            vec![[0., 0., 1.], [1., 0., 0.], [1., 0., 1.]]
+        );
+    }
+
+    #[test]
+    fn shared_vertices_are_not_duplicated() {
+        let mesh = PosNormMesh {
+            positions: vec![[0., 0., 0.], [1., 0., 0.], [0., 0., 1.], [1., 0., 1.]],
+            normals: vec![[0., 1., 0.]; 4],
+            indices: vec![0, 1, 2, 2, 1, 3],
+        };
+        let counts = vec![[1; NUM_VOXEL_TYPES]; 4];
+        let meshes = split_mesh_by_material(&mesh, &counts);
+        assert_eq!(meshes.len(), 1);
+        let only = meshes.values().next().unwrap();
+        assert_eq!(only.positions.len(), 4);
+        assert_eq!(only.indices, vec![0, 1, 2, 2, 1, 3]);
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bevy::log::debug;
+use building_blocks::{
+    core::{Extent2i, Extent3i, Point2i, Point3i, PointN},
+    storage::{Array2, Array3, Get, GetMut},
+};
+use colonize_common::VoxelType;
+use serde::Deserialize;
+
+use crate::Sample;
+
+/// The number of random sites to consider for each copy of a prefab. The flattest suitable
+/// one is used.
+const SITE_ATTEMPTS: usize = 32;
+/// Offsets the noise between prefabs, so that their sites aren't correlated.
+const OFFSET: f64 = 1000.;
+
+#[derive(Debug, PartialEq)]
+pub enum PrefabError {
+    /// The prefab has no voxels at all.
+    Empty,
+    /// Not every layer has the same number of rows, or not every row the same length.
+    Ragged,
+    /// A symbol in the layers which isn't in the palette.
+    UnknownSymbol(char),
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefabError::Empty => write!(f, "prefab has no layers"),
+            PrefabError::Ragged => write!(
+                f,
+                "every layer must have the same number of rows, and every row the same length"
+            ),
+            PrefabError::UnknownSymbol(symbol) => {
+                write!(f, "symbol {:?} is not in the palette", symbol)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrefabError {}
+
+/// A small hand-built structure, such as a ruin or an abandoned mine, which world generation
+/// places on the surface.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Prefab {
+    pub name: String,
+    /// How many copies to try to place in the region.
+    pub count: usize,
+    /// How far the ground may rise or fall across a site, in voxels, for it to be suitable.
+    pub max_roughness: f64,
+    /// How many voxels around the structure the flattened ground blends back into the
+    /// surrounding terrain over.
+    #[serde(default)]
+    pub blend: i32,
+    /// The layer which is level with the top of the ground. The layers below it are buried.
+    #[serde(default)]
+    pub floor: usize,
+    /// What each symbol in the layers is made of. Spaces leave the terrain as it is.
+    pub palette: BTreeMap<char, VoxelType>,
+    /// Horizontal slices of the structure, from the bottom up. Each slice is a list of rows
+    /// along the Z axis, and each row has one symbol per voxel along the X axis.
+    pub layers: Vec<Vec<String>>,
+}
+
+impl Prefab {
+    /// Checks that the layers form a box, and that every symbol in them is in the palette.
+    pub fn validate(&self) -> Result<(), PrefabError> {
+        let [width, _, depth] = self.shape();
+        if width == 0 || depth == 0 {
+            return Err(PrefabError::Empty);
+        }
+        for layer in &self.layers {
+            if layer.len() != depth as usize {
+                return Err(PrefabError::Ragged);
+            }
+            for row in layer {
+                if row.chars().count() != width as usize {
+                    return Err(PrefabError::Ragged);
+                }
+                if let Some(symbol) = row
+                    .chars()
+                    .find(|symbol| *symbol != ' ' && !self.palette.contains_key(symbol))
+                {
+                    return Err(PrefabError::UnknownSymbol(symbol));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The size of the structure along the X, Y and Z axes.
+    pub fn shape(&self) -> [i32; 3] {
+        let depth = self.layers.first().map_or(0, |layer| layer.len());
+        let width = self
+            .layers
+            .first()
+            .and_then(|layer| layer.first())
+            .map_or(0, |row| row.chars().count());
+        [width as i32, self.layers.len() as i32, depth as i32]
+    }
+
+    /// The material at a point relative to the bottom corner of the structure, or `None` if
+    /// the structure leaves the terrain there as it is.
+    pub fn voxel_at(&self, [x, y, z]: [i32; 3]) -> Option<VoxelType> {
+        let symbol = self
+            .layers
+            .get(y as usize)?
+            .get(z as usize)?
+            .chars()
+            .nth(x as usize)?;
+        self.palette.get(&symbol).copied()
+    }
+}
+
+/// A prefab which has been given a site.
+pub(crate) struct Placement<'a> {
+    prefab: &'a Prefab,
+    /// The bottom corner of the structure.
+    minimum: Point3i,
+}
+
+impl<'a> Placement<'a> {
+    pub(crate) fn extent(&self) -> Extent3i {
+        Extent3i::from_min_and_shape(self.minimum, PointN(self.prefab.shape()))
+    }
+
+    /// Writes the structure into the terrain, clipped to the bounds of the array.
+    pub(crate) fn stamp(&self, array: &mut Array3<VoxelType>) {
+        let [width, height, depth] = self.prefab.shape();
+        for y in 0..height {
+            for z in 0..depth {
+                for x in 0..width {
+                    let voxel_type = match self.prefab.voxel_at([x, y, z]) {
+                        Some(voxel_type) => voxel_type,
+                        None => continue,
+                    };
+                    let point = self.minimum + PointN([x, y, z]);
+                    if array.extent().contains(&point) {
+                        *array.get_mut(&point) = voxel_type;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Finds sites for the prefabs on the height map, and flattens the ground under each one,
+/// blending it back into the surrounding terrain. Sites are always on dry land, never
+/// overlap, and are picked pseudo-randomly from the noise, so that the same noise always
+/// gives the same sites.
+pub(crate) fn place_prefabs<'a, S>(
+    prefabs: &'a [Prefab],
+    height_array: &mut Array2<f64>,
+    sea_level: i32,
+    noise: &S,
+) -> Vec<Placement<'a>>
+where
+    S: Sample<[f64; 3], f64>,
+{
+    let region = *height_array.extent();
+    let mut occupied: Vec<Extent2i> = Vec::new();
+    let mut placements = Vec::new();
+
+    for (index, prefab) in prefabs.iter().enumerate() {
+        let [width, _, depth] = prefab.shape();
+        let margin = prefab.blend.max(0);
+        let footprint_shape = PointN([width, depth]);
+        // The range of positions at which the prefab and its margin fit inside the region.
+        let room = region.shape - footprint_shape - PointN([2 * margin; 2]);
+        if room.x() < 0 || room.y() < 0 {
+            debug!("{} is too big to fit in the region", prefab.name);
+            continue;
+        }
+
+        for copy in 0..prefab.count {
+            let site = (0..SITE_ATTEMPTS)
+                .filter_map(|attempt| {
+                    let key = [
+                        index as f64 * OFFSET,
+                        copy as f64 * 7.3,
+                        attempt as f64 * 13.7,
+                    ];
+                    let offset = PointN([
+                        (random(noise, key) * (room.x() + 1) as f64) as i32,
+                        (random(noise, [key[0] + 0.5 * OFFSET, key[1], key[2]])
+                            * (room.y() + 1) as f64) as i32,
+                    ]);
+                    let footprint = Extent2i::from_min_and_shape(
+                        region.minimum + PointN([margin; 2]) + offset,
+                        footprint_shape,
+                    );
+                    let surroundings = pad(&footprint, margin);
+                    if occupied.iter().any(|other| overlaps(other, &surroundings)) {
+                        return None;
+                    }
+                    let (lowest, highest, ground) = survey(height_array, &footprint);
+                    let roughness = highest - lowest;
+                    if lowest < sea_level as f64 || roughness > prefab.max_roughness {
+                        return None;
+                    }
+                    Some((roughness, footprint, ground))
+                })
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            let (_, footprint, ground) = match site {
+                Some(site) => site,
+                None => {
+                    debug!("Found no site for {} #{}", prefab.name, copy + 1);
+                    continue;
+                }
+            };
+
+            flatten(height_array, &footprint, margin, ground);
+            let surroundings = pad(&footprint, margin);
+            occupied.push(surroundings);
+
+            let minimum = PointN([
+                footprint.minimum.x(),
+                ground as i32 - prefab.floor as i32,
+                footprint.minimum.y(),
+            ]);
+            debug!("Placed {} at {:?}", prefab.name, minimum);
+            placements.push(Placement { prefab, minimum });
+        }
+    }
+
+    placements
+}
+
+/// A pseudo-random number from 0 to 1, taken from the low digits of the noise.
+fn random<S>(noise: &S, point: [f64; 3]) -> f64
+where
+    S: Sample<[f64; 3], f64>,
+{
+    let value = noise.get(point) * 10_000.;
+    value - value.floor()
+}
+
+fn pad(extent: &Extent2i, margin: i32) -> Extent2i {
+    Extent2i::from_min_and_shape(
+        extent.minimum - PointN([margin; 2]),
+        extent.shape + PointN([2 * margin; 2]),
+    )
+}
+
+fn overlaps(a: &Extent2i, b: &Extent2i) -> bool {
+    let (a_max, b_max) = (a.max(), b.max());
+    a.minimum.x() <= b_max.x()
+        && b.minimum.x() <= a_max.x()
+        && a.minimum.y() <= b_max.y()
+        && b.minimum.y() <= a_max.y()
+}
+
+fn points(extent: &Extent2i) -> impl Iterator<Item = Point2i> {
+    let (minimum, maximum) = (extent.minimum, extent.max());
+    (minimum.y()..=maximum.y())
+        .flat_map(move |z| (minimum.x()..=maximum.x()).map(move |x| PointN([x, z])))
+}
+
+/// The lowest, highest and (rounded) average height of the ground in an area.
+fn survey(height_array: &Array2<f64>, extent: &Extent2i) -> (f64, f64, f64) {
+    let mut lowest = f64::INFINITY;
+    let mut highest = f64::NEG_INFINITY;
+    let mut total = 0.;
+    for point in points(extent) {
+        let height = height_array.get(&point);
+        lowest = lowest.min(height);
+        highest = highest.max(height);
+        total += height;
+    }
+    let average = total / extent.shape.x() as f64 / extent.shape.y() as f64;
+    (lowest, highest, average.round())
+}
+
+/// Levels the footprint to the given height, and blends the margin around it back into the
+/// original terrain.
+fn flatten(height_array: &mut Array2<f64>, footprint: &Extent2i, margin: i32, ground: f64) {
+    let maximum = footprint.max();
+    for point in points(&pad(footprint, margin)) {
+        // How many voxels the point is outside of the footprint.
+        let distance = [
+            footprint.minimum.x() - point.x(),
+            point.x() - maximum.x(),
+            footprint.minimum.y() - point.y(),
+            point.y() - maximum.y(),
+        ]
+        .iter()
+        .copied()
+        .max()
+        .unwrap()
+        .max(0);
+        let t = distance as f64 / (margin + 1) as f64;
+        let height = height_array.get_mut(&point);
+        *height = (ground + (*height - ground) * t).round();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Constant(f64);
+
+    impl Sample<[f64; 3], f64> for Constant {
+        fn get(&self, _point: [f64; 3]) -> f64 {
+            self.0
+        }
+    }
+
+    fn hut() -> Prefab {
+        Prefab {
+            name: "hut".to_string(),
+            count: 1,
+            max_roughness: 4.,
+            blend: 2,
+            floor: 1,
+            palette: vec![('#', VoxelType::Stone), ('.', VoxelType::Air)]
+                .into_iter()
+                .collect(),
+            layers: vec![
+                vec!["   ".to_string(), " # ".to_string(), "   ".to_string()],
+                vec!["###".to_string(), "#.#".to_string(), "###".to_string()],
+            ],
+        }
+    }
+
+    fn sloped_region() -> Array2<f64> {
+        let extent = Extent2i::from_min_and_shape(PointN([0, 0]), PointN([16, 16]));
+        Array2::fill_with(extent, |p: &Point2i| (p.x() / 4) as f64)
+    }
+
+    #[test]
+    fn validates_layers() {
+        assert_eq!(hut().validate(), Ok(()));
+
+        let mut ragged = hut();
+        ragged.layers[1][2] = "##".to_string();
+        assert_eq!(ragged.validate(), Err(PrefabError::Ragged));
+
+        let mut unknown = hut();
+        unknown.layers[0][1] = " g ".to_string();
+        assert_eq!(unknown.validate(), Err(PrefabError::UnknownSymbol('g')));
+
+        let mut empty = hut();
+        empty.layers.clear();
+        assert_eq!(empty.validate(), Err(PrefabError::Empty));
+    }
+
+    #[test]
+    fn spaces_leave_terrain_alone() {
+        let hut = hut();
+        assert_eq!(hut.shape(), [3, 2, 3]);
+        assert_eq!(hut.voxel_at([0, 0, 0]), None);
+        assert_eq!(hut.voxel_at([1, 0, 1]), Some(VoxelType::Stone));
+        assert_eq!(hut.voxel_at([1, 1, 1]), Some(VoxelType::Air));
+        assert_eq!(hut.voxel_at([3, 0, 0]), None);
+    }
+
+    #[test]
+    fn flattens_the_site() {
+        let prefabs = vec![hut()];
+        let mut heights = sloped_region();
+        let placements = place_prefabs(&prefabs, &mut heights, 0, &Constant(0.));
+        assert_eq!(placements.len(), 1);
+
+        let extent = placements[0].extent();
+        let ground = heights.get(&extent.minimum.xz());
+        for point in points(&Extent2i::from_min_and_shape(
+            extent.minimum.xz(),
+            extent.shape.xz(),
+        )) {
+            assert!((heights.get(&point) - ground).abs() < f64::EPSILON);
+        }
+        // The floor layer is level with the top of the ground.
+        assert_eq!(extent.minimum.y(), ground as i32 - 1);
+    }
+
+    #[test]
+    fn sites_stay_above_sea_level_and_apart() {
+        let mut prefab = hut();
+        prefab.count = 10;
+        let prefabs = vec![prefab];
+
+        let mut heights = sloped_region();
+        let placements = place_prefabs(&prefabs, &mut heights, 100, &Constant(0.));
+        assert!(placements.is_empty());
+
+        // With constant noise every attempt picks the same site, so only one copy fits.
+        let mut heights = sloped_region();
+        let placements = place_prefabs(&prefabs, &mut heights, 0, &Constant(0.));
+        assert_eq!(placements.len(), 1);
+    }
+
+    #[test]
+    fn stamps_within_bounds() {
+        let prefab = hut();
+        let placement = Placement {
+            prefab: &prefab,
+            minimum: PointN([-1, 0, 0]),
+        };
+        let extent = Extent3i::from_min_and_shape(PointN([0, 0, 0]), PointN([4, 4, 4]));
+        let mut array = Array3::fill(extent, VoxelType::Soil);
+        placement.stamp(&mut array);
+        assert_eq!(array.get(&PointN([0, 0, 1])), VoxelType::Stone);
+        assert_eq!(array.get(&PointN([0, 1, 1])), VoxelType::Air);
+        assert_eq!(array.get(&PointN([1, 1, 1])), VoxelType::Stone);
+        assert_eq!(array.get(&PointN([0, 0, 0])), VoxelType::Soil);
+    }
+}
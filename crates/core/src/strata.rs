@@ -0,0 +1,146 @@
+use colonize_common::VoxelType;
+
+use crate::{Sample, StratumConfig};
+
+/// How much more slowly the boundaries between strata vary than the noise itself, so that
+/// layers fold gently rather than changing thickness every few voxels.
+const BOUNDARY_SCALE: f64 = 0.25;
+/// How quickly the kind of stone changes within a stratum, relative to the noise.
+const STONE_SCALE: f64 = 0.5;
+/// How quickly ore veins change, relative to the noise. Veins are much smaller than strata.
+const ORE_SCALE: f64 = 2.;
+/// Offsets the noise between its different uses, so that they aren't correlated.
+const OFFSET: f64 = 1000.;
+
+/// Decides which material the bedrock is made of at each point, from a list of strata and a
+/// 3D noise function.
+pub(crate) struct Strata<'a, S> {
+    layers: &'a [StratumConfig],
+    noise: &'a S,
+}
+
+impl<'a, S> Strata<'a, S>
+where
+    S: Sample<[f64; 3], f64>,
+{
+    pub(crate) fn new(layers: &'a [StratumConfig], noise: &'a S) -> Self {
+        Self { layers, noise }
+    }
+
+    fn sample(&self, point: [i32; 3], scale: f64, offset: f64) -> f64 {
+        self.noise.get([
+            point[0] as f64 * scale + offset,
+            point[1] as f64 * scale,
+            point[2] as f64 * scale,
+        ])
+    }
+
+    /// The material at a point `depth` voxels below the top of the bedrock.
+    pub(crate) fn material_at(&self, point: [i32; 3], depth: f64) -> VoxelType {
+        if self.layers.is_empty() {
+            return VoxelType::Stone;
+        }
+
+        // Find the stratum the point is in. The last one extends all the way down.
+        let mut bottom = 0.;
+        let (index, layer) = self
+            .layers
+            .iter()
+            .enumerate()
+            .find(|(i, layer)| {
+                let boundary = self.sample(point, BOUNDARY_SCALE, *i as f64 * OFFSET);
+                bottom += layer.thickness + boundary * layer.variation;
+                depth < bottom
+            })
+            .unwrap_or_else(|| (self.layers.len() - 1, self.layers.last().unwrap()));
+
+        let ore = layer.ores.iter().enumerate().find(|(i, ore)| {
+            let offset = (index * 10 + i + 1) as f64 * OFFSET;
+            self.sample(point, ORE_SCALE, offset) > ore.threshold()
+        });
+        if let Some((_, ore)) = ore {
+            return ore.ore;
+        }
+
+        if layer.stones.is_empty() {
+            return VoxelType::Stone;
+        }
+        let choice = (self.sample(point, STONE_SCALE, -(index as f64 + 1.) * OFFSET) + 1.) / 2.;
+        let stone = (choice * layer.stones.len() as f64) as usize;
+        layer.stones[stone.min(layer.stones.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::OreConfig;
+
+    struct Constant(f64);
+
+    impl Sample<[f64; 3], f64> for Constant {
+        fn get(&self, _point: [f64; 3]) -> f64 {
+            self.0
+        }
+    }
+
+    fn layer(thickness: f64, stones: Vec<VoxelType>, ores: Vec<OreConfig>) -> StratumConfig {
+        StratumConfig {
+            name: String::new(),
+            thickness,
+            variation: 4.,
+            stones,
+            ores,
+        }
+    }
+
+    #[test]
+    fn layers_are_stacked_by_thickness() {
+        let layers = vec![
+            layer(10., vec![VoxelType::Sandstone], Vec::new()),
+            layer(10., vec![VoxelType::Granite], Vec::new()),
+        ];
+        let strata = Strata::new(&layers, &Constant(0.));
+        assert_eq!(strata.material_at([0; 3], 0.), VoxelType::Sandstone);
+        assert_eq!(strata.material_at([0; 3], 9.), VoxelType::Sandstone);
+        assert_eq!(strata.material_at([0; 3], 10.), VoxelType::Granite);
+        // The last stratum extends all the way down.
+        assert_eq!(strata.material_at([0; 3], 100.), VoxelType::Granite);
+    }
+
+    #[test]
+    fn noise_moves_boundaries() {
+        let layers = vec![
+            layer(10., vec![VoxelType::Sandstone], Vec::new()),
+            layer(10., vec![VoxelType::Granite], Vec::new()),
+        ];
+        let strata = Strata::new(&layers, &Constant(0.5));
+        assert_eq!(strata.material_at([0; 3], 11.), VoxelType::Sandstone);
+        let strata = Strata::new(&layers, &Constant(-0.5));
+        assert_eq!(strata.material_at([0; 3], 9.), VoxelType::Granite);
+    }
+
+    #[test]
+    fn stones_and_ores_are_chosen_by_noise() {
+        let layers = vec![layer(
+            10.,
+            vec![VoxelType::Granite, VoxelType::Basalt],
+            vec![OreConfig {
+                ore: VoxelType::Gold,
+                frequency: 0.05,
+            }],
+        )];
+        let strata = Strata::new(&layers, &Constant(-0.5));
+        assert_eq!(strata.material_at([0; 3], 0.), VoxelType::Granite);
+        let strata = Strata::new(&layers, &Constant(0.5));
+        assert_eq!(strata.material_at([0; 3], 0.), VoxelType::Basalt);
+        let strata = Strata::new(&layers, &Constant(0.95));
+        assert_eq!(strata.material_at([0; 3], 0.), VoxelType::Gold);
+    }
+
+    #[test]
+    fn no_strata_is_plain_stone() {
+        let strata = Strata::new(&[], &Constant(0.));
+        assert_eq!(strata.material_at([0; 3], 0.), VoxelType::Stone);
+    }
+}
@@ -7,27 +7,30 @@ use building_blocks::{
 };
 use colonize_common::{Voxel, VoxelDistance, VoxelType, EMPTY_VOXEL};
 
-use crate::array_int_to_float;
+use crate::prefab::place_prefabs;
+use crate::strata::Strata;
+use crate::{array_int_to_float, MaterialMask, WorldGenConfig};
 
 pub fn generate_map<H, D>(
     elevation_noise: &H,
     dirt_thickness_noise: &D,
-    sea_level: i32,
-    minimum: Point3i,
-    shape: Point3i,
+    config: &WorldGenConfig,
 ) -> Array3<Voxel>
 where
     D: Sample<[f64; 2], f64>,
     H: Sample<[f64; 2], f64>,
 {
+    let region = config.extent();
+    let minimum = region.minimum;
+    let shape = region.shape;
+    let sea_level = config.sea_level;
+
     // Generate the 2D height map.
     trace!("Generating 2D height map");
     let extent = Extent2i::from_min_and_shape(minimum.xz(), shape.xz());
     let filler = |point: &PointN<[i32; 2]>| {
-        const MIN_WATER_LEVEL: f64 = -128.;
-        const MAX_MOUNTAIN_HEIGHT: f64 = 128.;
         let sample = elevation_noise.get(array_int_to_float(point.0));
-        scale(sample, -1., 1., MIN_WATER_LEVEL, MAX_MOUNTAIN_HEIGHT).round() as i32
+        scale(sample, -1., 1., config.min_elevation, config.max_elevation).round() as i32
     };
     let height_array = Array2::fill_with(extent, filler);
 
@@ -111,32 +114,65 @@ where
     sdf_array
 }
 
-pub fn generate_precise_map<H, D>(
+/// Generates the terrain of a region: bedrock made up of the strata in the config, covered
+/// by a layer of soil with grass on top, and flooded with water up to sea level.
+pub fn generate_precise_map<H, D, S>(
     elevation_noise: &H,
     dirt_thickness_noise: &D,
-    sea_level: i32,
-    minimum: Point3i,
-    shape: Point3i,
+    strata_noise: &S,
+    config: &WorldGenConfig,
 ) -> Array3<Voxel>
 where
     D: Sample<[f64; 2], f64>,
     H: Sample<[f64; 2], f64>,
+    S: Sample<[f64; 3], f64>,
 {
+    generate_precise_map_with_masks(
+        elevation_noise,
+        dirt_thickness_noise,
+        strata_noise,
+        &[],
+        config,
+    )
+}
+
+/// Like [`generate_precise_map`], but the soil of each column is made of the material of the
+/// last mask which covers it, rather than grass and soil.
+pub fn generate_precise_map_with_masks<H, D, S>(
+    elevation_noise: &H,
+    dirt_thickness_noise: &D,
+    strata_noise: &S,
+    masks: &[MaterialMask],
+    config: &WorldGenConfig,
+) -> Array3<Voxel>
+where
+    D: Sample<[f64; 2], f64>,
+    H: Sample<[f64; 2], f64>,
+    S: Sample<[f64; 3], f64>,
+{
+    let region = config.extent();
+    let minimum = region.minimum;
+    let shape = region.shape;
+    let sea_level = config.sea_level;
+
     // Generate the 2D height map.
     trace!("Generating 2D height map");
     let extent = Extent2i::from_min_and_shape(minimum.xz(), shape.xz());
     let filler = |point: &PointN<[i32; 2]>| {
-        const MIN_WATER_LEVEL: f64 = -128.;
-        const MAX_MOUNTAIN_HEIGHT: f64 = 128.;
         let sample = elevation_noise.get(array_int_to_float(point.0));
-        scale(sample, -1., 1., MIN_WATER_LEVEL, MAX_MOUNTAIN_HEIGHT).round()
+        scale(sample, -1., 1., config.min_elevation, config.max_elevation).round()
     };
-    let height_array = Array2::fill_with(extent, filler);
+    let mut height_array = Array2::fill_with(extent, filler);
+
+    // Find sites for the prefabs, and level the ground under them.
+    trace!("Placing prefabs");
+    let placements = place_prefabs(&config.prefabs, &mut height_array, sea_level, strata_noise);
 
     // Generate the 3D strata map from the height map.
     let total_extent = Extent3i::from_min_and_shape(minimum, shape);
     trace!("Generating 3D strata map for extent {:?}", total_extent);
     let mut strata_array = Array3::fill(total_extent, VoxelType::Air);
+    let strata = Strata::new(&config.strata, strata_noise);
     // Construct an iterator over 1x1-sized columns of the entire map.
     // We need to do this because we calculate once per columns: dirt thickness,
     // dirt transition, and stone transition.
@@ -158,16 +194,29 @@ where
         );
         let dirt_transition = height_array.get(&PointN(point));
         let stone_transition = dirt_transition - dirt_thickness;
+        let mask = masks
+            .iter()
+            .rev()
+            .find(|mask| mask.covers(array_int_to_float(point)))
+            .map(|mask| mask.voxel_type);
         strata_array.for_each_mut(&c, |point: Point3i, value| {
-            if point.y() as f64 <= stone_transition {
-                *value = VoxelType::Stone
-            } else if point.y() as f64 <= dirt_transition {
-                *value = VoxelType::Grass
+            let y = point.y() as f64;
+            if y <= stone_transition {
+                *value = strata.material_at(point.0, stone_transition - y)
+            } else if y <= dirt_transition {
+                *value = match mask {
+                    Some(voxel_type) => voxel_type,
+                    None if y > dirt_transition - 1. => VoxelType::Grass,
+                    None => VoxelType::Soil,
+                }
             } else {
                 *value = VoxelType::Air
             }
         })
     });
+    for placement in &placements {
+        placement.stamp(&mut strata_array);
+    }
 
     // Flood-fill the water on the map.
     trace!("Flood-filling water on map");
@@ -207,6 +256,19 @@ where
             *value = Voxel::new(strata_array.get(&point), VoxelDistance(distance));
         })
     });
+    // Prefabs aren't part of the height map, so make sure the solid voxels in them are inside
+    // the surface and the hollows in them are outside of it.
+    for placement in &placements {
+        let placement_extent = placement.extent().intersection(&total_extent);
+        sdf_array.for_each_mut(&placement_extent, |_p: Point3i, value: &mut Voxel| {
+            let distance = value.distance().0;
+            let distance = match value.voxel_type() {
+                VoxelType::Air | VoxelType::Water => distance.max(1),
+                _ => distance.min(-1),
+            };
+            *value = Voxel::new(*value.voxel_type(), VoxelDistance(distance));
+        });
+    }
 
     sdf_array
 }
@@ -342,7 +404,10 @@ fn scale(number: f64, a_min: f64, a_max: f64, b_min: f64, b_max: f64) -> f64 {
 
 #[cfg(test)]
 mod test {
+    use building_blocks::storage::ForEach;
+
     use super::*;
+    use crate::HeightMap;
 
     struct MockNoise;
 
@@ -352,9 +417,50 @@ mod test {
         }
     }
 
+    impl Sample<[f64; 3], f64> for MockNoise {
+        fn get(&self, _point: [f64; 3]) -> f64 {
+            0.0
+        }
+    }
+
     impl Sample<[i32; 2], i32> for MockNoise {
         fn get(&self, _point: [i32; 2]) -> i32 {
             1
         }
     }
+
+    #[test]
+    fn masks_replace_surface_material() {
+        let mut config = crate::config::test::config();
+        config.region_size = 4;
+        config.region_height = 16;
+        config.min_elevation = -4.;
+        config.max_elevation = 4.;
+        config.sea_level = -4;
+        let surface_types = |masks: &[MaterialMask]| {
+            let voxels =
+                generate_precise_map_with_masks(&MockNoise, &MockNoise, &MockNoise, masks, &config);
+            let mut types = Vec::new();
+            voxels.for_each(&config.extent(), |_p: Point3i, voxel: Voxel| {
+                if !types.contains(voxel.voxel_type()) {
+                    types.push(*voxel.voxel_type());
+                }
+            });
+            types
+        };
+
+        assert!(surface_types(&[]).contains(&VoxelType::Grass));
+
+        // Only the left half of the region is covered by the mask.
+        let gold = MaterialMask {
+            voxel_type: VoxelType::Gold,
+            mask: HeightMap::new(2, 1, vec![1., 0.]).stretch_over(Extent2i::from_min_and_shape(
+                PointN([-2, -2]),
+                PointN([4, 4]),
+            )),
+        };
+        let types = surface_types(&[gold]);
+        assert!(types.contains(&VoxelType::Gold));
+        assert!(types.contains(&VoxelType::Grass));
+    }
 }
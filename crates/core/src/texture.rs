@@ -0,0 +1,175 @@
+use colonize_common::VoxelType;
+
+/// The width and height of each material's texture, in pixels.
+pub const TEXTURE_SIZE: u32 = 32;
+
+/// The kind of surface detail each material has, so that materials can be told apart up close
+/// and not only by their color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pattern {
+    /// Fine grains at a couple of scales, like most rock.
+    Speckled,
+    /// Horizontal bands, like sedimentary rock and slate.
+    Layered,
+    /// Thin vertical blades.
+    Blades,
+    /// Coarse clumps.
+    Clumps,
+    /// Flecks of bright metal in duller rock.
+    Nuggets,
+    /// Gentle waves.
+    Ripples,
+}
+
+impl Pattern {
+    fn of(voxel_type: VoxelType) -> Self {
+        match voxel_type {
+            VoxelType::Air
+            | VoxelType::Stone
+            | VoxelType::Granite
+            | VoxelType::Basalt
+            | VoxelType::Marble => Pattern::Speckled,
+            VoxelType::Sandstone | VoxelType::Limestone | VoxelType::Slate => Pattern::Layered,
+            VoxelType::Grass => Pattern::Blades,
+            VoxelType::Soil => Pattern::Clumps,
+            VoxelType::Gold | VoxelType::Iron | VoxelType::Copper | VoxelType::Coal => {
+                Pattern::Nuggets
+            }
+            VoxelType::Water => Pattern::Ripples,
+        }
+    }
+
+    /// The brightness of the pattern at a point, from 0 to 1. Coordinates go from 0 to 1 across
+    /// the texture, and the pattern wraps around at the edges.
+    fn brightness(self, x: f32, y: f32, seed: u32) -> f32 {
+        let noise = |cells_x, cells_y, seed| tiling_noise(x, y, cells_x, cells_y, seed);
+        match self {
+            Pattern::Speckled => 0.5 * noise(4, 4, seed) + 0.5 * noise(16, 16, seed + 1),
+            Pattern::Layered => {
+                // Bands which wobble a little from side to side.
+                let wobble = 0.05 * noise(4, 4, seed);
+                let band = (((y + wobble) * 4.) * std::f32::consts::PI * 2.).sin() * 0.5 + 0.5;
+                0.6 * band + 0.4 * noise(16, 16, seed + 1)
+            }
+            Pattern::Blades => noise(32, 2, seed),
+            Pattern::Clumps => noise(8, 8, seed).powi(2),
+            Pattern::Nuggets => {
+                let grains = noise(16, 16, seed);
+                if noise(8, 8, seed + 1) > 0.7 {
+                    1.
+                } else {
+                    0.6 * grains
+                }
+            }
+            Pattern::Ripples => {
+                let wobble = 0.1 * noise(2, 2, seed);
+                ((x + y + wobble) * 3. * std::f32::consts::PI * 2.).sin() * 0.5 + 0.5
+            }
+        }
+    }
+}
+
+/// A tiling texture of surface detail for a material, as rows of 8-bit RGBA pixels.
+///
+/// The texture is grey, and brightens or darkens the material's color where it's drawn, so
+/// that the colors themselves still come from [`VoxelType::color`].
+pub fn material_texture(voxel_type: VoxelType) -> Vec<u8> {
+    let pattern = Pattern::of(voxel_type);
+    let seed = voxel_type.index() as u32 * 16;
+    let size = TEXTURE_SIZE as usize;
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let brightness = pattern.brightness(
+                (x as f32 + 0.5) / size as f32,
+                (y as f32 + 0.5) / size as f32,
+                seed,
+            );
+            // Keep the detail subtle, so that the materials' colors are still recognisable.
+            let value = ((0.75 + 0.25 * brightness.max(0.).min(1.)) * 255.).round() as u8;
+            pixels.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+    pixels
+}
+
+/// Value noise from 0 to 1, on a grid of the given number of cells across the texture. The
+/// grid wraps around so that the noise tiles.
+fn tiling_noise(x: f32, y: f32, cells_x: u32, cells_y: u32, seed: u32) -> f32 {
+    let (x, y) = (x * cells_x as f32, y * cells_y as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let corner = |dx: u32, dy: u32| {
+        let cx = (x0 as u32 + dx) % cells_x;
+        let cy = (y0 as u32 + dy) % cells_y;
+        hash(cx, cy, seed)
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(
+        lerp(corner(0, 0), corner(1, 0), tx),
+        lerp(corner(0, 1), corner(1, 1), tx),
+        ty,
+    )
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+/// A pseudo-random number from 0 to 1 for a grid cell.
+fn hash(x: u32, y: u32, seed: u32) -> f32 {
+    let mut h = x
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add(y.wrapping_mul(0xd816_3841))
+        .wrapping_add(seed.wrapping_mul(0xcb1a_b31f));
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 0xffff as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn textures_are_opaque_grey() {
+        for voxel_type in VoxelType::ALL.iter() {
+            let pixels = material_texture(*voxel_type);
+            assert_eq!(pixels.len(), (TEXTURE_SIZE * TEXTURE_SIZE * 4) as usize);
+            for pixel in pixels.chunks(4) {
+                assert_eq!(pixel[0], pixel[1]);
+                assert_eq!(pixel[0], pixel[2]);
+                assert_eq!(pixel[3], 255);
+            }
+        }
+    }
+
+    #[test]
+    fn materials_look_different() {
+        let textures = [
+            VoxelType::Stone,
+            VoxelType::Grass,
+            VoxelType::Gold,
+            VoxelType::Water,
+        ]
+        .iter()
+        .map(|voxel_type| material_texture(*voxel_type))
+        .collect::<Vec<_>>();
+        for (i, a) in textures.iter().enumerate() {
+            for b in textures.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_tiles() {
+        for y in 0..4 {
+            let y = y as f32 / 4.;
+            let left = tiling_noise(0., y, 4, 4, 1);
+            let right = tiling_noise(1., y, 4, 4, 1);
+            assert!((left - right).abs() < 1e-6, "{} != {}", left, right);
+        }
+    }
+}
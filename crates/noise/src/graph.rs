@@ -0,0 +1,449 @@
+//! Nodes which combine and transform other samplers, so that a layer of world generation can
+//! be described as a small graph rather than as a hand-written function:
+//!
+//! ```ignore
+//! let elevation = ridged_multi(&config.elevation, seed)
+//!     .domain_warp(warp_x, warp_z, 16.)
+//!     .terrace(0.25, 4.)
+//!     .clamp(-1., 1.);
+//! let elevation = Cache2d::new(elevation);
+//! ```
+//!
+//! Each node is itself a [`Sample`], so nodes can be nested arbitrarily and passed anywhere a
+//! noise function is expected.
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use colonize_core::{NoiseSample, Sample};
+
+/// The same value everywhere.
+#[derive(Clone, Copy, Debug)]
+pub struct Constant(pub f64);
+
+impl<T> Sample<T, f64> for Constant {
+    fn get(&self, _point: T) -> f64 {
+        self.0
+    }
+}
+
+/// The sum of two samplers.
+pub struct Add<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<T, A, B> Sample<T, f64> for Add<A, B>
+where
+    T: Copy,
+    A: Sample<T, f64>,
+    B: Sample<T, f64>,
+{
+    fn get(&self, point: T) -> f64 {
+        self.a.get(point) + self.b.get(point)
+    }
+}
+
+/// The product of two samplers.
+pub struct Multiply<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<T, A, B> Sample<T, f64> for Multiply<A, B>
+where
+    T: Copy,
+    A: Sample<T, f64>,
+    B: Sample<T, f64>,
+{
+    fn get(&self, point: T) -> f64 {
+        self.a.get(point) * self.b.get(point)
+    }
+}
+
+/// Limits a sampler to the range `[min, max]`.
+pub struct Clamp<S> {
+    source: S,
+    min: f64,
+    max: f64,
+}
+
+impl<T, S> Sample<T, f64> for Clamp<S>
+where
+    S: Sample<T, f64>,
+{
+    fn get(&self, point: T) -> f64 {
+        self.source.get(point).max(self.min).min(self.max)
+    }
+}
+
+/// Linearly maps a sampler from one range to another. Values outside of the source range are
+/// extrapolated rather than clamped.
+pub struct Remap<S> {
+    source: S,
+    from: (f64, f64),
+    to: (f64, f64),
+}
+
+impl<T, S> Sample<T, f64> for Remap<S>
+where
+    S: Sample<T, f64>,
+{
+    fn get(&self, point: T) -> f64 {
+        let (from_min, from_max) = self.from;
+        let (to_min, to_max) = self.to;
+        let t = (self.source.get(point) - from_min) / (from_max - from_min);
+        to_min + t * (to_max - to_min)
+    }
+}
+
+/// Chooses between two samplers depending on the value of a third. Below
+/// `threshold - falloff` this is `low`, above `threshold + falloff` it's `high`, and in
+/// between the two are blended linearly.
+pub struct Select<C, A, B> {
+    control: C,
+    low: A,
+    high: B,
+    threshold: f64,
+    falloff: f64,
+}
+
+impl<T, C, A, B> Sample<T, f64> for Select<C, A, B>
+where
+    T: Copy,
+    C: Sample<T, f64>,
+    A: Sample<T, f64>,
+    B: Sample<T, f64>,
+{
+    fn get(&self, point: T) -> f64 {
+        let control = self.control.get(point);
+        let lower = self.threshold - self.falloff;
+        let upper = self.threshold + self.falloff;
+        if control <= lower {
+            self.low.get(point)
+        } else if control >= upper {
+            self.high.get(point)
+        } else {
+            let t = (control - lower) / (upper - lower);
+            let low = self.low.get(point);
+            low + t * (self.high.get(point) - low)
+        }
+    }
+}
+
+/// Offsets the point a sampler is sampled at by the values of two other samplers, scaled by
+/// `strength`. This breaks up the regular look of fractal noise.
+pub struct DomainWarp<S, X, Y> {
+    source: S,
+    x: X,
+    y: Y,
+    strength: f64,
+}
+
+impl<S, X, Y> Sample<[f64; 2], f64> for DomainWarp<S, X, Y>
+where
+    S: Sample<[f64; 2], f64>,
+    X: Sample<[f64; 2], f64>,
+    Y: Sample<[f64; 2], f64>,
+{
+    fn get(&self, point: [f64; 2]) -> f64 {
+        let [x, y] = point;
+        self.source.get([
+            x + self.x.get(point) * self.strength,
+            y + self.y.get(point) * self.strength,
+        ])
+    }
+}
+
+/// Flattens a sampler into terraces `step` apart. Between two terraces, the value rises
+/// along a curve whose steepness is set by `sharpness`: 1 leaves the source unchanged, and
+/// higher values make flatter terraces with steeper cliffs between them.
+pub struct Terrace<S> {
+    source: S,
+    step: f64,
+    sharpness: f64,
+}
+
+impl<T, S> Sample<T, f64> for Terrace<S>
+where
+    S: Sample<T, f64>,
+{
+    fn get(&self, point: T) -> f64 {
+        let value = self.source.get(point) / self.step;
+        let base = value.floor();
+        (base + (value - base).powf(self.sharpness)) * self.step
+    }
+}
+
+/// Remembers every value sampled from a 2D sampler, for samplers which are expensive and are
+/// sampled at the same points more than once.
+///
+/// The cache is never cleared, so this should be dropped once a region has been generated.
+pub struct Cache2d<S> {
+    source: S,
+    cache: RefCell<HashMap<[u64; 2], f64>>,
+}
+
+impl<S> Cache2d<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S> Sample<[f64; 2], f64> for Cache2d<S>
+where
+    S: Sample<[f64; 2], f64>,
+{
+    fn get(&self, point: [f64; 2]) -> f64 {
+        let key = [point[0].to_bits(), point[1].to_bits()];
+        let source = &self.source;
+        *self
+            .cache
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| source.get(point))
+    }
+}
+
+/// The 3D equivalent of [`Cache2d`].
+pub struct Cache3d<S> {
+    source: S,
+    cache: RefCell<HashMap<[u64; 3], f64>>,
+}
+
+impl<S> Cache3d<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S> Sample<[f64; 3], f64> for Cache3d<S>
+where
+    S: Sample<[f64; 3], f64>,
+{
+    fn get(&self, point: [f64; 3]) -> f64 {
+        let key = [point[0].to_bits(), point[1].to_bits(), point[2].to_bits()];
+        let source = &self.source;
+        *self
+            .cache
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| source.get(point))
+    }
+}
+
+impl<T> NoiseSample<T, f64> for Constant {}
+impl<T: Copy, A: NoiseSample<T, f64>, B: NoiseSample<T, f64>> NoiseSample<T, f64> for Add<A, B> {}
+impl<T: Copy, A: NoiseSample<T, f64>, B: NoiseSample<T, f64>> NoiseSample<T, f64>
+    for Multiply<A, B>
+{
+}
+impl<T, S: NoiseSample<T, f64>> NoiseSample<T, f64> for Clamp<S> {}
+impl<T, S: NoiseSample<T, f64>> NoiseSample<T, f64> for Remap<S> {}
+impl<T: Copy, C, A, B> NoiseSample<T, f64> for Select<C, A, B>
+where
+    C: NoiseSample<T, f64>,
+    A: NoiseSample<T, f64>,
+    B: NoiseSample<T, f64>,
+{
+}
+impl<S, X, Y> NoiseSample<[f64; 2], f64> for DomainWarp<S, X, Y>
+where
+    S: NoiseSample<[f64; 2], f64>,
+    X: NoiseSample<[f64; 2], f64>,
+    Y: NoiseSample<[f64; 2], f64>,
+{
+}
+impl<T, S: NoiseSample<T, f64>> NoiseSample<T, f64> for Terrace<S> {}
+impl<S: NoiseSample<[f64; 2], f64>> NoiseSample<[f64; 2], f64> for Cache2d<S> {}
+impl<S: NoiseSample<[f64; 3], f64>> NoiseSample<[f64; 3], f64> for Cache3d<S> {}
+
+/// Methods for building up a graph of nodes from any sampler.
+pub trait SampleExt<T>: Sample<T, f64> + Sized {
+    fn add<B: Sample<T, f64>>(self, other: B) -> Add<Self, B> {
+        Add { a: self, b: other }
+    }
+
+    fn multiply<B: Sample<T, f64>>(self, other: B) -> Multiply<Self, B> {
+        Multiply { a: self, b: other }
+    }
+
+    fn clamp(self, min: f64, max: f64) -> Clamp<Self> {
+        assert!(min <= max, "{} must be less than or equal to {}", min, max);
+        Clamp {
+            source: self,
+            min,
+            max,
+        }
+    }
+
+    fn remap(self, from: (f64, f64), to: (f64, f64)) -> Remap<Self> {
+        Remap {
+            source: self,
+            from,
+            to,
+        }
+    }
+
+    /// Uses `self` as the control for a [`Select`] between `low` and `high`.
+    fn select<A, B>(self, low: A, high: B, threshold: f64, falloff: f64) -> Select<Self, A, B>
+    where
+        A: Sample<T, f64>,
+        B: Sample<T, f64>,
+    {
+        assert!(falloff >= 0., "falloff must not be negative");
+        Select {
+            control: self,
+            low,
+            high,
+            threshold,
+            falloff,
+        }
+    }
+
+    fn domain_warp<X, Y>(self, x: X, y: Y, strength: f64) -> DomainWarp<Self, X, Y> {
+        DomainWarp {
+            source: self,
+            x,
+            y,
+            strength,
+        }
+    }
+
+    fn terrace(self, step: f64, sharpness: f64) -> Terrace<Self> {
+        assert!(step > 0., "terrace step must be positive");
+        Terrace {
+            source: self,
+            step,
+            sharpness,
+        }
+    }
+}
+
+impl<T, S: Sample<T, f64>> SampleExt<T> for S {}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Returns the x coordinate of the point it's sampled at.
+    struct X;
+
+    impl Sample<[f64; 2], f64> for X {
+        fn get(&self, point: [f64; 2]) -> f64 {
+            point[0]
+        }
+    }
+
+    /// Counts how many times it's been sampled.
+    #[derive(Clone, Default)]
+    struct Counter(Rc<Cell<usize>>);
+
+    impl Counter {
+        fn count(&self) -> usize {
+            self.0.get()
+        }
+    }
+
+    impl Sample<[f64; 2], f64> for Counter {
+        fn get(&self, _point: [f64; 2]) -> f64 {
+            self.0.set(self.0.get() + 1);
+            0.
+        }
+    }
+
+    impl Sample<[f64; 3], f64> for Counter {
+        fn get(&self, _point: [f64; 3]) -> f64 {
+            self.0.set(self.0.get() + 1);
+            0.
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn add_and_multiply() {
+        assert_close(Constant(2.).add(X).get([3., 0.]), 5.);
+        assert_close(Constant(2.).multiply(X).get([3., 0.]), 6.);
+    }
+
+    #[test]
+    fn clamp() {
+        let clamped = X.clamp(-1., 1.);
+        assert_close(clamped.get([-5., 0.]), -1.);
+        assert_close(clamped.get([0.5, 0.]), 0.5);
+        assert_close(clamped.get([5., 0.]), 1.);
+    }
+
+    #[test]
+    fn remap() {
+        let remapped = X.remap((-1., 1.), (0., 100.));
+        assert_close(remapped.get([-1., 0.]), 0.);
+        assert_close(remapped.get([0., 0.]), 50.);
+        assert_close(remapped.get([1., 0.]), 100.);
+        // Values outside the range are extrapolated.
+        assert_close(remapped.get([2., 0.]), 150.);
+    }
+
+    #[test]
+    fn select() {
+        let selected = X.select(Constant(-1.), Constant(1.), 0., 0.5);
+        assert_close(selected.get([-1., 0.]), -1.);
+        assert_close(selected.get([1., 0.]), 1.);
+        // Within the falloff, the two are blended.
+        assert_close(selected.get([0., 0.]), 0.);
+        assert_close(selected.get([0.25, 0.]), 0.5);
+
+        let hard = X.select(Constant(-1.), Constant(1.), 0., 0.);
+        assert_close(hard.get([-0.01, 0.]), -1.);
+        assert_close(hard.get([0.01, 0.]), 1.);
+    }
+
+    #[test]
+    fn domain_warp() {
+        let warped = X.domain_warp(Constant(1.), Constant(0.), 10.);
+        assert_close(warped.get([5., 0.]), 15.);
+    }
+
+    #[test]
+    fn terrace() {
+        let unchanged = X.terrace(1., 1.);
+        assert_close(unchanged.get([0.3, 0.]), 0.3);
+
+        let terraced = X.terrace(1., 4.);
+        assert_close(terraced.get([2., 0.]), 2.);
+        assert_close(terraced.get([2.5, 0.]), 2.0625);
+        assert_close(terraced.get([-0.5, 0.]), -0.9375);
+    }
+
+    #[test]
+    fn caches_sample_each_point_once() {
+        let counter = Counter::default();
+        let cached = Cache2d::new(counter.clone());
+        cached.get([1., 2.]);
+        cached.get([1., 2.]);
+        cached.get([2., 1.]);
+        assert_eq!(counter.count(), 2);
+
+        let counter = Counter::default();
+        let cached = Cache3d::new(counter.clone());
+        cached.get([1., 2., 3.]);
+        cached.get([1., 2., 3.]);
+        assert_eq!(counter.count(), 1);
+    }
+}
@@ -1,7 +1,13 @@
+mod graph;
+
 use std::marker::PhantomData;
 
-use colonize_core::{NoiseSample, Sample};
-use noise::NoiseFn;
+use colonize_core::{NoiseConfig, NoiseSample, Sample};
+use noise::{Fbm, MultiFractal, NoiseFn, RidgedMulti, Seedable};
+
+pub use graph::{
+    Add, Cache2d, Cache3d, Clamp, Constant, DomainWarp, Multiply, Remap, SampleExt, Select, Terrace,
+};
 
 pub struct Noise2d<N, T>
 where
@@ -33,3 +39,20 @@ where
         self.noise.get(point)
     }
 }
+
+/// Builds the ridged multifractal noise used for terrain elevation from its configuration.
+pub fn ridged_multi(config: &NoiseConfig, seed: u32) -> Noise2d<RidgedMulti, [f64; 2]> {
+    Noise2d::new(
+        RidgedMulti::new()
+            .set_seed(seed)
+            .set_frequency(config.frequency)
+            .set_lacunarity(config.lacunarity)
+            .set_persistence(config.persistence)
+            .set_octaves(config.octaves),
+    )
+}
+
+/// Builds the 3D noise which shapes the strata underground and the ore veins within them.
+pub fn strata_noise(seed: u32) -> Noise2d<Fbm, [f64; 3]> {
+    Noise2d::new(Fbm::new().set_seed(seed).set_frequency(0.05))
+}
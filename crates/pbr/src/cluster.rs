@@ -0,0 +1,226 @@
+use bevy::{core::Byteable, math::Vec3, prelude::GlobalTransform};
+
+use crate::light::PointLightRaw;
+
+/// The width, height and depth of each cluster, in world units.
+pub const CLUSTER_SIZE: f32 = 8.0;
+/// The number of clusters along each axis. The clusters cover a box of this many clusters in
+/// front of the camera; fragments outside of it aren't lit by point lights.
+pub const CLUSTER_COUNTS: [i32; 3] = [32, 16, 32];
+
+/// The grid of clusters in front of the camera, and the point lights which reach into each
+/// cluster.
+///
+/// Rather than every fragment looping over every point light in the scene, the lights are
+/// sorted into the clusters they reach whenever they or the clusters move, and each fragment
+/// only loops over the lights of the cluster it's in. The clusters are boxes in world space, rather than slices of the
+/// view frustum, since the lights in a colony mostly stay put while the camera moves.
+#[derive(Debug)]
+pub(crate) struct LightClusters {
+    /// The world position of the minimum corner of the first cluster.
+    origin: Vec3,
+    /// First the offset into `data` of the light indices of each cluster, and how many there
+    /// are, then the indices of the lights themselves.
+    data: Vec<u32>,
+}
+
+/// The part of the clusters storage buffer which comes before their data.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LightClustersHeader {
+    pub counts: [i32; 4],
+    /// The origin of the clusters, and their size.
+    pub origin: [f32; 4],
+}
+
+unsafe impl Byteable for LightClustersHeader {}
+
+impl LightClusters {
+    /// The point to center the clusters on so that they cover as much of a camera's view as
+    /// they can. The clusters are pushed out along the view direction until the camera is just
+    /// inside the back of them, since nothing behind the camera is on screen.
+    pub fn view_center(camera: &GlobalTransform) -> Vec3 {
+        let forward = camera.rotation * -Vec3::unit_z();
+        let half_extent = Self::half_extent();
+        // How far the clusters reach from their center along the view direction.
+        let reach = (0..3)
+            .filter(|&axis| forward[axis] != 0.0)
+            .map(|axis| half_extent[axis] / forward[axis].abs())
+            .fold(f32::INFINITY, f32::min);
+        camera.translation + forward * (reach - CLUSTER_SIZE).max(0.0)
+    }
+
+    /// The world position of the minimum corner of the clusters around a point. The clusters
+    /// snap to a grid, so that they don't shift under the lights as the camera moves.
+    pub fn origin(center: Vec3) -> Vec3 {
+        (center / CLUSTER_SIZE).floor() * CLUSTER_SIZE - Self::half_extent()
+    }
+
+    fn half_extent() -> Vec3 {
+        Vec3::new(
+            (CLUSTER_COUNTS[0] / 2) as f32,
+            (CLUSTER_COUNTS[1] / 2) as f32,
+            (CLUSTER_COUNTS[2] / 2) as f32,
+        ) * CLUSTER_SIZE
+    }
+
+    /// Sorts the lights into the clusters around a point.
+    pub fn new(center: Vec3, lights: &[PointLightRaw]) -> Self {
+        let origin = Self::origin(center);
+
+        // Count the lights in each cluster first, so that each cluster's indices can be laid
+        // out next to each other without any allocations per cluster.
+        let num_clusters = Self::num_clusters();
+        let mut counts = vec![0u32; num_clusters];
+        for light in lights {
+            Self::for_each_cluster(origin, light, |index| counts[index] += 1);
+        }
+        let mut data = vec![0u32; 2 * num_clusters];
+        let mut offset = data.len() as u32;
+        for (index, count) in counts.iter().enumerate() {
+            data[2 * index] = offset;
+            offset += count;
+        }
+        data.resize(offset as usize, 0);
+
+        // Then fill them in, counting back up from zero.
+        for count in counts.iter_mut() {
+            *count = 0;
+        }
+        for (light_index, light) in lights.iter().enumerate() {
+            Self::for_each_cluster(origin, light, |index| {
+                let slot = data[2 * index] + counts[index];
+                data[slot as usize] = light_index as u32;
+                counts[index] += 1;
+            });
+        }
+        for (index, count) in counts.iter().enumerate() {
+            data[2 * index + 1] = *count;
+        }
+
+        LightClusters { origin, data }
+    }
+
+    fn num_clusters() -> usize {
+        CLUSTER_COUNTS.iter().product::<i32>() as usize
+    }
+
+    /// Calls `f` with the index of each cluster which the light reaches into.
+    fn for_each_cluster(origin: Vec3, light: &PointLightRaw, mut f: impl FnMut(usize)) {
+        let position = light.position();
+        let range = light.range();
+        let local = (position - origin) / CLUSTER_SIZE;
+        let reach = range / CLUSTER_SIZE;
+        let min = [
+            (local.x - reach).floor() as i32,
+            (local.y - reach).floor() as i32,
+            (local.z - reach).floor() as i32,
+        ];
+        let max = [
+            (local.x + reach).floor() as i32,
+            (local.y + reach).floor() as i32,
+            (local.z + reach).floor() as i32,
+        ];
+        if (0..3).any(|axis| max[axis] < 0 || min[axis] >= CLUSTER_COUNTS[axis]) {
+            return;
+        }
+
+        for z in min[2].max(0)..=max[2].min(CLUSTER_COUNTS[2] - 1) {
+            for y in min[1].max(0)..=max[1].min(CLUSTER_COUNTS[1] - 1) {
+                for x in min[0].max(0)..=max[0].min(CLUSTER_COUNTS[0] - 1) {
+                    // Skip the clusters in the corners of the light's bounding box, which its
+                    // sphere doesn't reach.
+                    let cluster_min =
+                        origin + Vec3::new(x as f32, y as f32, z as f32) * CLUSTER_SIZE;
+                    let cluster_max =
+                        cluster_min + Vec3::new(CLUSTER_SIZE, CLUSTER_SIZE, CLUSTER_SIZE);
+                    let nearest = position.max(cluster_min).min(cluster_max);
+                    if (nearest - position).length_squared() > range * range {
+                        continue;
+                    }
+                    f(((z * CLUSTER_COUNTS[1] + y) * CLUSTER_COUNTS[0] + x) as usize);
+                }
+            }
+        }
+    }
+
+    pub fn header(&self) -> LightClustersHeader {
+        LightClustersHeader {
+            counts: [CLUSTER_COUNTS[0], CLUSTER_COUNTS[1], CLUSTER_COUNTS[2], 0],
+            origin: [self.origin.x, self.origin.y, self.origin.z, CLUSTER_SIZE],
+        }
+    }
+
+    pub fn data(&self) -> &[u32] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn light(x: f32, y: f32, z: f32, range: f32) -> PointLightRaw {
+        PointLightRaw {
+            pos: [x, y, z, range],
+            color: [1.0; 4],
+        }
+    }
+
+    /// The indices of the lights in the cluster containing a point.
+    fn lights_at(clusters: &LightClusters, point: Vec3) -> &[u32] {
+        let local = (point - clusters.origin) / CLUSTER_SIZE;
+        let (x, y, z) = (local.x as i32, local.y as i32, local.z as i32);
+        let index = ((z * CLUSTER_COUNTS[1] + y) * CLUSTER_COUNTS[0] + x) as usize;
+        let offset = clusters.data[2 * index] as usize;
+        let count = clusters.data[2 * index + 1] as usize;
+        &clusters.data[offset..offset + count]
+    }
+
+    #[test]
+    fn lights_are_in_the_clusters_they_reach() {
+        let lights = [light(4.0, 4.0, 4.0, 3.0), light(20.0, 4.0, 4.0, 10.0)];
+        let clusters = LightClusters::new(Vec3::zero(), &lights);
+        assert_eq!(lights_at(&clusters, Vec3::new(4.0, 4.0, 4.0)), &[0]);
+        assert_eq!(lights_at(&clusters, Vec3::new(12.0, 4.0, 4.0)), &[1]);
+        assert_eq!(lights_at(&clusters, Vec3::new(20.0, 4.0, 4.0)), &[1]);
+        assert!(lights_at(&clusters, Vec3::new(40.0, 4.0, 4.0)).is_empty());
+        assert!(lights_at(&clusters, Vec3::new(4.0, -12.0, 4.0)).is_empty());
+    }
+
+    #[test]
+    fn sphere_skips_corner_clusters() {
+        // The light is in the corner of a cluster, and only just reaches the clusters next to
+        // it, so its bounding box covers a diagonal neighbour which its sphere doesn't.
+        let lights = [light(7.5, 7.5, 7.5, 0.8)];
+        let clusters = LightClusters::new(Vec3::zero(), &lights);
+        assert_eq!(lights_at(&clusters, Vec3::new(8.5, 4.0, 4.0)), &[0]);
+        assert!(lights_at(&clusters, Vec3::new(8.5, 8.5, 8.5)).is_empty());
+    }
+
+    #[test]
+    fn clusters_cover_the_ground_in_front_of_the_camera() {
+        // The camera the game starts with, high above the ground and looking down at it.
+        let camera = GlobalTransform::from_translation(Vec3::new(32.0, 100.0, 32.0))
+            .looking_at(Vec3::zero(), Vec3::unit_y());
+        let origin = LightClusters::origin(LightClusters::view_center(&camera));
+        let end = origin + LightClusters::half_extent() * 2.0;
+        for point in &[camera.translation, Vec3::zero(), Vec3::new(16.0, 0.0, 16.0)] {
+            assert!(
+                (0..3).all(|axis| origin[axis] <= point[axis] && point[axis] < end[axis]),
+                "{:?} isn't in the clusters from {:?} to {:?}",
+                point,
+                origin,
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn lights_outside_of_the_clusters_are_dropped() {
+        let far = CLUSTER_SIZE * CLUSTER_COUNTS[0] as f32;
+        let lights = [light(far, 0.0, 0.0, 5.0)];
+        let clusters = LightClusters::new(Vec3::zero(), &lights);
+        assert_eq!(clusters.data().len(), 2 * LightClusters::num_clusters());
+    }
+}
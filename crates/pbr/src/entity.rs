@@ -1,8 +1,32 @@
-use bevy::{pbr::PbrBundle, prelude::RenderPipelines, render::pipeline::RenderPipeline};
+use bevy::{
+    ecs::Bundle,
+    pbr::PbrBundle,
+    prelude::{GlobalTransform, RenderPipelines, Transform},
+    render::pipeline::RenderPipeline,
+};
 
-use crate::render_graph::FORWARD_PIPELINE_HANDLE;
+use crate::{
+    light::{DirectionalLight, PointLight},
+    render_graph::FORWARD_PIPELINE_HANDLE,
+};
 pub use bevy::pbr::LightBundle;
 
+/// A component bundle for "directional light" entities
+#[derive(Debug, Bundle, Default)]
+pub struct DirectionalLightBundle {
+    pub directional_light: DirectionalLight,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+/// A component bundle for "point light" entities
+#[derive(Debug, Bundle, Default)]
+pub struct PointLightBundle {
+    pub point_light: PointLight,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
 pub fn pbr_bundle() -> PbrBundle {
     PbrBundle {
         render_pipelines: RenderPipelines::from_pipelines(vec![RenderPipeline::new(
@@ -21,9 +21,12 @@
 // SOFTWARE.
 pub mod render_graph;
 
+#[cfg(not(target_arch = "wasm32"))]
+mod cluster;
 mod entity;
 mod light;
 mod material;
+mod shadow;
 mod y_level;
 
 use bevy::{
@@ -35,10 +38,19 @@ use bevy::{
 pub use entity::*;
 pub use light::*;
 pub use material::*;
+pub use shadow::{ShadowCaster, ShadowSettings, SHADOW_CAMERA, SHADOW_MAP_SIZE};
 pub use y_level::*;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use cluster::{CLUSTER_COUNTS, CLUSTER_SIZE};
+
 pub mod prelude {
-    pub use crate::{entity::*, light::Light, material::StandardMaterial};
+    pub use crate::{
+        entity::*,
+        light::{DirectionalLight, Light, PointLight},
+        material::StandardMaterial,
+        shadow::ShadowCaster,
+    };
 }
 
 use material::StandardMaterial;
@@ -52,11 +64,19 @@ impl Plugin for PbrPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_asset::<StandardMaterial>()
             .register_type::<Light>()
+            .register_type::<DirectionalLight>()
+            .register_type::<PointLight>()
             .add_system_to_stage(
                 stage::POST_UPDATE,
                 shader::asset_shader_defs_system::<StandardMaterial>.system(),
             )
-            .init_resource::<AmbientLight>();
+            .init_resource::<AmbientLight>()
+            .init_resource::<ShadowSettings>();
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_startup_system(shadow::spawn_shadow_camera.system())
+            .add_system(shadow::spawn_shadow_proxies.system())
+            .add_system_to_stage(stage::POST_UPDATE, shadow::update_shadow_camera.system())
+            .add_system_to_stage(stage::POST_UPDATE, shadow::update_shadow_proxies.system());
         let resources = app.resources();
         let mut render_graph = resources.get_mut::<RenderGraph>().unwrap();
         add_pbr_graph(&mut render_graph, resources);
@@ -21,36 +21,118 @@
 // SOFTWARE.
 use bevy::{
     core::Byteable,
-    prelude::GlobalTransform,
-    render::camera::{CameraProjection, PerspectiveProjection},
+    math::Vec3,
+    prelude::{Color, GlobalTransform},
+    reflect::{Reflect, ReflectComponent},
 };
 
 pub use bevy::pbr::{AmbientLight, Light};
 
+/// A light which is infinitely far away, like the sun, so that it shines in the same direction
+/// everywhere. It shines along its entity's forward (-Z) axis.
+///
+/// The first directional light is the primary light, which casts shadows. Meshes drawn with
+/// Bevy's own pipeline only know about Bevy's point [`Light`]s, so a directional light's entity
+/// can also have one far off in its direction to light them; the forward pipeline ignores it.
+#[derive(Debug, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct DirectionalLight {
+    pub color: Color,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        DirectionalLight {
+            color: Color::rgb(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl DirectionalLight {
+    /// The direction from any point towards the light.
+    pub fn direction(global_transform: &GlobalTransform) -> Vec3 {
+        global_transform.rotation * Vec3::unit_z()
+    }
+}
+
+/// A light which shines in every direction from its entity's position, like a torch or a lamp.
+/// It gets dimmer with distance, and goes out altogether at its range, so that each fragment
+/// only has to be lit by the few lights which are near it.
+#[derive(Debug, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct PointLight {
+    pub color: Color,
+    /// How bright the light is. A surface facing the light from one unit away is lit by half
+    /// of it.
+    pub intensity: f32,
+    /// How far the light reaches, in world units.
+    pub range: f32,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        PointLight {
+            color: Color::rgb(1.0, 1.0, 1.0),
+            intensity: 5.0,
+            range: 10.0,
+        }
+    }
+}
+
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub(crate) struct LightRaw {
-    pub proj: [[f32; 4]; 4],
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PointLightRaw {
+    /// The light's position, and its range.
     pub pos: [f32; 4],
+    /// The light's color, multiplied by its intensity.
     pub color: [f32; 4],
 }
 
-unsafe impl Byteable for LightRaw {}
+unsafe impl Byteable for PointLightRaw {}
 
-impl LightRaw {
-    pub fn from(light: &Light, global_transform: &GlobalTransform) -> LightRaw {
-        let perspective = PerspectiveProjection {
-            fov: light.fov,
-            aspect_ratio: 1.0,
-            near: light.depth.start,
-            far: light.depth.end,
-        };
-
-        let proj = perspective.get_projection_matrix() * global_transform.compute_matrix();
+impl PointLightRaw {
+    pub fn from(light: &PointLight, global_transform: &GlobalTransform) -> PointLightRaw {
         let (x, y, z) = global_transform.translation.into();
-        LightRaw {
-            proj: proj.to_cols_array_2d(),
-            pos: [x, y, z, 1.0],
+        let color = light.color;
+        PointLightRaw {
+            pos: [x, y, z, light.range],
+            color: [
+                color.r() * light.intensity,
+                color.g() * light.intensity,
+                color.b() * light.intensity,
+                1.0,
+            ],
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        Vec3::new(self.pos[0], self.pos[1], self.pos[2])
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn range(&self) -> f32 {
+        self.pos[3]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DirectionalLightRaw {
+    /// The direction towards the light.
+    pub direction: [f32; 4],
+    pub color: [f32; 4],
+}
+
+unsafe impl Byteable for DirectionalLightRaw {}
+
+impl DirectionalLightRaw {
+    pub fn from(
+        light: &DirectionalLight,
+        global_transform: &GlobalTransform,
+    ) -> DirectionalLightRaw {
+        let (x, y, z) = DirectionalLight::direction(global_transform).into();
+        DirectionalLightRaw {
+            direction: [x, y, z, 0.0],
             color: light.color.into(),
         }
     }
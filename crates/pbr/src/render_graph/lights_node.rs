@@ -19,13 +19,19 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
-use crate::light::{AmbientLight, Light, LightRaw};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::cluster::LightClusters;
+use crate::light::{
+    AmbientLight, DirectionalLight, DirectionalLightRaw, PointLight, PointLightRaw,
+};
 use bevy::{
     core::{AsBytes, Byteable},
-    ecs::{Commands, IntoSystem, Local, Query, Res, ResMut, Resources, System, World},
+    ecs::{Commands, IntoSystem, Local, Query, Res, ResMut, Resources, System, With, World},
+    math::Vec3,
     prelude::GlobalTransform,
     render::{
-        render_graph::{CommandQueue, Node, ResourceSlots, SystemNode},
+        camera::{ActiveCameras, Camera},
+        render_graph::{base::camera::CAMERA3D, CommandQueue, Node, ResourceSlots, SystemNode},
         renderer::{
             BufferId, BufferInfo, BufferUsage, RenderContext, RenderResourceBinding,
             RenderResourceBindings, RenderResourceContext,
@@ -35,17 +41,27 @@ use bevy::{
 
 use super::uniform;
 
+/// The most directional lights which light the scene. The shaders' `MAX_DIRECTIONAL_LIGHTS`
+/// has to match.
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+
+/// The most point lights which light the scene on WASM, which has no storage buffers to sort
+/// them into clusters with. The ones nearest the camera are used. The WASM shader's
+/// `MAX_POINT_LIGHTS` has to match.
+#[cfg(target_arch = "wasm32")]
+pub const MAX_POINT_LIGHTS: usize = 32;
+
 /// A Render Graph [Node] that write light data from the ECS to GPU buffers
 #[derive(Debug, Default)]
 pub struct LightsNode {
     command_queue: CommandQueue,
-    max_lights: usize,
+    max_directional_lights: usize,
 }
 
 impl LightsNode {
-    pub fn new(max_lights: usize) -> Self {
+    pub fn new(max_directional_lights: usize) -> Self {
         LightsNode {
-            max_lights,
+            max_directional_lights,
             command_queue: CommandQueue::default(),
         }
     }
@@ -67,6 +83,8 @@ impl Node for LightsNode {
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 struct LightCount {
+    /// The number of directional lights, then of point lights. Only the WASM shader reads the
+    /// number of point lights; elsewhere, the clusters list the lights to use.
     pub num_lights: [u32; 4],
 }
 
@@ -79,103 +97,233 @@ impl SystemNode for LightsNode {
             system.id(),
             LightsNodeSystemState {
                 command_queue: self.command_queue.clone(),
-                max_lights: self.max_lights,
-                light_buffer: None,
-                staging_buffer: None,
+                max_directional_lights: self.max_directional_lights,
+                ..Default::default()
             },
         );
         Box::new(system)
     }
 }
 
+/// A GPU buffer and the staging buffer it's written through, which are both replaced by bigger
+/// ones whenever what's written doesn't fit.
+#[derive(Debug, Default)]
+struct LightBuffer {
+    buffer: Option<BufferId>,
+    staging_buffer: Option<BufferId>,
+    capacity: usize,
+}
+
+impl LightBuffer {
+    /// The smallest buffer to create, since storage buffers can't be empty.
+    const MIN_CAPACITY: usize = 256;
+
+    fn write(
+        &mut self,
+        name: &str,
+        usage: BufferUsage,
+        bytes: &[u8],
+        render_resource_context: &dyn RenderResourceContext,
+        render_resource_bindings: &mut RenderResourceBindings,
+        command_queue: &mut CommandQueue,
+    ) {
+        let size = bytes.len();
+        let (buffer, staging_buffer) = match (self.buffer, self.staging_buffer) {
+            (Some(buffer), Some(staging_buffer)) if size <= self.capacity => {
+                render_resource_context.map_buffer(staging_buffer);
+                (buffer, staging_buffer)
+            }
+            (buffer, staging_buffer) => {
+                if let (Some(buffer), Some(staging_buffer)) = (buffer, staging_buffer) {
+                    render_resource_context.remove_buffer(buffer);
+                    render_resource_context.remove_buffer(staging_buffer);
+                }
+                self.capacity = size.max(Self::MIN_CAPACITY).next_power_of_two();
+
+                let buffer = render_resource_context.create_buffer(BufferInfo {
+                    size: self.capacity,
+                    buffer_usage: usage | BufferUsage::COPY_DST,
+                    ..Default::default()
+                });
+                render_resource_bindings.set(
+                    name,
+                    RenderResourceBinding::Buffer {
+                        buffer,
+                        range: 0..self.capacity as u64,
+                        dynamic_index: None,
+                    },
+                );
+                self.buffer = Some(buffer);
+
+                let staging_buffer = render_resource_context.create_buffer(BufferInfo {
+                    size: self.capacity,
+                    buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
+                    mapped_at_creation: true,
+                });
+                self.staging_buffer = Some(staging_buffer);
+                (buffer, staging_buffer)
+            }
+        };
+
+        if size > 0 {
+            render_resource_context.write_mapped_buffer(
+                staging_buffer,
+                0..size as u64,
+                &mut |data, _renderer| {
+                    data[0..size].copy_from_slice(bytes);
+                },
+            );
+        }
+        render_resource_context.unmap_buffer(staging_buffer);
+        if size > 0 {
+            command_queue.copy_buffer_to_buffer(staging_buffer, 0, buffer, 0, size as u64);
+        }
+    }
+}
+
 /// Local "lights node system" state
 #[derive(Debug, Default)]
 pub struct LightsNodeSystemState {
-    light_buffer: Option<BufferId>,
-    staging_buffer: Option<BufferId>,
+    lights: LightBuffer,
+    point_lights: LightBuffer,
+    #[cfg(not(target_arch = "wasm32"))]
+    light_clusters: LightBuffer,
+    /// The origin of the clusters and the point lights which were last written, so that they
+    /// aren't written again until something changes.
+    #[cfg(not(target_arch = "wasm32"))]
+    written_point_lights: Option<(Vec3, Vec<PointLightRaw>)>,
     command_queue: CommandQueue,
-    max_lights: usize,
+    max_directional_lights: usize,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn lights_node_system(
     mut state: Local<LightsNodeSystemState>,
     render_resource_context: Res<Box<dyn RenderResourceContext>>,
     ambient_light_resource: Res<AmbientLight>,
+    active_cameras: Res<ActiveCameras>,
     // TODO: this write on RenderResourceBindings will prevent this system from running in parallel with other systems that do the same
     mut render_resource_bindings: ResMut<RenderResourceBindings>,
-    query: Query<(&Light, &GlobalTransform)>,
+    directional_query: Query<(&DirectionalLight, &GlobalTransform)>,
+    point_query: Query<(&PointLight, &GlobalTransform)>,
+    camera_query: Query<&GlobalTransform, With<Camera>>,
 ) {
-    let state = &mut state;
+    let state = &mut *state;
     let render_resource_context = &**render_resource_context;
 
+    let directional_lights = directional_query
+        .iter()
+        .map(|(light, global_transform)| DirectionalLightRaw::from(light, global_transform))
+        .take(state.max_directional_lights)
+        .collect::<Vec<_>>();
+    let point_lights = point_query
+        .iter()
+        .map(|(light, global_transform)| PointLightRaw::from(light, global_transform))
+        .collect::<Vec<_>>();
+    // Only the point lights in front of the camera light anything on screen.
+    let camera = active_cameras
+        .get(CAMERA3D)
+        .and_then(|camera| camera_query.get(camera).ok());
+
+    #[cfg(target_arch = "wasm32")]
+    let point_lights = nearest_point_lights(
+        point_lights,
+        camera.map_or(Vec3::zero(), |transform| transform.translation),
+    );
+
     let ambient_light: [f32; 4] = ambient_light_resource.color.into();
-    let ambient_light_size = std::mem::size_of::<[f32; 4]>();
-    let light_count = query.iter().count();
-    let size = std::mem::size_of::<LightRaw>();
-    let light_count_size = ambient_light_size + std::mem::size_of::<LightCount>();
-    let light_array_size = size * light_count;
-    let light_array_max_size = size * state.max_lights;
-    let current_light_uniform_size = light_count_size + light_array_size;
-    let max_light_uniform_size = light_count_size + light_array_max_size;
-
-    if let Some(staging_buffer) = state.staging_buffer {
-        if light_count == 0 {
-            return;
-        }
+    let light_count = LightCount {
+        num_lights: [
+            directional_lights.len() as u32,
+            point_lights.len() as u32,
+            0,
+            0,
+        ],
+    };
+    // The uniform is always written whole, with the unused lights zeroed.
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(ambient_light.as_bytes());
+    bytes.extend_from_slice(light_count.as_bytes());
+    bytes.extend_from_slice(directional_lights.as_slice().as_bytes());
+    bytes.resize(
+        std::mem::size_of::<[f32; 4]>()
+            + std::mem::size_of::<LightCount>()
+            + std::mem::size_of::<DirectionalLightRaw>() * state.max_directional_lights,
+        0,
+    );
+    state.lights.write(
+        uniform::LIGHTS,
+        BufferUsage::UNIFORM,
+        &bytes,
+        render_resource_context,
+        &mut render_resource_bindings,
+        &mut state.command_queue,
+    );
 
-        render_resource_context.map_buffer(staging_buffer);
-    } else {
-        let buffer = render_resource_context.create_buffer(BufferInfo {
-            size: max_light_uniform_size,
-            buffer_usage: BufferUsage::UNIFORM | BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
-            ..Default::default()
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let center = camera.map_or(Vec3::zero(), |transform| {
+            LightClusters::view_center(transform)
         });
-        render_resource_bindings.set(
-            uniform::LIGHTS,
-            RenderResourceBinding::Buffer {
-                buffer,
-                range: 0..max_light_uniform_size as u64,
-                dynamic_index: None,
+        // The clusters only move in whole clusters, and the lights in a colony mostly stay put,
+        // so most frames there's nothing new to write.
+        let origin = LightClusters::origin(center);
+        let unchanged = state.written_point_lights.as_ref().map_or(
+            false,
+            |(written_origin, written_lights)| {
+                *written_origin == origin && *written_lights == point_lights
             },
         );
-        state.light_buffer = Some(buffer);
+        if !unchanged {
+            state.point_lights.write(
+                uniform::POINT_LIGHTS,
+                BufferUsage::STORAGE,
+                point_lights.as_slice().as_bytes(),
+                render_resource_context,
+                &mut render_resource_bindings,
+                &mut state.command_queue,
+            );
 
-        let staging_buffer = render_resource_context.create_buffer(BufferInfo {
-            size: max_light_uniform_size,
-            buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
-            mapped_at_creation: true,
-        });
-        state.staging_buffer = Some(staging_buffer);
+            let clusters = LightClusters::new(center, &point_lights);
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(clusters.header().as_bytes());
+            bytes.extend_from_slice(clusters.data().as_bytes());
+            state.light_clusters.write(
+                uniform::LIGHT_CLUSTERS,
+                BufferUsage::STORAGE,
+                &bytes,
+                render_resource_context,
+                &mut render_resource_bindings,
+                &mut state.command_queue,
+            );
+            state.written_point_lights = Some((origin, point_lights));
+        }
     }
 
-    let staging_buffer = state.staging_buffer.unwrap();
-    render_resource_context.write_mapped_buffer(
-        staging_buffer,
-        0..current_light_uniform_size as u64,
-        &mut |data, _renderer| {
-            // ambient light
-            data[0..ambient_light_size].copy_from_slice(ambient_light.as_bytes());
-
-            // light count
-            data[ambient_light_size..light_count_size]
-                .copy_from_slice([light_count as u32, 0, 0, 0].as_bytes());
-
-            // light array
-            for ((light, global_transform), slot) in query
-                .iter()
-                .zip(data[light_count_size..current_light_uniform_size].chunks_exact_mut(size))
-            {
-                slot.copy_from_slice(LightRaw::from(light, global_transform).as_bytes());
-            }
-        },
-    );
-    render_resource_context.unmap_buffer(staging_buffer);
-    let light_buffer = state.light_buffer.unwrap();
-    state.command_queue.copy_buffer_to_buffer(
-        staging_buffer,
-        0,
-        light_buffer,
-        0,
-        max_light_uniform_size as u64,
-    );
+    #[cfg(target_arch = "wasm32")]
+    {
+        let mut bytes = point_lights.as_slice().as_bytes().to_vec();
+        bytes.resize(std::mem::size_of::<PointLightRaw>() * MAX_POINT_LIGHTS, 0);
+        state.point_lights.write(
+            uniform::POINT_LIGHTS,
+            BufferUsage::UNIFORM,
+            &bytes,
+            render_resource_context,
+            &mut render_resource_bindings,
+            &mut state.command_queue,
+        );
+    }
+}
+
+/// The point lights nearest a position, as many as fit in the WASM shader's uniform.
+#[cfg(target_arch = "wasm32")]
+fn nearest_point_lights(mut lights: Vec<PointLightRaw>, position: Vec3) -> Vec<PointLightRaw> {
+    let distance = |light: &PointLightRaw| (light.position() - position).length_squared();
+    lights.sort_by(|a, b| {
+        distance(a)
+            .partial_cmp(&distance(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    lights.truncate(MAX_POINT_LIGHTS);
+    lights
 }
@@ -21,6 +21,10 @@
 // SOFTWARE.
 mod forward_pipeline;
 mod lights_node;
+#[cfg(not(target_arch = "wasm32"))]
+mod shadow_node;
+#[cfg(not(target_arch = "wasm32"))]
+mod shadow_pipeline;
 mod y_level_node;
 
 use bevy::{
@@ -33,9 +37,24 @@ use bevy::{
 };
 pub use forward_pipeline::*;
 pub use lights_node::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use shadow_node::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use shadow_pipeline::*;
 pub use y_level_node::*;
 
 use crate::prelude::StandardMaterial;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::shadow::{ShadowProxy, SHADOW_CAMERA};
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::render::{
+    camera::ActiveCameras,
+    pass::{
+        LoadOp, Operations, PassDescriptor, RenderPassDepthStencilAttachmentDescriptor,
+        TextureAttachment,
+    },
+    render_graph::{CameraNode, PassNode},
+};
 
 /// the names of pbr graph nodes
 pub mod node {
@@ -43,12 +62,21 @@ pub mod node {
     pub const STANDARD_MATERIAL: &str = "standard_material";
     pub const LIGHTS: &str = "lights";
     pub const Y_LEVEL: &str = "y_level";
+    pub const SHADOW: &str = "shadow";
+    pub const SHADOW_CAMERA: &str = "shadow_camera";
+    pub const SHADOW_PASS: &str = "shadow_pass";
 }
 
 /// the names of pbr uniforms
 pub mod uniform {
     pub const LIGHTS: &str = "Lights";
+    pub const POINT_LIGHTS: &str = "PointLights";
+    #[cfg(not(target_arch = "wasm32"))]
+    pub const LIGHT_CLUSTERS: &str = "LightClusters";
     pub const Y_LEVEL: &str = "YLevel";
+    pub const SHADOW_LIGHT: &str = "ShadowLight";
+    pub const SHADOW_MAP: &str = "ShadowMap";
+    pub const SHADOW_MAP_SAMPLER: &str = "ShadowMap_sampler";
 }
 
 pub(crate) fn add_pbr_graph(graph: &mut RenderGraph, resources: &Resources) {
@@ -60,7 +88,7 @@ pub(crate) fn add_pbr_graph(graph: &mut RenderGraph, resources: &Resources) {
         node::STANDARD_MATERIAL,
         AssetRenderResourcesNode::<StandardMaterial>::new(true),
     );
-    graph.add_system_node(node::LIGHTS, LightsNode::new(10));
+    graph.add_system_node(node::LIGHTS, LightsNode::new(MAX_DIRECTIONAL_LIGHTS));
     graph.add_system_node(node::Y_LEVEL, YLevelNode::new());
     let mut pipelines = resources.get_mut::<Assets<PipelineDescriptor>>().unwrap();
     let mut shaders = resources.get_mut::<Assets<Shader>>().unwrap();
@@ -69,6 +97,11 @@ pub(crate) fn add_pbr_graph(graph: &mut RenderGraph, resources: &Resources) {
         FORWARD_PIPELINE_HANDLE,
         build_forward_pipeline(&mut shaders, &mut asset_server),
     );
+    #[cfg(not(target_arch = "wasm32"))]
+    pipelines.set_untracked(
+        SHADOW_PIPELINE_HANDLE,
+        build_shadow_pipeline(&mut asset_server),
+    );
 
     // TODO: replace these with "autowire" groups
     graph
@@ -83,4 +116,54 @@ pub(crate) fn add_pbr_graph(graph: &mut RenderGraph, resources: &Resources) {
     graph
         .add_node_edge(node::Y_LEVEL, base::node::MAIN_PASS)
         .unwrap();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    add_shadow_graph(graph, resources);
+}
+
+/// Renders the shadow casters' proxies into the shadow map from the primary light, before the
+/// main pass samples it. Shadows are left out of the WASM build, whose shaders don't sample a
+/// shadow map.
+#[cfg(not(target_arch = "wasm32"))]
+fn add_shadow_graph(graph: &mut RenderGraph, resources: &Resources) {
+    resources
+        .get_mut::<ActiveCameras>()
+        .unwrap()
+        .add(SHADOW_CAMERA);
+    graph.add_system_node(node::SHADOW_CAMERA, CameraNode::new(SHADOW_CAMERA));
+    graph.add_system_node(node::SHADOW, ShadowNode::new());
+
+    let mut shadow_pass = PassNode::<&ShadowProxy>::new(PassDescriptor {
+        color_attachments: Vec::new(),
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+            attachment: TextureAttachment::Name(uniform::SHADOW_MAP.to_string()),
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+        sample_count: 1,
+    });
+    shadow_pass.add_camera(SHADOW_CAMERA);
+    graph.add_node(node::SHADOW_PASS, shadow_pass);
+
+    graph
+        .add_node_edge(node::SHADOW_CAMERA, node::SHADOW_PASS)
+        .unwrap();
+    graph
+        .add_node_edge(node::SHADOW, node::SHADOW_PASS)
+        .unwrap();
+    graph
+        .add_node_edge(node::TRANSFORM, node::SHADOW_PASS)
+        .unwrap();
+    graph
+        .add_node_edge(node::Y_LEVEL, node::SHADOW_PASS)
+        .unwrap();
+    graph
+        .add_node_edge(node::SHADOW, base::node::MAIN_PASS)
+        .unwrap();
+    graph
+        .add_node_edge(node::SHADOW_PASS, base::node::MAIN_PASS)
+        .unwrap();
 }
@@ -0,0 +1,189 @@
+use crate::shadow::{ShadowSettings, SHADOW_CAMERA, SHADOW_MAP_SIZE};
+use bevy::{
+    core::{AsBytes, Byteable},
+    ecs::{Commands, IntoSystem, Local, Query, Res, ResMut, Resources, System, World},
+    prelude::GlobalTransform,
+    render::{
+        camera::{ActiveCameras, Camera},
+        render_graph::{CommandQueue, Node, ResourceSlots, SystemNode},
+        renderer::{
+            BufferId, BufferInfo, BufferUsage, RenderContext, RenderResourceBinding,
+            RenderResourceBindings, RenderResourceContext,
+        },
+        texture::{
+            AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureUsage,
+        },
+    },
+};
+
+use super::uniform;
+
+/// A Render Graph [Node] which creates the shadow map, and writes the primary light's view of
+/// the world to a GPU buffer so that the forward pipeline can look up shadows in it.
+#[derive(Debug)]
+pub struct ShadowNode {
+    command_queue: CommandQueue,
+}
+
+impl ShadowNode {
+    pub fn new() -> Self {
+        ShadowNode {
+            command_queue: Default::default(),
+        }
+    }
+}
+
+impl Node for ShadowNode {
+    fn update(
+        &mut self,
+        _world: &World,
+        _resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        _input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        self.command_queue.execute(render_context);
+    }
+}
+
+impl SystemNode for ShadowNode {
+    fn get_system(&self, commands: &mut Commands) -> Box<dyn System<In = (), Out = ()>> {
+        let system = shadow_node_system.system();
+        commands.insert_local_resource(
+            system.id(),
+            ShadowNodeState {
+                command_queue: self.command_queue.clone(),
+                shadow_light_buffer: None,
+                staging_buffer: None,
+            },
+        );
+        Box::new(system)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ShadowLightRaw {
+    pub view_proj: [[f32; 4]; 4],
+    /// The depth bias, the size of a texel of the shadow map in texture coordinates, and
+    /// whether shadows are enabled.
+    pub params: [f32; 4],
+}
+
+unsafe impl Byteable for ShadowLightRaw {}
+
+#[derive(Debug, Default)]
+pub struct ShadowNodeState {
+    command_queue: CommandQueue,
+    shadow_light_buffer: Option<BufferId>,
+    staging_buffer: Option<BufferId>,
+}
+
+pub(crate) fn shadow_node_system(
+    mut state: Local<ShadowNodeState>,
+    render_resource_context: Res<Box<dyn RenderResourceContext>>,
+    // TODO: this write on RenderResourceBindings will prevent this system from running in parallel with other systems that do the same
+    mut render_resource_bindings: ResMut<RenderResourceBindings>,
+    settings: Res<ShadowSettings>,
+    active_cameras: Res<ActiveCameras>,
+    query: Query<(&Camera, &GlobalTransform)>,
+) {
+    let state = &mut state;
+    let render_resource_context = &**render_resource_context;
+
+    let view = active_cameras
+        .get(SHADOW_CAMERA)
+        .and_then(|entity| query.get(entity).ok())
+        .map(|(camera, transform)| camera.projection_matrix * transform.compute_matrix().inverse());
+    let shadow_light = ShadowLightRaw {
+        view_proj: view.unwrap_or_default().to_cols_array_2d(),
+        params: [
+            settings.bias,
+            1. / SHADOW_MAP_SIZE as f32,
+            if settings.enabled && view.is_some() {
+                1.
+            } else {
+                0.
+            },
+            0.,
+        ],
+    };
+    let size = std::mem::size_of::<ShadowLightRaw>();
+
+    let staging_buffer = if let Some(staging_buffer) = state.staging_buffer {
+        render_resource_context.map_buffer(staging_buffer);
+        staging_buffer
+    } else {
+        let shadow_map = render_resource_context.create_texture(TextureDescriptor {
+            size: Extent3d::new(SHADOW_MAP_SIZE, SHADOW_MAP_SIZE, 1),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+        });
+        render_resource_bindings.set(
+            uniform::SHADOW_MAP,
+            RenderResourceBinding::Texture(shadow_map),
+        );
+        // The shader compares the depths itself, to soften the edges of the shadows, so the
+        // sampler mustn't filter or compare them.
+        let sampler = render_resource_context.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            compare_function: None,
+            ..Default::default()
+        });
+        render_resource_bindings.set(
+            uniform::SHADOW_MAP_SAMPLER,
+            RenderResourceBinding::Sampler(sampler),
+        );
+
+        let buffer = render_resource_context.create_buffer(BufferInfo {
+            size,
+            buffer_usage: BufferUsage::COPY_DST | BufferUsage::UNIFORM,
+            ..Default::default()
+        });
+        render_resource_bindings.set(
+            uniform::SHADOW_LIGHT,
+            RenderResourceBinding::Buffer {
+                buffer,
+                range: 0..size as u64,
+                dynamic_index: None,
+            },
+        );
+        state.shadow_light_buffer = Some(buffer);
+
+        let staging_buffer = render_resource_context.create_buffer(BufferInfo {
+            size,
+            buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
+            mapped_at_creation: true,
+        });
+
+        state.staging_buffer = Some(staging_buffer);
+        staging_buffer
+    };
+
+    render_resource_context.write_mapped_buffer(
+        staging_buffer,
+        0..size as u64,
+        &mut |data, _renderer| {
+            data[0..size].copy_from_slice(shadow_light.as_bytes());
+        },
+    );
+    render_resource_context.unmap_buffer(staging_buffer);
+
+    let shadow_light_buffer = state.shadow_light_buffer.unwrap();
+    state.command_queue.copy_buffer_to_buffer(
+        staging_buffer,
+        0,
+        shadow_light_buffer,
+        0,
+        size as u64,
+    );
+}
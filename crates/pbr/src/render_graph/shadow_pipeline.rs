@@ -0,0 +1,48 @@
+use bevy::{
+    prelude::{AssetServer, HandleUntyped},
+    reflect::TypeUuid,
+    render::{
+        pipeline::{
+            CompareFunction, CullMode, DepthStencilStateDescriptor, FrontFace, PipelineDescriptor,
+            RasterizationStateDescriptor, StencilStateDescriptor, StencilStateFaceDescriptor,
+        },
+        shader::ShaderStages,
+        texture::TextureFormat,
+    },
+};
+
+pub const SHADOW_PIPELINE_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 7327170591463394870);
+
+/// A depth-only pipeline which renders shadow casters into the shadow map.
+pub(crate) fn build_shadow_pipeline(asset_server: &mut AssetServer) -> PipelineDescriptor {
+    PipelineDescriptor {
+        rasterization_state: Some(RasterizationStateDescriptor {
+            front_face: FrontFace::Ccw,
+            // Chunk meshes are open at their edges, so both sides of each face have to cast.
+            cull_mode: CullMode::None,
+            // Push the depths away from the light a little more on steep slopes, where a texel
+            // of the shadow map covers a long stretch of the surface.
+            depth_bias: 2,
+            depth_bias_slope_scale: 2.0,
+            depth_bias_clamp: 0.0,
+            clamp_depth: false,
+        }),
+        depth_stencil_state: Some(DepthStencilStateDescriptor {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilStateDescriptor {
+                front: StencilStateFaceDescriptor::IGNORE,
+                back: StencilStateFaceDescriptor::IGNORE,
+                read_mask: 0,
+                write_mask: 0,
+            },
+        }),
+        color_states: Vec::new(),
+        ..PipelineDescriptor::new(ShaderStages {
+            vertex: asset_server.load("shaders/shadow.vert"),
+            fragment: Some(asset_server.load("shaders/shadow.frag")),
+        })
+    }
+}
@@ -0,0 +1,196 @@
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::{
+    ecs::{Added, Commands, Entity, Query, QuerySet, Res, With},
+    math::{Mat4, Vec3},
+    prelude::{Draw, GlobalTransform, Handle, Mesh, RenderPipelines, Transform, Visible},
+    render::{
+        camera::{ActiveCameras, Camera, VisibleEntities},
+        pipeline::RenderPipeline,
+        render_graph::base::camera::CAMERA3D,
+    },
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{light::DirectionalLight, render_graph::SHADOW_PIPELINE_HANDLE};
+
+/// The name of the camera which renders the shadow map from the primary light.
+pub const SHADOW_CAMERA: &str = "ShadowCamera";
+
+/// The width and height of the shadow map, in texels.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// How far the shadow camera sits from the point it's looking at, which has to be far enough
+/// that nothing between the light and the view casts shadows from behind the camera.
+#[cfg(not(target_arch = "wasm32"))]
+const SHADOW_DISTANCE: f32 = 256.;
+
+/// Marks a mesh which casts shadows from the primary light.
+///
+/// Shadows are only rendered on native builds; on WASM this marker does nothing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShadowCaster;
+
+/// A copy of a [`ShadowCaster`]'s mesh which is drawn into the shadow map, since each entity
+/// can only be drawn with one set of pipelines. It follows the caster around, and is despawned
+/// along with it.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowProxy {
+    caster: Entity,
+}
+
+/// How the primary light's shadows are rendered.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    /// Whether the primary light casts shadows at all.
+    pub enabled: bool,
+    /// How far the shadow map reaches from the camera in each direction, in world units. The
+    /// further it reaches, the blurrier the shadows are.
+    pub extent: f32,
+    /// How much further from the light a surface must be than the nearest one in the shadow
+    /// map to be in shadow, so that surfaces don't shadow themselves.
+    pub bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            enabled: cfg!(not(target_arch = "wasm32")),
+            extent: 96.,
+            bias: 0.002,
+        }
+    }
+}
+
+/// Spawns the camera from which the shadow map is rendered.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn spawn_shadow_camera(commands: &mut Commands) {
+    commands.spawn((
+        Camera {
+            name: Some(SHADOW_CAMERA.to_string()),
+            ..Default::default()
+        },
+        VisibleEntities::default(),
+        Transform::default(),
+        GlobalTransform::default(),
+    ));
+}
+
+/// Points the shadow camera along the primary light at the point the player's camera is, so
+/// that the shadow map covers the area around them. The primary light is the first directional
+/// light, which is also the first light in the lights uniform. Point lights don't cast shadows.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn update_shadow_camera(
+    settings: Res<ShadowSettings>,
+    active_cameras: Res<ActiveCameras>,
+    directional_query: Query<&GlobalTransform, With<DirectionalLight>>,
+    mut camera_query: Query<(&mut Camera, &mut Transform)>,
+    transform_query: Query<&GlobalTransform, With<Camera>>,
+) {
+    let (shadow_camera, focus) = match (
+        active_cameras.get(SHADOW_CAMERA),
+        active_cameras.get(CAMERA3D),
+    ) {
+        (Some(shadow_camera), Some(camera)) => match transform_query.get(camera) {
+            Ok(transform) => (shadow_camera, transform.translation),
+            Err(_) => return,
+        },
+        _ => return,
+    };
+    let direction = match directional_query.iter().next() {
+        Some(transform) => DirectionalLight::direction(transform),
+        None => return,
+    };
+    let (mut camera, mut transform) = match camera_query.get_mut(shadow_camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    let up = if direction.y.abs() > 0.99 {
+        Vec3::unit_z()
+    } else {
+        Vec3::unit_y()
+    };
+    // Move the focus in whole shadow map texels across the light's view, so that the shadows
+    // don't shimmer as the camera moves.
+    let rotation = Transform::default().looking_at(-direction, up).rotation;
+    let texel = 2. * settings.extent / SHADOW_MAP_SIZE as f32;
+    let local = rotation.conjugate() * focus;
+    let snapped = Vec3::new(
+        (local.x / texel).round() * texel,
+        (local.y / texel).round() * texel,
+        local.z,
+    );
+    let focus = rotation * snapped;
+
+    *transform =
+        Transform::from_translation(focus + direction * SHADOW_DISTANCE).looking_at(focus, up);
+    camera.projection_matrix = Mat4::orthographic_rh(
+        -settings.extent,
+        settings.extent,
+        -settings.extent,
+        settings.extent,
+        0.,
+        2. * SHADOW_DISTANCE,
+    );
+}
+
+/// Spawns a shadow proxy for each new shadow caster.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn spawn_shadow_proxies(
+    commands: &mut Commands,
+    query: Query<(Entity, &Handle<Mesh>), Added<ShadowCaster>>,
+) {
+    for (caster, mesh) in query.iter() {
+        commands.spawn((
+            ShadowProxy { caster },
+            mesh.clone(),
+            Draw::default(),
+            Visible::default(),
+            RenderPipelines::from_pipelines(vec![RenderPipeline::new(
+                SHADOW_PIPELINE_HANDLE.typed(),
+            )]),
+            Transform::default(),
+            GlobalTransform::default(),
+        ));
+    }
+}
+
+/// Moves, shows and hides each shadow proxy along with its caster, and despawns the proxies
+/// of casters which have been despawned.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn update_shadow_proxies(
+    commands: &mut Commands,
+    settings: Res<ShadowSettings>,
+    mut queries: QuerySet<(
+        Query<(Entity, &ShadowProxy)>,
+        Query<(&GlobalTransform, &Visible), With<ShadowCaster>>,
+        Query<(&mut Transform, &mut Visible), With<ShadowProxy>>,
+    )>,
+) {
+    let proxies = queries
+        .q0()
+        .iter()
+        .map(|(proxy, ShadowProxy { caster })| (proxy, *caster))
+        .collect::<Vec<_>>();
+    for (proxy, caster) in proxies {
+        let (caster_transform, is_visible) = match queries.q1().get(caster) {
+            Ok((transform, visible)) => (*transform, visible.is_visible),
+            Err(_) => {
+                commands.despawn(proxy);
+                continue;
+            }
+        };
+        if let Ok((mut transform, mut visible)) = queries.q2_mut().get_mut(proxy) {
+            *transform = Transform {
+                translation: caster_transform.translation,
+                rotation: caster_transform.rotation,
+                scale: caster_transform.scale,
+            };
+            let is_visible = is_visible && settings.enabled;
+            // Avoid flagging the component as changed if it's already right.
+            if visible.is_visible != is_visible {
+                visible.is_visible = is_visible;
+            }
+        }
+    }
+}
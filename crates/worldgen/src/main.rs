@@ -0,0 +1,550 @@
+//! Generates a world offline and writes out images and statistics describing it, so that
+//! world generation can be tuned without launching the game.
+//!
+//! ```text
+//! cargo run --release -p colonize_worldgen -- --seed 42 --out worldgen-out
+//! ```
+//!
+//! Settings start out as those of a preset from `assets/data/worldgen.ron`, the same file
+//! the game reads, and can then be overridden one at a time.
+//!
+//! This writes the following to the output directory:
+//!
+//! - `heightmap.png`: the height of the surface of each column, from black (lowest) to
+//!   white (highest).
+//! - `slices/y_<level>.png`: the material of each voxel at the given Y-level.
+//! - `stats.txt`: the number of voxels of each type, and the range of surface heights.
+//!
+//! With `--obj`, it also meshes the terrain the way the game does and writes it to an OBJ file,
+//! with its materials in an MTL file next to it, for viewing in Blender and other 3D tools.
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+
+use building_blocks::{
+    core::{Extent3i, Point3i, PointN},
+    prelude::copy_extent,
+    storage::{Array3, ForEach, Get},
+};
+use colonize_common::{Voxel, VoxelType, NUM_VOXEL_TYPES};
+use colonize_core::{
+    generate_precise_map_with_masks, HeightMapError, MaterialMaskConfig, MeshBuffers,
+    MeshGenerationMethod, ObjWriter, WorldGenConfig, WorldGenPresets,
+};
+use colonize_noise::Noise2d;
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use noise::{RidgedMulti, Seedable};
+
+/// The size of the chunks the terrain is meshed in when exporting it.
+const EXPORT_CHUNK_SIZE: i32 = 64;
+
+const USAGE: &str = "Usage: worldgen [OPTIONS]
+
+Options:
+    --presets <FILE>     Preset file to read [default: assets/data/worldgen.ron]
+    --assets <DIR>       Asset root, which the paths in presets are relative to
+                         [default: assets]
+    --preset <NAME>      Preset to start from [default: the file's default preset]
+    --seed <N>           Seed for the noise functions [default: the preset's, or 0]
+    --frequency <F>      Elevation noise frequency
+    --lacunarity <F>     Elevation noise lacunarity
+    --persistence <F>    Elevation noise persistence
+    --octaves <N>        Elevation noise octaves
+    --sea-level <N>      Y-level below which open air is flooded
+    --size <N>           Width and depth of the region, in voxels
+    --height <N>         Height of the region, in voxels
+    --height-map <FILE>  Grayscale PNG or 16-bit RAW image to use instead of elevation noise.
+                         Paths given here are relative to the working directory
+    --mask <TYPE=FILE>   Image whose bright parts override the surface material, e.g.
+                         `--mask stone=cliffs.png`. May be given more than once
+    --slice-step <N>     Distance between Y-level slices [default: 8]
+    --out <DIR>          Directory to write the output to [default: worldgen-out]
+    --obj <FILE>         Also mesh the terrain and write it to an OBJ file, e.g. `--obj world.obj`
+    --obj-region <BOX>   Only export the voxels from one corner of a box to the other, given as
+                         `X,Y,Z,X,Y,Z`, e.g. `--obj-region -32,0,-32,31,63,31`
+    --obj-slice <Y>      Only export a slice through the terrain at a Y-level, as the game
+                         shows it
+    --help               Print this message";
+
+/// A single setting given on the command line, which overrides the preset's.
+enum Override {
+    Seed(u32),
+    Frequency(f64),
+    Lacunarity(f64),
+    Persistence(f64),
+    Octaves(usize),
+    SeaLevel(i32),
+    Size(i32),
+    Height(i32),
+    HeightMap(PathBuf),
+    Mask(MaterialMaskConfig),
+}
+
+impl Override {
+    fn apply(&self, config: &mut WorldGenConfig) {
+        match *self {
+            Override::Seed(seed) => config.seed = Some(seed),
+            Override::Frequency(frequency) => config.elevation.frequency = frequency,
+            Override::Lacunarity(lacunarity) => config.elevation.lacunarity = lacunarity,
+            Override::Persistence(persistence) => config.elevation.persistence = persistence,
+            Override::Octaves(octaves) => config.elevation.octaves = octaves,
+            Override::SeaLevel(sea_level) => config.sea_level = sea_level,
+            Override::Size(size) => config.region_size = size,
+            Override::Height(height) => config.region_height = height,
+            Override::HeightMap(ref path) => config.height_map = Some(path.clone()),
+            Override::Mask(ref mask) => config.material_masks.push(mask.clone()),
+        }
+    }
+}
+
+struct Options {
+    /// The name of the preset the config started out as.
+    preset: String,
+    config: WorldGenConfig,
+    /// The directory the height map and material masks in the config are relative to.
+    asset_root: PathBuf,
+    /// The seed actually used. This is the config's seed, or 0 if it doesn't have one, so
+    /// that runs are reproducible.
+    seed: u32,
+    slice_step: i32,
+    out: PathBuf,
+    export: Option<Export>,
+}
+
+/// What to export to an OBJ file.
+struct Export {
+    path: PathBuf,
+    region: Option<Extent3i>,
+    slice: Option<i32>,
+}
+
+impl Options {
+    fn from_args(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        fn value<T: std::str::FromStr>(
+            flag: &str,
+            args: &mut impl Iterator<Item = String>,
+        ) -> Result<T, String> {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} requires a value", flag))?;
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {}: {:?}", flag, value))
+        }
+
+        let mut presets_path = PathBuf::from("assets/data/worldgen.ron");
+        let mut asset_root = PathBuf::from("assets");
+        let mut preset = None;
+        let mut overrides = Vec::new();
+        let mut slice_step = 8;
+        let mut out = PathBuf::from("worldgen-out");
+        let mut obj: Option<PathBuf> = None;
+        let mut obj_region = None;
+        let mut obj_slice = None;
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--presets" => presets_path = value(&arg, &mut args)?,
+                "--assets" => asset_root = value(&arg, &mut args)?,
+                "--preset" => preset = Some(value::<String>(&arg, &mut args)?),
+                "--seed" => overrides.push(Override::Seed(value(&arg, &mut args)?)),
+                "--frequency" => overrides.push(Override::Frequency(value(&arg, &mut args)?)),
+                "--lacunarity" => overrides.push(Override::Lacunarity(value(&arg, &mut args)?)),
+                "--persistence" => overrides.push(Override::Persistence(value(&arg, &mut args)?)),
+                "--octaves" => overrides.push(Override::Octaves(value(&arg, &mut args)?)),
+                "--sea-level" => overrides.push(Override::SeaLevel(value(&arg, &mut args)?)),
+                "--size" => overrides.push(Override::Size(value(&arg, &mut args)?)),
+                "--height" => overrides.push(Override::Height(value(&arg, &mut args)?)),
+                "--height-map" => {
+                    let path = from_working_dir(value(&arg, &mut args)?);
+                    overrides.push(Override::HeightMap(path));
+                }
+                "--mask" => {
+                    let mask = parse_mask(&value::<String>(&arg, &mut args)?)?;
+                    overrides.push(Override::Mask(mask));
+                }
+                "--slice-step" => slice_step = value(&arg, &mut args)?,
+                "--out" => out = value(&arg, &mut args)?,
+                "--obj" => obj = Some(value(&arg, &mut args)?),
+                "--obj-region" => {
+                    obj_region = Some(parse_region(&value::<String>(&arg, &mut args)?)?)
+                }
+                "--obj-slice" => obj_slice = Some(value(&arg, &mut args)?),
+                "--help" => return Err(USAGE.to_string()),
+                _ => return Err(format!("unknown argument {:?}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        let data = fs::read_to_string(&presets_path)
+            .map_err(|e| format!("failed to read {}: {}", presets_path.display(), e))?;
+        let presets = WorldGenPresets::from_ron(&data).map_err(|e| e.to_string())?;
+        let preset = preset.unwrap_or_else(|| presets.default.clone());
+        let mut config = presets
+            .get(&preset)
+            .ok_or_else(|| {
+                let names: Vec<_> = presets.names().map(String::as_str).collect();
+                format!(
+                    "no preset named {:?}; expected one of: {}",
+                    preset,
+                    names.join(", ")
+                )
+            })?
+            .clone();
+        for setting in &overrides {
+            setting.apply(&mut config);
+        }
+
+        if config.region_size <= 0 || config.region_height <= 0 || slice_step <= 0 {
+            return Err("--size, --height and --slice-step must be positive".to_string());
+        }
+        let export = match obj {
+            Some(path) => Some(Export {
+                path,
+                region: obj_region,
+                slice: obj_slice,
+            }),
+            None if obj_region.is_some() || obj_slice.is_some() => {
+                return Err("--obj-region and --obj-slice require --obj".to_string())
+            }
+            None => None,
+        };
+        Ok(Self {
+            preset,
+            seed: config.seed.unwrap_or(0),
+            config,
+            asset_root,
+            slice_step,
+            out,
+            export,
+        })
+    }
+}
+
+/// Parses a material mask given as `TYPE=FILE`, where the type is the name of a voxel type.
+fn parse_mask(value: &str) -> Result<MaterialMaskConfig, String> {
+    let mut parts = value.splitn(2, '=');
+    let (name, path) = match (parts.next(), parts.next()) {
+        (Some(name), Some(path)) => (name, path),
+        _ => return Err(format!("invalid mask {:?}: expected TYPE=FILE", value)),
+    };
+    let material = VoxelType::ALL
+        .iter()
+        .find(|t| format!("{:?}", t).eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("unknown voxel type {:?}", name))?;
+    Ok(MaterialMaskConfig {
+        material: *material,
+        path: from_working_dir(PathBuf::from(path)),
+    })
+}
+
+/// Makes a path given on the command line absolute, so that it's still relative to the working
+/// directory rather than to the asset root like the paths in presets.
+fn from_working_dir(path: PathBuf) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path,
+    }
+}
+
+/// Parses a box given as the coordinates of two opposite corners, `X,Y,Z,X,Y,Z`. Both corners
+/// are included in the box.
+fn parse_region(value: &str) -> Result<Extent3i, String> {
+    let coordinates = value
+        .split(',')
+        .map(|c| c.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid region {:?}: expected X,Y,Z,X,Y,Z", value))?;
+    match coordinates.as_slice() {
+        [x1, y1, z1, x2, y2, z2] => Ok(Extent3i::from_min_and_max(
+            PointN([*x1.min(x2), *y1.min(y2), *z1.min(z2)]),
+            PointN([*x1.max(x2), *y1.max(y2), *z1.max(z2)]),
+        )),
+        _ => Err(format!("invalid region {:?}: expected X,Y,Z,X,Y,Z", value)),
+    }
+}
+
+/// The color used for each material. These match the materials the game renders with.
+/// Slices are drawn fully opaque, apart from air.
+fn color(voxel_type: VoxelType) -> Rgba<u8> {
+    if voxel_type == VoxelType::Air {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let [r, g, b, _] = voxel_type.color();
+    let channel = |c: f32| (c * 255.).round() as u8;
+    Rgba([channel(r), channel(g), channel(b), 255])
+}
+
+/// Generates the world the same way the game does, given the same seed.
+fn generate(options: &Options) -> Result<Array3<Voxel>, HeightMapError> {
+    let config = &options.config;
+    let dirt_thickness_noise =
+        Noise2d::new(RidgedMulti::new().set_seed(options.seed.wrapping_add(1)));
+    let strata_noise = colonize_noise::strata_noise(options.seed.wrapping_add(2));
+    let masks = config.load_material_masks(&options.asset_root)?;
+    let voxels = match config.load_height_map(&options.asset_root)? {
+        Some(height_map) => generate_precise_map_with_masks(
+            &height_map,
+            &dirt_thickness_noise,
+            &strata_noise,
+            &masks,
+            config,
+        ),
+        None => {
+            let elevation_noise = colonize_noise::ridged_multi(&config.elevation, options.seed);
+            generate_precise_map_with_masks(
+                &elevation_noise,
+                &dirt_thickness_noise,
+                &strata_noise,
+                &masks,
+                config,
+            )
+        }
+    };
+    Ok(voxels)
+}
+
+/// Finds the Y-level of the topmost non-air voxel in each column, indexed by `[x][z]`
+/// relative to the minimum of the extent.
+fn surface_heights(voxels: &Array3<Voxel>, extent: &Extent3i) -> Vec<Vec<i32>> {
+    let min = extent.minimum;
+    let max = extent.max();
+    (min.x()..=max.x())
+        .map(|x| {
+            (min.z()..=max.z())
+                .map(|z| {
+                    (min.y()..=max.y())
+                        .rev()
+                        .find(|y| {
+                            let p: Point3i = PointN([x, *y, z]);
+                            *voxels.get(&p).voxel_type() != VoxelType::Air
+                        })
+                        .unwrap_or(min.y())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn write_heightmap(heights: &[Vec<i32>], lowest: i32, highest: i32, options: &Options) {
+    let range = (highest - lowest).max(1) as f32;
+    let size = options.config.region_size as u32;
+    let image = GrayImage::from_fn(size, size, |x, z| {
+        let height = heights[x as usize][z as usize];
+        Luma([((height - lowest) as f32 / range * 255.).round() as u8])
+    });
+    let path = options.out.join("heightmap.png");
+    image.save(&path).expect("failed to write height map");
+    println!("Wrote {}", path.display());
+}
+
+/// Writes an image of each Y-level which has anything other than air in it.
+fn write_slices(voxels: &Array3<Voxel>, extent: &Extent3i, options: &Options) {
+    let directory = options.out.join("slices");
+    fs::create_dir_all(&directory).expect("failed to create slice directory");
+
+    let min = extent.minimum;
+    let size = options.config.region_size as u32;
+    let mut written = 0;
+    for y in (min.y()..=extent.max().y()).step_by(options.slice_step as usize) {
+        let mut empty = true;
+        let image = RgbaImage::from_fn(size, size, |x, z| {
+            let p: Point3i = PointN([min.x() + x as i32, y, min.z() + z as i32]);
+            let voxel_type = *voxels.get(&p).voxel_type();
+            if voxel_type != VoxelType::Air {
+                empty = false;
+            }
+            color(voxel_type)
+        });
+        if empty {
+            continue;
+        }
+        image
+            .save(directory.join(format!("y_{}.png", y)))
+            .expect("failed to write slice");
+        written += 1;
+    }
+    println!("Wrote {} slices to {}", written, directory.display());
+}
+
+fn write_stats(
+    voxels: &Array3<Voxel>,
+    extent: &Extent3i,
+    lowest: i32,
+    highest: i32,
+    options: &Options,
+) {
+    let mut counts = [0u64; NUM_VOXEL_TYPES];
+    voxels.for_each(extent, |_p: Point3i, voxel: Voxel| {
+        counts[voxel.voxel_type().index()] += 1;
+    });
+    let total: u64 = counts.iter().sum();
+
+    let mut stats = String::new();
+    let config = &options.config;
+    writeln!(stats, "Preset: {}", options.preset).unwrap();
+    writeln!(stats, "Seed: {}", options.seed).unwrap();
+    if let Some(path) = &config.height_map {
+        writeln!(stats, "Height map: {}", path.display()).unwrap();
+    }
+    for mask in &config.material_masks {
+        writeln!(
+            stats,
+            "Mask: {:?} from {}",
+            mask.material,
+            mask.path.display()
+        )
+        .unwrap();
+    }
+    writeln!(
+        stats,
+        "Noise: frequency {}, lacunarity {}, persistence {}, octaves {}",
+        config.elevation.frequency,
+        config.elevation.lacunarity,
+        config.elevation.persistence,
+        config.elevation.octaves
+    )
+    .unwrap();
+    writeln!(
+        stats,
+        "Elevation: {} to {}",
+        config.min_elevation, config.max_elevation
+    )
+    .unwrap();
+    writeln!(stats, "Sea level: {}", config.sea_level).unwrap();
+    let strata: Vec<_> = config.strata.iter().map(|s| s.name.as_str()).collect();
+    writeln!(stats, "Strata: {}", strata.join(", ")).unwrap();
+    let prefabs: Vec<_> = config
+        .prefabs
+        .iter()
+        .map(|p| format!("{} x{}", p.name, p.count))
+        .collect();
+    writeln!(stats, "Prefabs: {}", prefabs.join(", ")).unwrap();
+    writeln!(stats, "Extent: {:?}", extent).unwrap();
+    writeln!(stats, "Surface height: {} to {}", lowest, highest).unwrap();
+    writeln!(stats, "Voxels:").unwrap();
+    for voxel_type in VoxelType::ALL.iter() {
+        let count = counts[voxel_type.index()];
+        writeln!(
+            stats,
+            "    {:?}: {} ({:.2}%)",
+            voxel_type,
+            count,
+            count as f64 / total as f64 * 100.
+        )
+        .unwrap();
+    }
+
+    print!("{}", stats);
+    let path = options.out.join("stats.txt");
+    fs::write(&path, stats).expect("failed to write statistics");
+    println!("Wrote {}", path.display());
+}
+
+/// Meshes the terrain in chunks, the same way the game does, and writes it to an OBJ file.
+/// Only the voxels in the export region are meshed, so the terrain is cut off cleanly at its
+/// sides.
+fn write_obj(voxels: &Array3<Voxel>, extent: &Extent3i, export: &Export) -> io::Result<()> {
+    let region = export
+        .region
+        .map_or(*extent, |region| region.intersection(extent));
+    let method = MeshGenerationMethod::default();
+    let mtl_path = export.path.with_extension("mtl");
+    let mtl_file_name = mtl_path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let mut writer = ObjWriter::new(BufWriter::new(File::create(&export.path)?), &mtl_file_name)?;
+
+    // Chunks are always whole, even at the sides of the region, since the smooth meshers
+    // expect them to be.
+    let chunk_shape = PointN([EXPORT_CHUNK_SIZE; 3]);
+    let (min, max) = (region.minimum, region.max());
+    let chunks_between =
+        |min: i32, max: i32| min.div_euclid(EXPORT_CHUNK_SIZE)..=max.div_euclid(EXPORT_CHUNK_SIZE);
+    let mut chunks = 0;
+    for x in chunks_between(min.x(), max.x()) {
+        for y in chunks_between(min.y(), max.y()) {
+            for z in chunks_between(min.z(), max.z()) {
+                let chunk_extent = Extent3i::from_min_and_shape(
+                    PointN([
+                        x * EXPORT_CHUNK_SIZE,
+                        y * EXPORT_CHUNK_SIZE,
+                        z * EXPORT_CHUNK_SIZE,
+                    ]),
+                    chunk_shape,
+                );
+                let (padded_extent, extent_to_copy) = match export.slice {
+                    Some(slice)
+                        if slice < chunk_extent.minimum.y() || slice > chunk_extent.max().y() =>
+                    {
+                        continue
+                    }
+                    Some(slice) => {
+                        let padded_layer_extent = method.padded_slice_extent(&chunk_extent, slice);
+                        // Leave the top layer empty.
+                        (
+                            padded_layer_extent,
+                            padded_layer_extent.add_to_shape(PointN([0, -1, 0])),
+                        )
+                    }
+                    None => {
+                        let padded_chunk_extent = method.padded_chunk_extent(&chunk_extent);
+                        (padded_chunk_extent, padded_chunk_extent)
+                    }
+                };
+                let extent_to_copy = extent_to_copy.intersection(&region);
+                let meshes = MeshBuffers::with(|buffers| {
+                    let (padded_array, meshers) = buffers.array_and_meshers(padded_extent);
+                    copy_extent(&extent_to_copy, voxels, padded_array);
+                    method.generate_mesh(padded_array, meshers)
+                });
+                for (material, mesh) in meshes.unwrap_or_default() {
+                    let name = format!("chunk_{}_{}_{}_{:?}", x, y, z, material);
+                    writer.add_mesh(&name, material, &mesh)?;
+                }
+                chunks += 1;
+            }
+        }
+    }
+
+    writer.write_materials(BufWriter::new(File::create(&mtl_path)?), |material| {
+        material.color()
+    })?;
+    println!("Wrote {} chunks to {}", chunks, export.path.display());
+    Ok(())
+}
+
+fn main() {
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    fs::create_dir_all(&options.out).expect("failed to create output directory");
+
+    println!(
+        "Generating world from preset {:?} with seed {}",
+        options.preset, options.seed
+    );
+    let voxels = generate(&options).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let extent = options.config.extent();
+
+    let heights = surface_heights(&voxels, &extent);
+    let lowest = heights.iter().flatten().copied().min().unwrap();
+    let highest = heights.iter().flatten().copied().max().unwrap();
+
+    write_heightmap(&heights, lowest, highest, &options);
+    write_slices(&voxels, &extent, &options);
+    write_stats(&voxels, &extent, lowest, highest, &options);
+    if let Some(export) = &options.export {
+        write_obj(&voxels, &extent, export).unwrap_or_else(|e| {
+            eprintln!("failed to write {}: {}", export.path.display(), e);
+            std::process::exit(1);
+        });
+    }
+}
@@ -0,0 +1,594 @@
+//! Melee combat.
+//!
+//! Anything with a [`Fighter`] component can attack whatever it is targeting. An
+//! attack is resolved in three steps: the attacker makes a skill check to land the
+//! blow, the defender gets a chance to dodge it or, if they're holding a weapon,
+//! block it, and finally a body part is picked to take the hit, with bigger parts
+//! being hit more often. Everything that happens is written to the [`CombatLog`].
+//!
+//! Dwarves only fight when drafted: select a dwarf, press `R` to draft them, then
+//! click on a creature to send them after it.
+use std::collections::VecDeque;
+use std::fmt;
+
+use bevy::{
+    ecs::{Commands, Entity, Query, Res, ResMut, With},
+    input::Input,
+    prelude::{debug, AppBuilder, IntoSystem, KeyCode, MouseButton, Plugin, Transform},
+};
+use bevy_mod_picking::{Group, InteractableMesh};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+
+use crate::creature::Creature;
+use crate::dwarf::{Dwarf, Name, SelectedDwarf};
+use crate::health::{BodyPart, Health, BODY_PARTS};
+use crate::item::{Item, ItemKind};
+use crate::mood::{MentalState, ThoughtKind, Thoughts};
+use crate::navigation::{chase, Navigator};
+use crate::simulation::{SIMULATION, TICK_SECONDS};
+use crate::terrain::TerrainResource;
+
+/// The highest level a skill can be trained to.
+const MAX_SKILL: f32 = 20.;
+/// How much a skill improves each time it is used successfully.
+const SKILL_GAIN: f32 = 0.1;
+/// How much each skill level adds to the chance of succeeding at a check.
+const SKILL_BONUS: f32 = 0.02;
+/// The chance for a completely unskilled attacker to land a blow.
+const BASE_HIT_CHANCE: f32 = 0.6;
+/// The chance for a completely unskilled defender to dodge a blow.
+const BASE_DODGE_CHANCE: f32 = 0.05;
+/// The chance for a completely unskilled defender to block a blow with a weapon.
+const BASE_BLOCK_CHANCE: f32 = 0.1;
+/// Seconds between swings when fighting without a weapon.
+const UNARMED_SWING_TIME: f32 = 1.5;
+/// Damage a dwarf deals with their bare fists.
+const DWARF_UNARMED_DAMAGE: f32 = 4.;
+/// How far a dwarf can reach, measured from their center.
+const DWARF_REACH: f32 = 1.5;
+/// How close a drafted dwarf must be to a weapon lying on the ground to pick it up.
+const PICK_UP_RADIUS: f32 = 1.5;
+/// The number of entries kept in the combat log.
+const MAX_LOG_ENTRIES: usize = 8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Weapon {
+    Axe,
+    Pick,
+    Hammer,
+    Sword,
+}
+
+const WEAPONS: [Weapon; 4] = [Weapon::Axe, Weapon::Pick, Weapon::Hammer, Weapon::Sword];
+
+impl Weapon {
+    pub(crate) fn random(rng: &mut impl Rng) -> Self {
+        *WEAPONS.choose(rng).unwrap()
+    }
+
+    fn damage(&self) -> f32 {
+        match self {
+            Weapon::Axe => 14.,
+            Weapon::Pick => 10.,
+            Weapon::Hammer => 12.,
+            Weapon::Sword => 12.,
+        }
+    }
+
+    /// Seconds between swings.
+    fn swing_time(&self) -> f32 {
+        match self {
+            Weapon::Axe => 1.4,
+            Weapon::Pick => 1.2,
+            Weapon::Hammer => 1.6,
+            Weapon::Sword => 1.,
+        }
+    }
+
+    /// How much easier it is to block a blow with this weapon in hand.
+    fn block_bonus(&self) -> f32 {
+        match self {
+            Weapon::Axe | Weapon::Hammer => 0.05,
+            Weapon::Pick => 0.,
+            Weapon::Sword => 0.15,
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Weapon::Axe => "hacks at",
+            Weapon::Pick => "stabs at",
+            Weapon::Hammer => "bashes",
+            Weapon::Sword => "slashes at",
+        }
+    }
+}
+
+impl fmt::Display for Weapon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Weapon::Axe => "axe",
+            Weapon::Pick => "pick",
+            Weapon::Hammer => "war hammer",
+            Weapon::Sword => "short sword",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Skills {
+    melee: f32,
+    dodging: f32,
+    blocking: f32,
+}
+
+impl Skills {
+    pub(crate) fn new(melee: f32, dodging: f32, blocking: f32) -> Self {
+        Self {
+            melee: melee.min(MAX_SKILL),
+            dodging: dodging.min(MAX_SKILL),
+            blocking: blocking.min(MAX_SKILL),
+        }
+    }
+
+    /// A novice's skill set, with a little bit of experience in each skill.
+    pub(crate) fn random(rng: &mut impl Rng) -> Self {
+        Self::new(
+            rng.gen_range(0., 5.),
+            rng.gen_range(0., 5.),
+            rng.gen_range(0., 5.),
+        )
+    }
+}
+
+impl fmt::Display for Skills {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "melee {:.1}, dodging {:.1}, blocking {:.1}",
+            self.melee, self.dodging, self.blocking
+        )
+    }
+}
+
+fn practice(skill: &mut f32) {
+    *skill = (*skill + SKILL_GAIN).min(MAX_SKILL);
+}
+
+/// Marks a dwarf who has been drafted, and so will fight rather than work.
+pub(crate) struct Drafted;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Fighter {
+    skills: Skills,
+    weapon: Option<Weapon>,
+    /// Damage dealt without a weapon: fists for dwarves, teeth and claws for creatures.
+    unarmed_damage: f32,
+    unarmed_verb: &'static str,
+    /// How far the fighter can reach, measured from their center.
+    reach: f32,
+    /// Whoever the fighter is trying to attack.
+    pub(crate) target: Option<Entity>,
+    /// Seconds until the fighter can swing again.
+    cooldown: f32,
+}
+
+impl Fighter {
+    pub(crate) fn dwarf(skills: Skills, weapon: Option<Weapon>) -> Self {
+        Self {
+            skills,
+            weapon,
+            unarmed_damage: DWARF_UNARMED_DAMAGE,
+            unarmed_verb: "punches",
+            reach: DWARF_REACH,
+            target: None,
+            cooldown: 0.,
+        }
+    }
+
+    pub(crate) fn creature(skills: Skills, damage: f32, reach: f32) -> Self {
+        Self {
+            skills,
+            weapon: None,
+            unarmed_damage: damage,
+            unarmed_verb: "mauls",
+            reach,
+            target: None,
+            cooldown: 0.,
+        }
+    }
+
+    pub(crate) fn weapon(&self) -> Option<Weapon> {
+        self.weapon
+    }
+
+    pub(crate) fn skills(&self) -> &Skills {
+        &self.skills
+    }
+
+    /// Damage dealt by a typical blow. Skilled fighters know where to aim.
+    fn damage(&self) -> f32 {
+        let base = self.weapon.map_or(self.unarmed_damage, |w| w.damage());
+        base * (1. + 0.5 * self.skills.melee / MAX_SKILL)
+    }
+
+    fn swing_time(&self) -> f32 {
+        self.weapon.map_or(UNARMED_SWING_TIME, |w| w.swing_time())
+    }
+
+    fn verb(&self) -> &'static str {
+        self.weapon.map_or(self.unarmed_verb, |w| w.verb())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum AttackOutcome {
+    Missed,
+    Dodged,
+    Blocked(Weapon),
+    Hit(BodyPart, f32),
+}
+
+/// Resolves a single blow. A defender who can't act can neither dodge nor block.
+fn resolve_attack(
+    rng: &mut impl Rng,
+    attacker: &Fighter,
+    defender: &Fighter,
+    defender_can_act: bool,
+) -> AttackOutcome {
+    let hit_chance = BASE_HIT_CHANCE + attacker.skills.melee * SKILL_BONUS;
+    if rng.gen::<f32>() >= hit_chance {
+        return AttackOutcome::Missed;
+    }
+
+    if defender_can_act {
+        let dodge_chance = BASE_DODGE_CHANCE + defender.skills.dodging * SKILL_BONUS;
+        if rng.gen::<f32>() < dodge_chance {
+            return AttackOutcome::Dodged;
+        }
+        if let Some(weapon) = defender.weapon {
+            let block_chance =
+                BASE_BLOCK_CHANCE + defender.skills.blocking * SKILL_BONUS + weapon.block_bonus();
+            if rng.gen::<f32>() < block_chance {
+                return AttackOutcome::Blocked(weapon);
+            }
+        }
+    }
+
+    let part = *BODY_PARTS.choose_weighted(rng, |p| p.size()).unwrap();
+    let damage = attacker.damage() * rng.gen_range(0.75, 1.25);
+    AttackOutcome::Hit(part, damage)
+}
+
+/// The most recent things that happened in combat, newest last.
+#[derive(Default)]
+pub(crate) struct CombatLog {
+    entries: VecDeque<String>,
+}
+
+impl CombatLog {
+    fn push(&mut self, entry: String) {
+        debug!("{}", entry);
+        if self.entries.len() == MAX_LOG_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter()
+    }
+}
+
+/// Drafts the selected dwarf, or releases them if they're already drafted.
+fn toggle_draft(
+    commands: &mut Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    selected_dwarf: Res<SelectedDwarf>,
+    mut dwarf_query: Query<(&Name, Option<&Drafted>, &mut Fighter, &mut Navigator), With<Dwarf>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::R) {
+        return;
+    }
+    let entity = match selected_dwarf.dwarf {
+        Some(entity) => entity,
+        None => return,
+    };
+
+    if let Ok((name, drafted, mut fighter, mut navigator)) = dwarf_query.get_mut(entity) {
+        // Either way, the dwarf drops whatever they were doing.
+        navigator.clear();
+        if drafted.is_some() {
+            debug!("{} has been released from the draft", name);
+            fighter.target = None;
+            commands.remove_one::<Drafted>(entity);
+        } else {
+            debug!("{} has been drafted", name);
+            commands.insert_one(entity, Drafted);
+        }
+    }
+}
+
+/// Points the selected dwarf at whichever creature is clicked on, if they're drafted.
+fn select_target(
+    event_query: Query<(&InteractableMesh, Entity)>,
+    selected_dwarf: Res<SelectedDwarf>,
+    mut dwarf_query: Query<(&Name, &mut Fighter), With<Drafted>>,
+    creature_query: Query<&Name, With<Creature>>,
+) {
+    let dwarf = match selected_dwarf.dwarf {
+        Some(dwarf) => dwarf,
+        None => return,
+    };
+
+    for (interactable, entity) in &mut event_query.iter() {
+        // There are no events without the picking plugins, as when running headless.
+        let mouse_down_event = interactable.mouse_down_event(&Group::default(), MouseButton::Left);
+        if !matches!(mouse_down_event, Ok(Some(_))) {
+            continue;
+        }
+
+        if let Ok(target_name) = creature_query.get(entity) {
+            if let Ok((name, mut fighter)) = dwarf_query.get_mut(dwarf) {
+                debug!("{} has been ordered to attack the {}", name, target_name);
+                fighter.target = Some(entity);
+            }
+        }
+    }
+}
+
+/// Walks drafted dwarves over to whatever they've been ordered to attack.
+fn pursue_targets(
+    terrain_res: Res<TerrainResource>,
+    mut dwarf_query: Query<
+        (
+            &mut Fighter,
+            &Transform,
+            &Health,
+            &MentalState,
+            &mut Navigator,
+        ),
+        With<Drafted>,
+    >,
+    target_query: Query<&Transform>,
+) {
+    for (mut fighter, transform, health, mental_state, mut navigator) in dwarf_query.iter_mut() {
+        // A dwarf in the middle of a mental break doesn't follow orders.
+        if !health.can_act() || mental_state.is_breaking() {
+            continue;
+        }
+        let target = match fighter.target {
+            Some(target) => target,
+            None => continue,
+        };
+        let target_position = match target_query.get(target) {
+            Ok(target_transform) => target_transform.translation,
+            Err(_) => {
+                // The target is gone, most likely because it died.
+                fighter.target = None;
+                navigator.clear();
+                continue;
+            }
+        };
+
+        if transform.translation.distance(target_position) <= fighter.reach {
+            navigator.clear();
+        } else {
+            chase(
+                &terrain_res,
+                &mut navigator,
+                transform.translation,
+                target_position,
+            );
+        }
+    }
+}
+
+/// Lets unarmed drafted dwarves arm themselves with any weapon they walk over.
+fn pick_up_weapons(
+    commands: &mut Commands,
+    mut dwarf_query: Query<(&Name, &Transform, &mut Fighter), With<Drafted>>,
+    item_query: Query<(Entity, &Item, &Transform)>,
+) {
+    let mut taken = Vec::new();
+    for (name, transform, mut fighter) in dwarf_query.iter_mut() {
+        if fighter.weapon.is_some() {
+            continue;
+        }
+
+        let nearby_weapon = item_query
+            .iter()
+            .find_map(|(entity, item, item_transform)| match item.kind {
+                ItemKind::Weapon(weapon)
+                    if !taken.contains(&entity)
+                        && item_transform.translation.distance(transform.translation)
+                            <= PICK_UP_RADIUS =>
+                {
+                    Some((entity, weapon))
+                }
+                _ => None,
+            });
+        if let Some((entity, weapon)) = nearby_weapon {
+            debug!("{} picks up the {}", name, weapon);
+            fighter.weapon = Some(weapon);
+            taken.push(entity);
+            commands.despawn(entity);
+        }
+    }
+}
+
+/// Has every fighter within reach of their target take a swing at it.
+fn melee_attacks(
+    mut combat_log: ResMut<CombatLog>,
+    mut query: Query<(
+        Entity,
+        &Name,
+        &Transform,
+        &mut Fighter,
+        &mut Health,
+        Option<&mut Thoughts>,
+    )>,
+) {
+    let mut rng = thread_rng();
+    let mut ready = Vec::new();
+    for (entity, _name, _transform, mut fighter, health, _thoughts) in query.iter_mut() {
+        fighter.cooldown = (fighter.cooldown - TICK_SECONDS).max(0.);
+        if let Some(target) = fighter.target {
+            if fighter.cooldown <= 0. && health.can_act() {
+                ready.push((entity, target));
+            }
+        }
+    }
+
+    for (attacker_entity, target_entity) in ready {
+        // Both sides of the fight live in the same query, so take a copy of the attacker
+        // while the defender is borrowed.
+        let (attacker_name, attacker_position, attacker) = {
+            let (_, name, transform, fighter, _, _) = query.get_mut(attacker_entity).unwrap();
+            (name.to_string(), transform.translation, fighter.clone())
+        };
+
+        let outcome = match query.get_mut(target_entity) {
+            Ok((_, name, transform, mut fighter, mut health, thoughts)) if !health.is_dead() => {
+                if transform.translation.distance(attacker_position) > attacker.reach {
+                    continue;
+                }
+
+                let outcome = resolve_attack(&mut rng, &attacker, &fighter, health.can_act());
+                let entry = match outcome {
+                    AttackOutcome::Missed => {
+                        format!("{} swings at {} and misses", attacker_name, name)
+                    }
+                    AttackOutcome::Dodged => {
+                        practice(&mut fighter.skills.dodging);
+                        format!("{} dodges {}'s attack", name, attacker_name)
+                    }
+                    AttackOutcome::Blocked(weapon) => {
+                        practice(&mut fighter.skills.blocking);
+                        format!(
+                            "{} blocks {}'s attack with their {}",
+                            name, attacker_name, weapon
+                        )
+                    }
+                    AttackOutcome::Hit(part, damage) => {
+                        health.wound(part, damage);
+                        if let Some(mut thoughts) = thoughts {
+                            thoughts.add(ThoughtKind::WasInjured);
+                        }
+                        format!("{} {} {}'s {}", attacker_name, attacker.verb(), name, part)
+                    }
+                };
+                combat_log.push(entry);
+                Some(outcome)
+            }
+            // The target is dead or gone, so there's nothing left to fight.
+            _ => None,
+        };
+
+        let (_, _, _, mut fighter, _, _) = query.get_mut(attacker_entity).unwrap();
+        match outcome {
+            Some(outcome) => {
+                fighter.cooldown = fighter.swing_time();
+                if let AttackOutcome::Hit(..) = outcome {
+                    practice(&mut fighter.skills.melee);
+                }
+            }
+            None => fighter.target = None,
+        }
+    }
+}
+
+pub(crate) struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(CombatLog::default())
+            .add_system(toggle_draft.system())
+            .add_system(select_target.system())
+            .add_system_to_stage(SIMULATION, pursue_targets.system())
+            .add_system_to_stage(SIMULATION, pick_up_weapons.system())
+            .add_system_to_stage(SIMULATION, melee_attacks.system());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    const TRIALS: usize = 1000;
+
+    fn outcomes(
+        attacker: &Fighter,
+        defender: &Fighter,
+        defender_can_act: bool,
+    ) -> Vec<AttackOutcome> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..TRIALS)
+            .map(|_| resolve_attack(&mut rng, attacker, defender, defender_can_act))
+            .collect()
+    }
+
+    #[test]
+    fn masters_never_miss() {
+        let master = Fighter::dwarf(Skills::new(MAX_SKILL, 0., 0.), Some(Weapon::Axe));
+        let novice = Fighter::dwarf(Skills::new(0., 0., 0.), None);
+        let damage = master.damage();
+        for outcome in outcomes(&master, &novice, false) {
+            match outcome {
+                AttackOutcome::Hit(_, d) => assert!(d >= 0.75 * damage && d <= 1.25 * damage),
+                other => panic!("expected a hit, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn novices_miss_sometimes() {
+        let novice = Fighter::dwarf(Skills::new(0., 0., 0.), None);
+        let misses = outcomes(&novice, &novice, false)
+            .into_iter()
+            .filter(|o| *o == AttackOutcome::Missed)
+            .count() as f32
+            / TRIALS as f32;
+        assert!((misses - (1. - BASE_HIT_CHANCE)).abs() < 0.05);
+    }
+
+    #[test]
+    fn only_armed_defenders_block() {
+        let attacker = Fighter::dwarf(Skills::new(MAX_SKILL, 0., 0.), None);
+        let armed = Fighter::dwarf(Skills::new(0., MAX_SKILL, MAX_SKILL), Some(Weapon::Sword));
+        let unarmed = Fighter::dwarf(Skills::new(0., MAX_SKILL, MAX_SKILL), None);
+
+        let armed_outcomes = outcomes(&attacker, &armed, true);
+        assert!(armed_outcomes.contains(&AttackOutcome::Blocked(Weapon::Sword)));
+        assert!(armed_outcomes.contains(&AttackOutcome::Dodged));
+
+        let unarmed_outcomes = outcomes(&attacker, &unarmed, true);
+        assert!(unarmed_outcomes.contains(&AttackOutcome::Dodged));
+        assert!(!unarmed_outcomes
+            .iter()
+            .any(|o| matches!(o, AttackOutcome::Blocked(_))));
+    }
+
+    #[test]
+    fn helpless_defenders_neither_dodge_nor_block() {
+        let attacker = Fighter::dwarf(Skills::new(MAX_SKILL, 0., 0.), None);
+        let defender = Fighter::dwarf(Skills::new(0., MAX_SKILL, MAX_SKILL), Some(Weapon::Sword));
+        assert!(outcomes(&attacker, &defender, false)
+            .iter()
+            .all(|o| matches!(o, AttackOutcome::Hit(..))));
+    }
+
+    #[test]
+    fn every_body_part_gets_hit() {
+        let attacker = Fighter::dwarf(Skills::new(MAX_SKILL, 0., 0.), None);
+        let outcomes = outcomes(&attacker, &attacker, false);
+        for part in BODY_PARTS.iter() {
+            assert!(outcomes
+                .iter()
+                .any(|o| matches!(o, AttackOutcome::Hit(p, _) if p == part)));
+        }
+    }
+}
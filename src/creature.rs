@@ -0,0 +1,312 @@
+//! Wildlife and hostile creatures.
+//!
+//! Species are defined in `assets/data/species.ron`. Each species spawns in packs
+//! in the biomes it is suited to, and wanders around using the same navigation as
+//! dwarves. Meat-eating, aggressive species hunt down any dwarf they spot, while
+//! timid ones run away.
+use bevy::{
+    app::{stage, EventReader, Events},
+    ecs::{Commands, Entity, Local, Query, Res, ResMut, With},
+    math::Vec3,
+    pbr::PbrBundle,
+    prelude::{
+        debug, error, shape, trace, AppBuilder, Assets, Color, IntoSystem, Mesh, Plugin,
+        StandardMaterial, Transform,
+    },
+};
+use bevy_mod_picking::{HighlightablePickMesh, InteractableMesh, PickableMesh};
+use bevy_rapier3d::rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder};
+use building_blocks::core::PointN;
+use colonize_pbr::ShadowCaster;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+
+use crate::combat::{Fighter, Skills};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::data::read_asset;
+use crate::dwarf::{Dwarf, Name};
+use crate::health::Health;
+use crate::navigation::{chase, find_path, point_from_translation, Navigator};
+use crate::simulation::{SIMULATION, TICK_SECONDS};
+use crate::terrain::{Biome, TerrainGenerated, TerrainResource};
+
+/// The species data file, relative to the asset root.
+const SPECIES_PATH: &str = "data/species.ron";
+/// Creatures spawn within this distance of the origin.
+const SPAWN_RADIUS: f64 = 100.;
+/// The number of random locations to try when looking for a suitable spawn point for a pack.
+const SPAWN_ATTEMPTS: usize = 20;
+/// How far away a creature can spot a dwarf.
+const PERCEPTION_RADIUS: f32 = 20.;
+/// How far away from its current position a wandering creature picks its next destination.
+const WANDER_RADIUS: i32 = 8;
+/// Chance per second that an idle creature starts wandering somewhere.
+const WANDER_CHANCE_PER_SECOND: f32 = 0.2;
+/// How far a fleeing creature tries to run.
+const FLEE_DISTANCE: f32 = 16.;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub(crate) enum Diet {
+    Herbivore,
+    Carnivore,
+    Omnivore,
+}
+
+/// A kind of creature, as defined in the species data file.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Species {
+    name: String,
+    size: f32,
+    speed: f32,
+    diet: Diet,
+    aggression: f32,
+    skill: f32,
+    biomes: Vec<Biome>,
+    color: (f32, f32, f32),
+    groups: u32,
+    group_size: u32,
+}
+
+impl Species {
+    /// Whether members of this species attack any dwarf they spot.
+    fn hunts(&self) -> bool {
+        self.diet != Diet::Herbivore && self.aggression >= 0.5
+    }
+
+    /// Whether members of this species run away from any dwarf they spot.
+    fn flees(&self) -> bool {
+        self.aggression < 0.3
+    }
+
+    fn attack_damage(&self) -> f32 {
+        10. * self.size
+    }
+}
+
+pub(crate) struct SpeciesRegistry {
+    species: Vec<Species>,
+}
+
+impl SpeciesRegistry {
+    fn load() -> Result<Self, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let data = read_asset(SPECIES_PATH)?;
+        #[cfg(target_arch = "wasm32")]
+        let data = include_str!("../assets/data/species.ron");
+        let species = ron::de::from_str(&data)
+            .map_err(|e| format!("failed to parse {}: {}", SPECIES_PATH, e))?;
+        Ok(Self { species })
+    }
+}
+
+#[derive(Debug)]
+enum Behavior {
+    Wandering,
+    Hunting(Entity),
+    Fleeing,
+}
+
+#[derive(Debug)]
+pub(crate) struct Creature {
+    species: usize,
+    behavior: Behavior,
+}
+
+/// Replaces all of the wildlife whenever the world is generated.
+#[allow(clippy::too_many_arguments)]
+fn spawn_creatures(
+    commands: &mut Commands,
+    mut generated_reader: Local<EventReader<TerrainGenerated>>,
+    generated_events: Res<Events<TerrainGenerated>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain_res: Res<TerrainResource>,
+    registry: Res<SpeciesRegistry>,
+    creature_query: Query<Entity, With<Creature>>,
+) {
+    if generated_reader.iter(&generated_events).last().is_none() {
+        return;
+    }
+    for entity in creature_query.iter() {
+        commands.despawn(entity);
+    }
+
+    let mut rng = thread_rng();
+    for (index, species) in registry.species.iter().enumerate() {
+        for _ in 0..species.groups {
+            // Find a spot in one of the biomes this species lives in.
+            let location = (0..SPAWN_ATTEMPTS)
+                .map(|_| {
+                    let r = SPAWN_RADIUS * rng.gen::<f64>().sqrt();
+                    let theta = rng.gen::<f64>() * 2. * std::f64::consts::PI;
+                    PointN([(r * theta.cos()) as i32, (r * theta.sin()) as i32])
+                })
+                .find(|column| {
+                    terrain_res
+                        .biome_at(*column)
+                        .map_or(false, |biome| species.biomes.contains(&biome))
+                });
+            let column = match location {
+                Some(column) => column,
+                None => {
+                    debug!("Couldn't find anywhere to spawn a pack of {}", species.name);
+                    continue;
+                }
+            };
+
+            // Spread the pack out in a line so that they don't spawn inside of each other.
+            for i in 0..species.group_size as i32 {
+                let x = column.x() + i * 2;
+                let z = column.y();
+                // Drop them onto the surface from a little above it.
+                let y = terrain_res.surface_y(PointN([x, z])) + 3;
+                let position = Vec3::new(x as f32, y as f32, z as f32);
+                spawn_creature(
+                    index,
+                    species,
+                    position,
+                    commands,
+                    &mut meshes,
+                    &mut materials,
+                );
+            }
+        }
+    }
+}
+
+fn spawn_creature(
+    index: usize,
+    species: &Species,
+    position: Vec3,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+) {
+    trace!("Spawning {} at {:?}", species.name, position);
+    let (r, g, b) = species.color;
+    let entity = commands
+        .spawn(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: species.size })),
+            material: materials.add(Color::rgb(r, g, b).into()),
+            transform: Transform::from_translation(position),
+            ..Default::default()
+        })
+        .with(Creature {
+            species: index,
+            behavior: Behavior::Wandering,
+        })
+        .with(Name(species.name.clone()))
+        .with(Health::default())
+        .with(Fighter::creature(
+            Skills::new(species.skill, species.skill, 0.),
+            species.attack_damage(),
+            1. + species.size / 2.,
+        ))
+        .with(Navigator::new(species.speed))
+        .with(ShadowCaster)
+        .with(PickableMesh::default())
+        .with(InteractableMesh::default())
+        .with(HighlightablePickMesh::default())
+        .current_entity()
+        .unwrap();
+    let half_size = species.size / 2.;
+    let rigid_body =
+        RigidBodyBuilder::new_dynamic().translation(position.x, position.y, position.z);
+    let collider = ColliderBuilder::cuboid(half_size, half_size, half_size)
+        .user_data(entity.to_bits() as u128);
+    commands.insert(entity, (rigid_body, collider));
+}
+
+/// Decides what each creature should be doing, and plans a path for it.
+fn creature_behavior(
+    registry: Res<SpeciesRegistry>,
+    terrain_res: Res<TerrainResource>,
+    mut creature_query: Query<(
+        &mut Creature,
+        &Transform,
+        &Health,
+        &mut Fighter,
+        &mut Navigator,
+    )>,
+    dwarf_query: Query<(Entity, &Transform), With<Dwarf>>,
+) {
+    let mut rng = thread_rng();
+
+    for (mut creature, transform, health, mut fighter, mut navigator) in creature_query.iter_mut() {
+        if !health.can_act() {
+            creature.behavior = Behavior::Wandering;
+            fighter.target = None;
+            continue;
+        }
+
+        let species = &registry.species[creature.species];
+        let position = transform.translation;
+        let nearest_dwarf = dwarf_query
+            .iter()
+            .map(|(entity, t)| (entity, t.translation, t.translation.distance(position)))
+            .filter(|(_, _, distance)| *distance <= PERCEPTION_RADIUS)
+            .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+
+        let start = point_from_translation(position);
+        match nearest_dwarf {
+            Some((dwarf, dwarf_position, _)) if species.hunts() => {
+                creature.behavior = Behavior::Hunting(dwarf);
+                chase(&terrain_res, &mut navigator, position, dwarf_position);
+            }
+            Some((_, dwarf_position, _)) if species.flees() => {
+                creature.behavior = Behavior::Fleeing;
+                if navigator.is_idle() {
+                    let mut away = position - dwarf_position;
+                    away.y = 0.;
+                    // A dwarf standing right on top of the creature gives no direction to run
+                    // in, so just pick one.
+                    if away.length_squared() < f32::EPSILON {
+                        let angle = rng.gen_range(0., std::f32::consts::TAU);
+                        away = Vec3::new(angle.cos(), 0., angle.sin());
+                    }
+                    let goal = point_from_translation(position + away.normalize() * FLEE_DISTANCE);
+                    if let Some(path) = find_path(&terrain_res, start, goal) {
+                        navigator.set_path(path);
+                    }
+                }
+            }
+            _ => {
+                creature.behavior = Behavior::Wandering;
+                if navigator.is_idle() && rng.gen::<f32>() < WANDER_CHANCE_PER_SECOND * TICK_SECONDS
+                {
+                    let offset = PointN([
+                        rng.gen_range(-WANDER_RADIUS, WANDER_RADIUS + 1),
+                        0,
+                        rng.gen_range(-WANDER_RADIUS, WANDER_RADIUS + 1),
+                    ]);
+                    if let Some(path) = find_path(&terrain_res, start, start + offset) {
+                        navigator.set_path(path);
+                    }
+                }
+            }
+        }
+
+        // Only hunters go on the attack. Everything else just defends itself.
+        fighter.target = match creature.behavior {
+            Behavior::Hunting(dwarf) => Some(dwarf),
+            _ => None,
+        };
+    }
+}
+
+pub(crate) struct CreaturePlugin;
+
+impl Plugin for CreaturePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // Without any species the world is just empty of wildlife, which beats not starting.
+        let registry = SpeciesRegistry::load().unwrap_or_else(|e| {
+            error!("Failed to load species: {}", e);
+            SpeciesRegistry {
+                species: Vec::new(),
+            }
+        });
+        app.add_resource(registry)
+            .add_system_to_stage(stage::PRE_UPDATE, spawn_creatures.system())
+            .add_system_to_stage(SIMULATION, creature_behavior.system());
+    }
+}
@@ -0,0 +1,27 @@
+//! Reading the game's data files, such as species and world generation presets.
+//!
+//! The WASM build can't read files, so it compiles the data in with `include_str!` instead.
+
+use std::path::PathBuf;
+
+/// The directory assets are loaded from. This matches where Bevy's asset server looks: next to
+/// the crate's manifest when run through cargo, and next to the executable otherwise. Unlike the
+/// working directory, neither depends on where the game was started from.
+pub(crate) fn asset_root() -> PathBuf {
+    std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(PathBuf::from))
+        })
+        .unwrap_or_default()
+        .join("assets")
+}
+
+/// Reads a file relative to the asset root, such as `data/species.ron`.
+pub(crate) fn read_asset(path: &str) -> Result<String, String> {
+    let full_path = asset_root().join(path);
+    std::fs::read_to_string(&full_path)
+        .map_err(|e| format!("failed to read {}: {}", full_path.display(), e))
+}
@@ -0,0 +1,249 @@
+//! The day/night cycle.
+//!
+//! The time of day follows the [`SimulationClock`], so it stops when the simulation is paused
+//! and speeds up with it. Gameplay systems can read how light it is from the [`Daylight`]
+//! resource; dwarves, for example, sleep through the night. The [`SkyPlugin`] moves the sun
+//! and moon across the sky to match, and sets the color of the light, the ambient light and
+//! the sky.
+use std::f32::consts::PI;
+use std::fmt;
+
+use bevy::{
+    ecs::{Commands, IntoSystem, Query, Res, ResMut, With},
+    math::Vec3,
+    pbr::{AmbientLight, Light},
+    prelude::{AppBuilder, ClearColor, Color, Plugin, Transform},
+};
+use colonize_pbr::{prelude::DirectionalLight, DirectionalLightBundle};
+
+use crate::simulation::{SimulationClock, SIMULATION, TICK_SECONDS};
+
+/// How long a day lasts, in seconds of simulated time.
+const DAY_SECONDS: f32 = 600.;
+/// The time of day when the game starts, as a fraction of a day after midnight.
+const START_TIME: f32 = 0.3;
+/// How far the sun's path leans away from straight overhead, in radians, so that there are
+/// shadows even at noon.
+const SUN_TILT: f32 = 0.4;
+/// How far below the horizon the sun can be before it's night.
+const NIGHT_ELEVATION: f32 = -0.05;
+/// How far away the point light which stands in for the sky light is. See
+/// [`DirectionalLight`].
+const SKY_LIGHT_DISTANCE: f32 = 10_000.;
+
+const NOON_SUN: [f32; 3] = [1.0, 0.96, 0.88];
+const HORIZON_SUN: [f32; 3] = [1.0, 0.55, 0.3];
+const MOON: [f32; 3] = [0.25, 0.3, 0.45];
+const DAY_AMBIENT: [f32; 3] = [0.25, 0.27, 0.3];
+const NIGHT_AMBIENT: [f32; 3] = [0.03, 0.04, 0.08];
+const DAY_SKY: [f32; 3] = [0.45, 0.65, 0.9];
+const DUSK_SKY: [f32; 3] = [0.8, 0.45, 0.3];
+const NIGHT_SKY: [f32; 3] = [0.01, 0.01, 0.04];
+
+/// The time of day, and how light it is.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Daylight {
+    /// Days since the game started, counting from zero.
+    day: u64,
+    /// The time of day, as a fraction of a day after midnight.
+    time: f32,
+}
+
+impl Default for Daylight {
+    fn default() -> Self {
+        Self::at_tick(0)
+    }
+}
+
+impl Daylight {
+    fn at_tick(ticks: u64) -> Self {
+        let days = START_TIME as f64 + ticks as f64 * TICK_SECONDS as f64 / DAY_SECONDS as f64;
+        Self {
+            day: days as u64,
+            time: days.fract() as f32,
+        }
+    }
+
+    /// The direction towards the sun. It rises in the east (+X) at six, is at its highest at
+    /// noon, and sets in the west at six.
+    fn sun_direction(&self) -> Vec3 {
+        let angle = 2. * PI * (self.time - 0.25);
+        Vec3::new(
+            angle.cos(),
+            angle.sin() * SUN_TILT.cos(),
+            angle.sin() * SUN_TILT.sin(),
+        )
+    }
+
+    /// How light it is outside, from 0 at night to 1 in the middle of the day.
+    pub(crate) fn light_level(&self) -> f32 {
+        smoothstep(NIGHT_ELEVATION, 0.3, self.sun_direction().y)
+    }
+
+    /// Whether the sun has set, and it's time for dwarves to be asleep.
+    pub(crate) fn is_night(&self) -> bool {
+        self.sun_direction().y < NIGHT_ELEVATION
+    }
+}
+
+impl fmt::Display for Daylight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let minutes = (self.time * 24. * 60.) as u32;
+        write!(
+            f,
+            "Day {}, {:02}:{:02}",
+            self.day + 1,
+            minutes / 60,
+            minutes % 60
+        )?;
+        if self.is_night() {
+            write!(f, " (night)")?;
+        }
+        Ok(())
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).max(0.).min(1.);
+    t * t * (3. - 2. * t)
+}
+
+fn rgb([r, g, b]: [f32; 3]) -> Color {
+    Color::rgb(r, g, b)
+}
+
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    Color::rgb(
+        a.r() + (b.r() - a.r()) * t,
+        a.g() + (b.g() - a.g()) * t,
+        a.b() + (b.b() - a.b()) * t,
+    )
+}
+
+fn scale(color: Color, factor: f32) -> Color {
+    Color::rgb(color.r() * factor, color.g() * factor, color.b() * factor)
+}
+
+fn update_daylight(clock: Res<SimulationClock>, mut daylight: ResMut<Daylight>) {
+    *daylight = Daylight::at_tick(clock.ticks());
+}
+
+/// Keeps track of the time of day. Needs neither a window nor a renderer, so it runs headless
+/// too.
+pub(crate) struct DaylightPlugin;
+
+impl Plugin for DaylightPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(Daylight::default())
+            .add_system_to_stage(SIMULATION, update_daylight.system());
+    }
+}
+
+/// The light from the sky: the sun by day, and the moon by night.
+struct SkyLight;
+
+fn spawn_sky_light(commands: &mut Commands) {
+    commands
+        .spawn(DirectionalLightBundle::default())
+        .with(Light::default())
+        .with(SkyLight);
+}
+
+/// Moves the sky light to wherever the sun or moon is, and colors it, the ambient light and
+/// the sky for the time of day.
+fn update_sky(
+    daylight: Res<Daylight>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+    mut query: Query<(&mut DirectionalLight, &mut Light, &mut Transform), With<SkyLight>>,
+) {
+    let sun = daylight.sun_direction();
+    // The moon is opposite the sun, so one of them is always up. Both are dark at the
+    // horizon, where the light switches from one to the other.
+    let (direction, color) = if sun.y >= 0. {
+        let color = mix(rgb(HORIZON_SUN), rgb(NOON_SUN), smoothstep(0., 0.5, sun.y));
+        (sun, scale(color, smoothstep(0., 0.15, sun.y)))
+    } else {
+        let moon = -sun;
+        (moon, scale(rgb(MOON), smoothstep(0., 0.15, moon.y)))
+    };
+
+    let light_level = daylight.light_level();
+    ambient_light.color = mix(rgb(NIGHT_AMBIENT), rgb(DAY_AMBIENT), light_level);
+    // The sky glows around sunrise and sunset.
+    let dusk = 1. - smoothstep(0., 0.25, sun.y.abs());
+    let sky = mix(rgb(NIGHT_SKY), rgb(DAY_SKY), light_level);
+    clear_color.0 = mix(sky, rgb(DUSK_SKY), 0.6 * dusk);
+
+    for (mut directional_light, mut light, mut transform) in query.iter_mut() {
+        directional_light.color = color;
+        light.color = color;
+        // The light shines along its forward axis, away from the sun or moon. Neither is ever
+        // straight overhead, so the up axis is never parallel to it.
+        *transform = Transform::from_translation(direction * SKY_LIGHT_DISTANCE)
+            .looking_at(Vec3::zero(), Vec3::unit_y());
+    }
+}
+
+/// Lights the scene by the sun and moon. Only added when there's something to render.
+pub(crate) struct SkyPlugin;
+
+impl Plugin for SkyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(spawn_sky_light.system())
+            .add_system(update_sky.system());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at_time(time: f32) -> Daylight {
+        Daylight { day: 0, time }
+    }
+
+    /// How far from six the sun crosses the elevation at which night starts or ends, as a
+    /// fraction of a day.
+    fn twilight() -> f32 {
+        (-NIGHT_ELEVATION / SUN_TILT.cos()).asin() / (2. * PI)
+    }
+
+    #[test]
+    fn it_is_fully_light_at_noon() {
+        let noon = at_time(0.5);
+        assert_eq!(noon.light_level(), 1.);
+        assert!(!noon.is_night());
+    }
+
+    #[test]
+    fn it_is_fully_dark_at_midnight() {
+        let midnight = at_time(0.);
+        assert_eq!(midnight.light_level(), 0.);
+        assert!(midnight.is_night());
+    }
+
+    #[test]
+    fn night_ends_just_before_sunrise() {
+        let end = 0.25 - twilight();
+        assert!(at_time(end - 0.001).is_night());
+        assert!(!at_time(end + 0.001).is_night());
+    }
+
+    #[test]
+    fn night_starts_just_after_sunset() {
+        let start = 0.75 + twilight();
+        assert!(!at_time(start - 0.001).is_night());
+        assert!(at_time(start + 0.001).is_night());
+    }
+
+    #[test]
+    fn it_is_dark_whenever_it_is_night() {
+        for i in 0..100 {
+            let daylight = at_time(i as f32 / 100.);
+            if daylight.is_night() {
+                assert_eq!(daylight.light_level(), 0.);
+            }
+        }
+    }
+}
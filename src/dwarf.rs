@@ -1,5 +1,8 @@
+use std::fmt;
+
 use bevy::{
-    ecs::{Entity, Query, Res, ResMut},
+    app::{stage, EventReader, Events},
+    ecs::{Entity, Local, Query, Res, ResMut, With, Without},
     input::Input,
     math::Vec3,
     pbr::PbrBundle,
@@ -17,46 +20,108 @@ use bevy_rapier3d::{
     physics::{EventQueue, RigidBodyHandleComponent},
     rapier::{
         dynamics::{RigidBodyBuilder, RigidBodySet},
-        geometry::{ColliderBuilder, ColliderSet, ContactEvent},
+        geometry::{ColliderBuilder, ColliderHandle, ColliderSet, ContactEvent},
     },
 };
-use building_blocks::core::PointN;
+use building_blocks::core::{Point3i, PointN};
+use colonize_pbr::{PointLight, ShadowCaster};
 use rand::{thread_rng, Rng};
 
-use crate::terrain::{Chunk, TerrainResource};
-
-pub(crate) const DWARVES: &str = "DWARVES";
+use crate::combat::{Drafted, Fighter, Skills, Weapon};
+use crate::daylight::Daylight;
+use crate::health::Health;
+use crate::mood::{work_speed, MentalState, Mood, Personality, ThoughtKind, Thoughts};
+use crate::navigation::{find_path, Navigator};
+use crate::simulation::{FallSpeed, SIMULATION, TICK_SECONDS};
+use crate::terrain::{Chunk, TerrainGenerated, TerrainResource};
+use crate::vegetation::Chopping;
+
+/// How fast a dwarf walks, in m/s, before taking their mood into account.
+const DWARF_SPEED: f32 = 3.;
+/// Chance per second that an idle dwarf goes looking for something to do.
+const IDLE_ACTION_CHANCE_PER_SECOND: f32 = 1.;
+/// How far above the surface dwarves are spawned.
+const SPAWN_HEIGHT: f32 = 2.5;
+/// How close a dwarf has to get to a piece of gold to have found it.
+const PROSPECTING_RADIUS: f32 = 1.5;
 
 // Struct for storing the currently selected dwarf, if any.
-struct SelectedDwarf {
-    dwarf: Option<Entity>,
+pub(crate) struct SelectedDwarf {
+    pub(crate) dwarf: Option<Entity>,
 }
 
 #[derive(Debug)]
-struct Dwarf {
-    free_fall: bool,
+pub(crate) struct Dwarf {
+    /// How many terrain colliders the dwarf is touching. A dwarf who isn't touching any is
+    /// falling.
+    ground_contacts: usize,
+    /// The highest downward speed the dwarf has reached since they last left the ground.
+    fall_speed: f32,
+    /// Whether the dwarf has gone to sleep for the night.
+    asleep: bool,
+    /// The gold the dwarf is walking over to, if any.
+    prospecting: Option<Point3i>,
 }
 
 impl Dwarf {
-    fn set_free_fall(&mut self, free_fall: bool) {
-        self.free_fall = free_fall
+    fn is_falling(&self) -> bool {
+        self.ground_contacts == 0
+    }
+
+    pub(crate) fn is_asleep(&self) -> bool {
+        self.asleep
     }
 }
 
 impl Default for Dwarf {
     fn default() -> Self {
-        Self { free_fall: true }
+        Self {
+            ground_contacts: 0,
+            fall_speed: 0.,
+            asleep: false,
+            prospecting: None,
+        }
     }
 }
 
 #[derive(Debug)]
-struct Name(String);
+pub(crate) struct Name(pub(crate) String);
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
+/// Replaces the colony with a fresh one whenever the world is generated.
+#[allow(clippy::too_many_arguments)]
 fn add_dwarves(
     commands: &mut Commands,
+    mut generated_reader: Local<EventReader<TerrainGenerated>>,
+    generated_events: Res<Events<TerrainGenerated>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     terrain_res: Res<TerrainResource>,
+    mut selected_dwarf: ResMut<SelectedDwarf>,
+    dwarf_query: Query<Entity, With<Dwarf>>,
+) {
+    if generated_reader.iter(&generated_events).last().is_none() {
+        return;
+    }
+    for entity in dwarf_query.iter() {
+        commands.despawn(entity);
+    }
+    selected_dwarf.dwarf = None;
+
+    spawn_dwarves(commands, &mut meshes, &mut materials, &terrain_res);
+}
+
+/// Drops ten dwarves onto the surface around the origin.
+fn spawn_dwarves(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    terrain_res: &TerrainResource,
 ) {
     let names = [
         "Khustrul Lavablade",
@@ -84,28 +149,20 @@ fn add_dwarves(
     }
     let mut spawn_positions = spawn_positions
         .into_iter()
-        // FIXME: `surface_y` doesn't return the correct value. It returns a value that's way
-        //   above the actual surface.
+        // Dwarves are dropped onto the surface from just above it, which is too short a fall to
+        // hurt them.
         .map(|(x, z)| {
             (
                 x as f32,
-                terrain_res.surface_y(PointN([x as i32, z as i32])) as f32 + 5.,
+                terrain_res.surface_y(PointN([x as i32, z as i32])) as f32 + SPAWN_HEIGHT,
                 z as f32,
             )
         });
 
     for name in &names {
         let position = spawn_positions.next().unwrap();
-        spawn_dwarf(
-            name.to_string(),
-            position,
-            commands,
-            &mut meshes,
-            &mut materials,
-        );
+        spawn_dwarf(name.to_string(), position, commands, meshes, materials);
     }
-
-    commands.insert_resource(SelectedDwarf { dwarf: None });
 }
 
 fn spawn_dwarf(
@@ -117,6 +174,13 @@ fn spawn_dwarf(
 ) {
     trace!("Spawning dwarf at {:?}", (px, py, pz));
     const SIZE: f32 = 1.;
+    let mut rng = thread_rng();
+    // Some dwarves bring a weapon with them; the rest will have to find one.
+    let weapon = if rng.gen::<bool>() {
+        Some(Weapon::random(&mut rng))
+    } else {
+        None
+    };
 
     let entity = commands
         .spawn(PbrBundle {
@@ -127,6 +191,21 @@ fn spawn_dwarf(
         })
         .with(Dwarf::default())
         .with(Name(name))
+        .with(Personality::random(&mut rng))
+        .with(Thoughts::default())
+        .with(Mood::default())
+        .with(MentalState::default())
+        .with(Health::default())
+        .with(FallSpeed::default())
+        .with(Fighter::dwarf(Skills::random(&mut rng), weapon))
+        .with(Navigator::new(DWARF_SPEED))
+        .with(ShadowCaster)
+        // Every dwarf carries a lantern, to find their way around underground.
+        .with(PointLight {
+            color: Color::rgb(1.0, 0.75, 0.45),
+            intensity: 4.,
+            range: 8.,
+        })
         .with(PickableMesh::default())
         .with(InteractableMesh::default())
         .with(HighlightablePickMesh::default())
@@ -153,20 +232,52 @@ fn random_point_in_circle(origin: (f64, f64), radius: f64) -> (f64, f64) {
 fn input_system(
     keyboard_input: Res<Input<KeyCode>>,
     commands: &mut Commands,
-    meshes: ResMut<Assets<Mesh>>,
-    materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
     terrain_res: Res<TerrainResource>,
 ) {
     // If the `T` button is pressed, spawn in 10 more dwarves.
-    if keyboard_input.pressed(KeyCode::T) {
-        add_dwarves(commands, meshes, materials, terrain_res);
+    if keyboard_input.just_pressed(KeyCode::T) {
+        spawn_dwarves(commands, &mut meshes, &mut materials, &terrain_res);
+    }
+}
+
+/// Records how fast each dwarf is falling, so that the impact can be judged when they land.
+/// This runs before the contact events of the same tick are handled, so the speed of the tick
+/// they land in still counts.
+fn track_fall_speed(mut dwarf_query: Query<(&mut Dwarf, &FallSpeed)>) {
+    for (mut dwarf, fall_speed) in dwarf_query.iter_mut() {
+        if dwarf.is_falling() && fall_speed.get() > dwarf.fall_speed {
+            dwarf.fall_speed = fall_speed.get();
+        }
+    }
+}
+
+/// The dwarf in a collision between a dwarf and a chunk of terrain, if it was one.
+fn dwarf_on_ground(
+    collider_set: &ColliderSet,
+    dwarf_query: &Query<(&mut Dwarf, &Name, &mut Health, &mut Thoughts)>,
+    chunk_query: &Query<&Chunk>,
+    handle_1: ColliderHandle,
+    handle_2: ColliderHandle,
+) -> Option<Entity> {
+    let entity_1 = Entity::from_bits(collider_set.get(handle_1)?.user_data as u64);
+    let entity_2 = Entity::from_bits(collider_set.get(handle_2)?.user_data as u64);
+    let is_dwarf = |entity| dwarf_query.get_component::<Dwarf>(entity).is_ok();
+    let is_chunk = |entity| chunk_query.get(entity).is_ok();
+    if is_dwarf(entity_1) && is_chunk(entity_2) {
+        Some(entity_1)
+    } else if is_dwarf(entity_2) && is_chunk(entity_1) {
+        Some(entity_2)
+    } else {
+        None
     }
 }
 
 fn handle_physics_events(
     events: Res<EventQueue>,
     collider_set: Res<ColliderSet>,
-    mut dwarf_query: Query<&mut Dwarf>,
+    mut dwarf_query: Query<(&mut Dwarf, &Name, &mut Health, &mut Thoughts)>,
     chunk_query: Query<&Chunk>,
 ) {
     while let Ok(proximity_event) = events.proximity_events.pop() {
@@ -176,67 +287,49 @@ fn handle_physics_events(
     while let Ok(contact_event) = events.contact_events.pop() {
         trace!("Received contact event: {:?}", contact_event);
 
-        if let ContactEvent::Started(handle_1, handle_2) = contact_event {
-            // If a dwarf has collided with the ground, mark him as no longer falling.
-            let collider_1 = collider_set.get(handle_1).unwrap();
-            let collider_2 = collider_set.get(handle_2).unwrap();
-
-            let entity_1 = Entity::from_bits(collider_1.user_data as u64);
-            let entity_2 = Entity::from_bits(collider_2.user_data as u64);
-
-            let mut maybe_dwarf_entity: Option<Entity> = Option::None;
-            if dwarf_query.get_component::<Dwarf>(entity_1).is_ok() {
-                maybe_dwarf_entity.replace(entity_1);
-            } else if dwarf_query.get_component::<Dwarf>(entity_2).is_ok() {
-                maybe_dwarf_entity.replace(entity_2);
-            }
-
-            let mut maybe_chunk_entity: Option<Entity> = Option::None;
-            if chunk_query.get_component::<Chunk>(entity_1).is_ok() {
-                maybe_chunk_entity.replace(entity_1);
-            } else if chunk_query.get_component::<Chunk>(entity_2).is_ok() {
-                maybe_chunk_entity.replace(entity_2);
-            }
-
-            if let Some(dwarf_entity) = maybe_dwarf_entity {
-                if let Some(_chunk_entity) = maybe_chunk_entity {
-                    let mut dwarf = dwarf_query
-                        .get_component_mut::<Dwarf>(dwarf_entity)
-                        .unwrap();
-                    dwarf.set_free_fall(false);
-                    trace!("Dwarf is now stationary");
+        match contact_event {
+            ContactEvent::Started(handle_1, handle_2) => {
+                let dwarf_entity = match dwarf_on_ground(
+                    &collider_set,
+                    &dwarf_query,
+                    &chunk_query,
+                    handle_1,
+                    handle_2,
+                ) {
+                    Some(entity) => entity,
+                    None => continue,
+                };
+                let (mut dwarf, name, mut health, mut thoughts) =
+                    dwarf_query.get_mut(dwarf_entity).unwrap();
+                let landed = dwarf.is_falling();
+                dwarf.ground_contacts += 1;
+                if landed {
+                    trace!("Dwarf {} landed at {} m/s", name, dwarf.fall_speed);
+                    if health.apply_fall_damage(dwarf.fall_speed) {
+                        trace!("Dwarf {} was hurt by the fall", name);
+                        thoughts.add(ThoughtKind::Fell);
+                        thoughts.add(ThoughtKind::WasInjured);
+                    }
+                    dwarf.fall_speed = 0.;
                 }
             }
-        } else if let ContactEvent::Stopped(handle_1, handle_2) = contact_event {
-            // If a dwarf is colliding with the ground, mark him as falling.
-            let collider_1 = collider_set.get(handle_1).unwrap();
-            let collider_2 = collider_set.get(handle_2).unwrap();
-
-            let entity_1 = Entity::from_bits(collider_1.user_data as u64);
-            let entity_2 = Entity::from_bits(collider_2.user_data as u64);
-
-            let mut maybe_dwarf_entity: Option<Entity> = Option::None;
-            if dwarf_query.get_component::<Dwarf>(entity_1).is_ok() {
-                maybe_dwarf_entity.replace(entity_1);
-            } else if dwarf_query.get_component::<Dwarf>(entity_2).is_ok() {
-                maybe_dwarf_entity.replace(entity_2);
-            }
-
-            let mut maybe_chunk_entity: Option<Entity> = Option::None;
-            if chunk_query.get_component::<Chunk>(entity_1).is_ok() {
-                maybe_chunk_entity.replace(entity_1);
-            } else if chunk_query.get_component::<Chunk>(entity_2).is_ok() {
-                maybe_chunk_entity.replace(entity_2);
-            }
-
-            if let Some(dwarf_entity) = maybe_dwarf_entity {
-                if let Some(_chunk_entity) = maybe_chunk_entity {
-                    let _dwarf = dwarf_query
-                        .get_component_mut::<Dwarf>(dwarf_entity)
-                        .unwrap();
-                    // FIXME
-                    //dwarf.set_free_fall(true);
-                    //trace!("Dwarf is now falling");
+            ContactEvent::Stopped(handle_1, handle_2) => {
+                let dwarf_entity = match dwarf_on_ground(
+                    &collider_set,
+                    &dwarf_query,
+                    &chunk_query,
+                    handle_1,
+                    handle_2,
+                ) {
+                    Some(entity) => entity,
+                    None => continue,
+                };
+                let mut dwarf = dwarf_query
+                    .get_component_mut::<Dwarf>(dwarf_entity)
+                    .unwrap();
+                dwarf.ground_contacts = dwarf.ground_contacts.saturating_sub(1);
+                if dwarf.is_falling() {
+                    trace!("Dwarf is now falling");
                 }
             }
         }
@@ -244,43 +337,109 @@ fn handle_physics_events(
 }
 
 fn move_around(
-    mut rigid_body_set: ResMut<RigidBodySet>,
-    mut dwarf_rigid_body_query: Query<(&mut Dwarf, &Name, &RigidBodyHandleComponent)>,
-    terrain_res: ResMut<TerrainResource>,
+    rigid_body_set: Res<RigidBodySet>,
+    daylight: Res<Daylight>,
+    mut dwarf_rigid_body_query: Query<
+        (
+            &mut Dwarf,
+            &Name,
+            &Personality,
+            &Mood,
+            &MentalState,
+            &Health,
+            &mut Thoughts,
+            &mut Navigator,
+            &RigidBodyHandleComponent,
+        ),
+        (Without<Drafted>, Without<Chopping>),
+    >,
+    terrain_res: Res<TerrainResource>,
 ) {
     let mut rng = thread_rng();
 
-    for (dwarf, name, rigid_body_handle) in dwarf_rigid_body_query.iter_mut() {
-        let rigid_body = rigid_body_set.get_mut(rigid_body_handle.handle()).unwrap();
-        // A dwarf that is falling can't do anything until they stop falling.
-        // A dwarf that is in motion stays in motion.
-        // FIXME
-        let _is_idle = !dwarf.free_fall && !rigid_body.is_moving();
-        if !dwarf.free_fall {
-            // If the dwarf is idle, then that means it can start performing an action.
-            //trace!("Dwarf {:?} is now walking around", name);
-            if rng.gen::<f32>() < 0.05 {
-                let rigid_body_position = rigid_body
-                    .position()
-                    .transform_point(&Point3::new(0., 0., 0.));
-                let nearest_gold = terrain_res.find_nearest_gold(
-                    rigid_body_position.x as i32,
-                    rigid_body_position.y as i32,
-                    rigid_body_position.z as i32,
+    for (
+        mut dwarf,
+        name,
+        personality,
+        mood,
+        mental_state,
+        health,
+        mut thoughts,
+        mut navigator,
+        rigid_body_handle,
+    ) in dwarf_rigid_body_query.iter_mut()
+    {
+        // A dwarf who is unconscious, or in the middle of a mental break, can't do any work.
+        if !health.can_act() || mental_state.is_breaking() {
+            dwarf.asleep = false;
+            dwarf.prospecting = None;
+            navigator.clear();
+            continue;
+        }
+
+        // Happier dwarves put more effort into getting where they're going.
+        navigator.speed = DWARF_SPEED * work_speed(personality, mood);
+
+        let rigid_body = rigid_body_set.get(rigid_body_handle.handle()).unwrap();
+        let rigid_body_position = rigid_body
+            .position()
+            .transform_point(&Point3::new(0., 0., 0.));
+
+        // A dwarf who was headed for some gold has found it if they made it all the way there.
+        if navigator.is_idle() {
+            if let Some(gold) = dwarf.prospecting.take() {
+                let gold = Vec3::new(gold.x() as f32, gold.y() as f32, gold.z() as f32)
+                    + Vec3::new(0.5, 1.5, 0.5);
+                let position = Vec3::new(
+                    rigid_body_position.x,
+                    rigid_body_position.y,
+                    rigid_body_position.z,
                 );
-                if let Some(gold) = nearest_gold {
-                    let difference = rigid_body_position
-                        - Point3::new(gold.x() as f32, gold.y() as f32 + 1., gold.z() as f32);
-                    // TODO: do we need to wake up the rigidbody?
-                    //let impulse = Vector3::new(rng.gen::<f32>(), 0., rng.gen::<f32>()).normalize() * 100.;
+                if position.distance(gold) <= PROSPECTING_RADIUS {
+                    trace!("Dwarf {} found the gold at {:?}", name, gold);
+                    thoughts.add(ThoughtKind::FoundGold);
+                }
+            }
+        }
+
+        // Once it's dark, dwarves lie down wherever they are as soon as they've got nothing
+        // left to do, and get up again at dawn, stiff from sleeping on the ground.
+        let was_asleep = dwarf.asleep;
+        dwarf.asleep = daylight.is_night() && !dwarf.is_falling() && navigator.is_idle();
+        if was_asleep && !daylight.is_night() {
+            thoughts.add(ThoughtKind::SleptOnTheGround);
+        }
+
+        // A dwarf that is falling can't do anything until they stop falling.
+        // A dwarf that is already walking somewhere keeps going until they get there.
+        if dwarf.is_falling() || !navigator.is_idle() || dwarf.asleep {
+            continue;
+        }
+
+        // If the dwarf is idle, then that means it can start performing an action.
+        if rng.gen::<f32>() < IDLE_ACTION_CHANCE_PER_SECOND * TICK_SECONDS {
+            let nearest_gold = terrain_res.find_nearest_gold(
+                rigid_body_position.x as i32,
+                rigid_body_position.y as i32,
+                rigid_body_position.z as i32,
+            );
+            if let Some(gold) = nearest_gold {
+                let start = PointN([
+                    rigid_body_position.x.floor() as i32,
+                    rigid_body_position.y.floor() as i32,
+                    rigid_body_position.z.floor() as i32,
+                ]);
+                // Stand on top of the gold.
+                let goal = gold + PointN([0, 1, 0]);
+                if let Some(path) = find_path(&terrain_res, start, goal) {
                     trace!(
                         "Dwarf {:?} is moving from {:?} to {:?}",
                         name,
                         rigid_body_position,
                         gold
                     );
-                    let impulse = -difference * 10.;
-                    rigid_body.apply_impulse(impulse, true);
+                    navigator.set_path(path);
+                    dwarf.prospecting = Some(gold);
                 }
             }
         }
@@ -293,11 +452,10 @@ fn select_dwarves(
     mut selected_dwarf: ResMut<SelectedDwarf>,
 ) {
     for (interactable, entity) in &mut event_query.iter() {
-        let mouse_down_event = interactable
-            .mouse_down_event(&Group::default(), MouseButton::Left)
-            .unwrap();
-        // If a mouse down event has occurred, select the dwarf for motion.
-        if mouse_down_event.is_none() {
+        // If a mouse down event has occurred, select the dwarf for motion. Without the picking
+        // plugins, as when running headless, there are never any events.
+        let mouse_down_event = interactable.mouse_down_event(&Group::default(), MouseButton::Left);
+        if !matches!(mouse_down_event, Ok(Some(_))) {
             continue;
         }
 
@@ -352,10 +510,12 @@ pub(crate) struct DwarfPlugin;
 
 impl Plugin for DwarfPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system_to_stage(DWARVES, add_dwarves.system())
+        app.add_resource(SelectedDwarf { dwarf: None })
+            .add_system_to_stage(stage::PRE_UPDATE, add_dwarves.system())
             .add_system(input_system.system())
-            .add_system(handle_physics_events.system())
-            .add_system(move_around.system())
+            .add_system_to_stage(SIMULATION, track_fall_speed.system())
+            .add_system_to_stage(SIMULATION, handle_physics_events.system())
+            .add_system_to_stage(SIMULATION, move_around.system())
             .add_system(select_dwarves.system())
             .add_system(keyboard_movement_system.system());
     }
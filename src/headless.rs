@@ -0,0 +1,217 @@
+//! Running the simulation without a window or a GPU.
+//!
+//! Pass `--headless` to run worldgen, physics, dwarves and creatures for a fixed
+//! number of ticks, as fast as possible, and then exit. This is mostly useful for
+//! soak-testing colonies on machines without a display:
+//!
+//! ```text
+//! colonize --headless --ticks 10000 --dump colony.ron
+//! ```
+//!
+//! Nothing is rendered. Physics doesn't need the terrain's meshes either, since
+//! its colliders are built from boxes of solid voxels, but the meshes are still
+//! generated: each chunk's collider is built in the same background task as its
+//! meshes, and the simulation waits for every chunk's collider before it starts.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::{
+    app::{AppExit, Events, PluginGroupBuilder, ScheduleRunnerSettings},
+    ecs::{Commands, IntoSystem, Local, Query, Res, ResMut, With},
+    prelude::{
+        info, AddAsset, AppBuilder, Mesh, Plugin, PluginGroup, StandardMaterial, Texture, Transform,
+    },
+};
+use serde::Serialize;
+
+use crate::creature::Creature;
+use crate::dwarf::{Dwarf, Name};
+use crate::health::Health;
+use crate::item::Item;
+use crate::mood::{MentalState, Mood};
+use crate::simulation::SimulationClock;
+
+/// The number of ticks simulated when `--ticks` isn't given.
+const DEFAULT_TICKS: u64 = 1000;
+
+/// Command-line options for a headless run.
+#[derive(Debug)]
+pub struct HeadlessSettings {
+    /// How many ticks to simulate before exiting.
+    ticks: u64,
+    /// Where to write a report of the state of the world once the run is over.
+    dump: Option<PathBuf>,
+}
+
+impl HeadlessSettings {
+    /// Parses the command-line arguments. Returns `None` unless `--headless` was passed.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut headless = false;
+        let mut settings = Self {
+            ticks: DEFAULT_TICKS,
+            dump: None,
+        };
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--ticks" => {
+                    let value = args.next().expect("--ticks requires a value");
+                    settings.ticks = value.parse().expect("--ticks must be a whole number");
+                }
+                "--dump" => {
+                    let value = args.next().expect("--dump requires a path");
+                    settings.dump = Some(PathBuf::from(value));
+                }
+                // Logging hasn't been set up yet at this point.
+                _ => eprintln!("Ignoring unknown argument {:?}", arg),
+            }
+        }
+
+        if headless {
+            Some(settings)
+        } else {
+            None
+        }
+    }
+}
+
+/// The engine plugins needed to run the simulation, without any windowing or rendering.
+pub struct HeadlessPlugins;
+
+impl PluginGroup for HeadlessPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(bevy::log::LogPlugin::default());
+        group.add(bevy::reflect::ReflectPlugin::default());
+        group.add(bevy::core::CorePlugin::default());
+        group.add(bevy::transform::TransformPlugin::default());
+        // Nothing ever presses a key, but the input resources still need to exist for the
+        // systems which read them.
+        group.add(bevy::input::InputPlugin::default());
+        group.add(bevy::asset::AssetPlugin::default());
+        group.add(bevy::app::ScheduleRunnerPlugin::default());
+    }
+}
+
+/// Runs the simulation one tick per frame, as fast as possible, and exits once enough ticks
+/// have passed. This must be added after the `SimulationPlugin`.
+pub(crate) struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // These are normally registered by the render and PBR plugins.
+        app.add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .add_asset::<colonize_pbr::StandardMaterial>()
+            .add_asset::<Texture>()
+            .add_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs(0)))
+            .add_resource(SimulationClock::lockstep())
+            .add_system(finish_run.system());
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DwarfReport {
+    pub name: String,
+    pub position: (f32, f32, f32),
+    pub health: String,
+    pub wounds: Vec<String>,
+    pub mood: String,
+    pub mental_state: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatureReport {
+    pub name: String,
+    pub position: (f32, f32, f32),
+    pub health: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ItemReport {
+    pub kind: String,
+    pub position: (f32, f32, f32),
+}
+
+/// A snapshot of the world at the end of a headless run.
+#[derive(Debug, Serialize)]
+pub struct ColonyReport {
+    pub ticks: u64,
+    pub dwarves: Vec<DwarfReport>,
+    pub creatures: Vec<CreatureReport>,
+    pub items: Vec<ItemReport>,
+}
+
+fn position(transform: &Transform) -> (f32, f32, f32) {
+    let t = transform.translation;
+    (t.x, t.y, t.z)
+}
+
+/// Once the requested number of ticks have been simulated, dumps the state of the world
+/// (if asked to), keeps it around as a resource for whoever ran the app, and exits.
+#[allow(clippy::too_many_arguments)]
+fn finish_run(
+    commands: &mut Commands,
+    mut finished: Local<bool>,
+    clock: Res<SimulationClock>,
+    settings: Res<HeadlessSettings>,
+    mut app_exit_events: ResMut<Events<AppExit>>,
+    dwarf_query: Query<(&Name, &Transform, &Health, &Mood, &MentalState), With<Dwarf>>,
+    creature_query: Query<(&Name, &Transform, &Health), With<Creature>>,
+    item_query: Query<(&Item, &Transform)>,
+) {
+    if *finished || clock.ticks() < settings.ticks {
+        return;
+    }
+    *finished = true;
+
+    let report = ColonyReport {
+        ticks: clock.ticks(),
+        dwarves: dwarf_query
+            .iter()
+            .map(
+                |(name, transform, health, mood, mental_state)| DwarfReport {
+                    name: name.to_string(),
+                    position: position(transform),
+                    health: health.to_string(),
+                    wounds: health.wounds().iter().map(|w| w.to_string()).collect(),
+                    mood: mood.to_string(),
+                    mental_state: mental_state.to_string(),
+                },
+            )
+            .collect(),
+        creatures: creature_query
+            .iter()
+            .map(|(name, transform, health)| CreatureReport {
+                name: name.to_string(),
+                position: position(transform),
+                health: health.to_string(),
+            })
+            .collect(),
+        items: item_query
+            .iter()
+            .map(|(item, transform)| ItemReport {
+                kind: item.kind.to_string(),
+                position: position(transform),
+            })
+            .collect(),
+    };
+    info!(
+        "Simulated {} ticks: {} dwarves, {} creatures and {} items remain",
+        report.ticks,
+        report.dwarves.len(),
+        report.creatures.len(),
+        report.items.len()
+    );
+
+    if let Some(path) = &settings.dump {
+        let data = ron::ser::to_string_pretty(&report, ron::ser::PrettyConfig::new())
+            .expect("failed to serialize colony report");
+        std::fs::write(path, data).expect("failed to write colony report");
+        info!("Wrote colony report to {}", path.display());
+    }
+
+    commands.insert_resource(report);
+    app_exit_events.send(AppExit);
+}
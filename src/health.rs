@@ -0,0 +1,434 @@
+//! The body-part injury model.
+//!
+//! Rather than a single hit point pool, each dwarf has a body made up of parts
+//! which can be individually wounded. Wounds bleed until they clot, and a dwarf
+//! in too much pain or with too little blood falls unconscious. Losing all of
+//! their blood, or destroying a vital part, kills them and leaves a corpse behind.
+use std::fmt;
+
+use bevy::{
+    ecs::{Commands, Entity, Query, ResMut},
+    math::Vec3,
+    prelude::{debug, AppBuilder, Assets, IntoSystem, Mesh, Plugin, StandardMaterial, Transform},
+};
+
+use crate::combat::Fighter;
+use crate::dwarf::{Dwarf, Name, SelectedDwarf};
+use crate::item::{spawn_item, ItemKind};
+use crate::mood::{ThoughtKind, Thoughts};
+use crate::simulation::{SIMULATION, TICK_SECONDS};
+
+/// The amount of blood in a healthy body.
+const MAX_BLOOD: f32 = 100.;
+/// Blood regenerated per second once all wounds have stopped bleeding.
+const BLOOD_REGENERATION: f32 = 0.5;
+/// Fraction of its bleeding rate a wound loses per second as it clots.
+const CLOTTING_RATE: f32 = 0.05;
+/// Bleeding rate below which a wound has clotted completely.
+const MIN_BLEEDING: f32 = 0.01;
+/// Damage a wound recovers per second.
+const HEALING_RATE: f32 = 0.2;
+/// Total pain above which a dwarf passes out.
+const PAIN_THRESHOLD: f32 = 60.;
+/// Blood level below which a dwarf passes out.
+const BLOOD_THRESHOLD: f32 = 50.;
+/// Distance within which other dwarves witness a death.
+const WITNESS_RADIUS: f32 = 30.;
+/// Impact speed, in m/s, that a body can land at without getting hurt.
+const SAFE_FALL_SPEED: f32 = 8.;
+/// Damage dealt for each m/s of impact speed above the safe fall speed.
+const FALL_DAMAGE_PER_SPEED: f32 = 5.;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum BodyPart {
+    Head,
+    Torso,
+    LeftArm,
+    RightArm,
+    LeftLeg,
+    RightLeg,
+}
+
+pub(crate) const BODY_PARTS: [BodyPart; 6] = [
+    BodyPart::Head,
+    BodyPart::Torso,
+    BodyPart::LeftArm,
+    BodyPart::RightArm,
+    BodyPart::LeftLeg,
+    BodyPart::RightLeg,
+];
+
+impl BodyPart {
+    /// The amount of damage a part can take before it is destroyed.
+    pub(crate) fn max_health(&self) -> f32 {
+        match self {
+            BodyPart::Head => 25.,
+            BodyPart::Torso => 50.,
+            BodyPart::LeftArm | BodyPart::RightArm => 30.,
+            BodyPart::LeftLeg | BodyPart::RightLeg => 35.,
+        }
+    }
+
+    /// Whether destroying this part is fatal.
+    pub(crate) fn is_vital(&self) -> bool {
+        matches!(self, BodyPart::Head | BodyPart::Torso)
+    }
+
+    /// How large a target this part presents in a fight, relative to the rest of the body.
+    pub(crate) fn size(&self) -> f32 {
+        match self {
+            BodyPart::Head => 10.,
+            BodyPart::Torso => 40.,
+            BodyPart::LeftArm | BodyPart::RightArm => 12.,
+            BodyPart::LeftLeg | BodyPart::RightLeg => 13.,
+        }
+    }
+
+    /// How much blood per second a wound on this part bleeds, per point of damage.
+    fn bleed_factor(&self) -> f32 {
+        match self {
+            BodyPart::Head => 0.08,
+            BodyPart::Torso => 0.1,
+            BodyPart::LeftArm | BodyPart::RightArm => 0.05,
+            BodyPart::LeftLeg | BodyPart::RightLeg => 0.06,
+        }
+    }
+}
+
+impl fmt::Display for BodyPart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BodyPart::Head => "head",
+            BodyPart::Torso => "torso",
+            BodyPart::LeftArm => "left arm",
+            BodyPart::RightArm => "right arm",
+            BodyPart::LeftLeg => "left leg",
+            BodyPart::RightLeg => "right leg",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Wound {
+    part: BodyPart,
+    damage: f32,
+    /// Blood lost per second.
+    bleeding: f32,
+}
+
+impl fmt::Display for Wound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fraction = self.damage / self.part.max_health();
+        let severity = if fraction >= 1. {
+            "destroyed"
+        } else if fraction >= 0.6 {
+            "mangled"
+        } else if fraction >= 0.3 {
+            "broken"
+        } else {
+            "bruised"
+        };
+        write!(f, "{}: {}", self.part, severity)?;
+        if self.bleeding > 0. {
+            write!(f, " (bleeding)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Health {
+    wounds: Vec<Wound>,
+    blood: f32,
+    conscious: bool,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            wounds: Vec::new(),
+            blood: MAX_BLOOD,
+            conscious: true,
+        }
+    }
+}
+
+impl Health {
+    /// Wounds the given body part. Damage to an already wounded part worsens the existing wound.
+    pub(crate) fn wound(&mut self, part: BodyPart, damage: f32) {
+        let bleeding = damage * part.bleed_factor();
+        if let Some(wound) = self.wounds.iter_mut().find(|w| w.part == part) {
+            wound.damage = (wound.damage + damage).min(part.max_health());
+            wound.bleeding += bleeding;
+        } else {
+            self.wounds.push(Wound {
+                part,
+                damage: damage.min(part.max_health()),
+                bleeding,
+            });
+        }
+    }
+
+    pub(crate) fn damage_to(&self, part: BodyPart) -> f32 {
+        self.wounds
+            .iter()
+            .filter(|w| w.part == part)
+            .map(|w| w.damage)
+            .sum()
+    }
+
+    pub(crate) fn is_destroyed(&self, part: BodyPart) -> bool {
+        self.damage_to(part) >= part.max_health()
+    }
+
+    pub(crate) fn is_dead(&self) -> bool {
+        self.blood <= 0.
+            || BODY_PARTS
+                .iter()
+                .any(|part| part.is_vital() && self.is_destroyed(*part))
+    }
+
+    /// Whether the dwarf is both alive and awake, and thus able to act.
+    pub(crate) fn can_act(&self) -> bool {
+        self.conscious && !self.is_dead()
+    }
+
+    pub(crate) fn wounds(&self) -> &[Wound] {
+        &self.wounds
+    }
+
+    fn pain(&self) -> f32 {
+        self.wounds.iter().map(|w| w.damage).sum()
+    }
+
+    /// Bleeds, clots and heals every wound for the given number of seconds, then updates
+    /// whether the body is conscious. Returns whether that changed.
+    fn update(&mut self, delta: f32) -> bool {
+        let mut blood_loss = 0.;
+        for wound in self.wounds.iter_mut() {
+            blood_loss += wound.bleeding * delta;
+            wound.bleeding -= wound.bleeding * CLOTTING_RATE * delta;
+            // Clotting slows down as the bleeding does, so it would never quite stop by
+            // itself.
+            if wound.bleeding < MIN_BLEEDING {
+                wound.bleeding = 0.;
+            }
+            // Destroyed parts never heal.
+            if wound.damage < wound.part.max_health() {
+                wound.damage -= HEALING_RATE * delta;
+            }
+        }
+        self.wounds.retain(|w| w.damage > 0.);
+
+        if blood_loss > 0. {
+            self.blood -= blood_loss;
+        } else {
+            self.blood = (self.blood + BLOOD_REGENERATION * delta).min(MAX_BLOOD);
+        }
+
+        let conscious = self.pain() < PAIN_THRESHOLD && self.blood > BLOOD_THRESHOLD;
+        let changed = conscious != self.conscious;
+        self.conscious = conscious;
+        changed
+    }
+
+    /// Applies the damage from landing at the given speed. Legs take the brunt of the
+    /// impact. Returns whether the fall caused any injury.
+    pub(crate) fn apply_fall_damage(&mut self, impact_speed: f32) -> bool {
+        let damage = (impact_speed - SAFE_FALL_SPEED) * FALL_DAMAGE_PER_SPEED;
+        if damage <= 0. {
+            return false;
+        }
+
+        for (part, share) in &[
+            (BodyPart::LeftLeg, 0.4),
+            (BodyPart::RightLeg, 0.4),
+            (BodyPart::Torso, 0.15),
+            (BodyPart::Head, 0.05),
+        ] {
+            self.wound(*part, damage * share);
+        }
+        true
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.is_dead() {
+            "dead"
+        } else if self.conscious {
+            "conscious"
+        } else {
+            "unconscious"
+        };
+        write!(f, "{}, blood {:.0}%", state, self.blood / MAX_BLOOD * 100.)
+    }
+}
+
+/// Bleeds, clots and heals every wound, then updates whether each body is conscious.
+fn update_health(mut query: Query<(&Name, &mut Health)>) {
+    for (name, mut health) in query.iter_mut() {
+        if health.update(TICK_SECONDS) {
+            if health.conscious {
+                debug!("{} has regained consciousness", name);
+            } else {
+                debug!("{} has passed out", name);
+            }
+        }
+    }
+}
+
+/// Replaces the dead with their corpses, dropping whatever weapon they held. Dwarves who
+/// witness the death of another dwarf will remember it.
+fn handle_deaths(
+    commands: &mut Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut selected_dwarf: ResMut<SelectedDwarf>,
+    dead_query: Query<(
+        Entity,
+        &Name,
+        &Health,
+        &Transform,
+        Option<&Dwarf>,
+        Option<&Fighter>,
+    )>,
+    mut witness_query: Query<(Entity, &Transform, &mut Thoughts)>,
+) {
+    for (entity, name, health, transform, dwarf, fighter) in dead_query.iter() {
+        if !health.is_dead() {
+            continue;
+        }
+
+        debug!("{} has died", name);
+        if dwarf.is_some() {
+            for (witness, witness_transform, mut thoughts) in witness_query.iter_mut() {
+                if witness != entity
+                    && witness_transform
+                        .translation
+                        .distance(transform.translation)
+                        <= WITNESS_RADIUS
+                {
+                    thoughts.add(ThoughtKind::SawFriendDie);
+                }
+            }
+        }
+
+        if selected_dwarf.dwarf == Some(entity) {
+            selected_dwarf.dwarf = None;
+        }
+        commands.despawn(entity);
+        spawn_item(
+            ItemKind::Corpse(name.to_string()),
+            transform.translation,
+            commands,
+            &mut meshes,
+            &mut materials,
+        );
+        if let Some(weapon) = fighter.and_then(|f| f.weapon()) {
+            spawn_item(
+                ItemKind::Weapon(weapon),
+                transform.translation + Vec3::new(0., 1., 0.),
+                commands,
+                &mut meshes,
+                &mut materials,
+            );
+        }
+    }
+}
+
+pub(crate) struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(SIMULATION, update_health.system())
+            .add_system_to_stage(SIMULATION, handle_deaths.system());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs the body for the given number of seconds, a tick at a time.
+    fn run(health: &mut Health, seconds: f32) {
+        for _ in 0..(seconds / TICK_SECONDS) as usize {
+            health.update(TICK_SECONDS);
+        }
+    }
+
+    #[test]
+    fn wounds_to_the_same_part_worsen() {
+        let mut health = Health::default();
+        health.wound(BodyPart::LeftArm, 10.);
+        health.wound(BodyPart::LeftArm, 10.);
+        assert_eq!(health.wounds().len(), 1);
+        assert_eq!(health.damage_to(BodyPart::LeftArm), 20.);
+        health.wound(BodyPart::LeftArm, 100.);
+        assert_eq!(
+            health.damage_to(BodyPart::LeftArm),
+            BodyPart::LeftArm.max_health()
+        );
+        assert!(health.is_destroyed(BodyPart::LeftArm));
+        assert!(!health.is_dead());
+    }
+
+    #[test]
+    fn destroying_a_vital_part_is_fatal() {
+        let mut health = Health::default();
+        health.wound(BodyPart::Head, BodyPart::Head.max_health());
+        assert!(health.is_dead());
+        assert!(!health.can_act());
+    }
+
+    #[test]
+    fn safe_falls_do_no_damage() {
+        let mut health = Health::default();
+        assert!(!health.apply_fall_damage(SAFE_FALL_SPEED));
+        assert!(health.wounds().is_empty());
+        assert!(health.apply_fall_damage(SAFE_FALL_SPEED + 4.));
+        assert_eq!(health.damage_to(BodyPart::LeftLeg), 8.);
+        assert_eq!(health.damage_to(BodyPart::Head), 1.);
+    }
+
+    #[test]
+    fn bleeding_stops_and_blood_regenerates() {
+        let mut health = Health::default();
+        // A destroyed part never heals, so the wound stays open until it clots.
+        health.wound(BodyPart::LeftArm, BodyPart::LeftArm.max_health());
+        run(&mut health, 10.);
+        assert!(health.blood < MAX_BLOOD);
+
+        run(&mut health, 110.);
+        assert_eq!(health.wounds()[0].bleeding, 0.);
+        let clotted = health.blood;
+        run(&mut health, 10.);
+        assert!(health.blood > clotted);
+        run(&mut health, 600.);
+        assert_eq!(health.blood, MAX_BLOOD);
+    }
+
+    #[test]
+    fn pain_knocks_out_until_the_wounds_heal() {
+        let mut health = Health::default();
+        health.wound(BodyPart::LeftLeg, 31.);
+        health.wound(BodyPart::RightLeg, 31.);
+        for wound in health.wounds.iter_mut() {
+            wound.bleeding = 0.;
+        }
+        assert!(health.update(TICK_SECONDS));
+        assert!(!health.can_act());
+        run(&mut health, 10.);
+        assert!(health.can_act());
+    }
+
+    #[test]
+    fn losing_all_blood_is_fatal() {
+        let mut health = Health::default();
+        health.blood = 1.;
+        health.wound(BodyPart::Torso, 10.);
+        run(&mut health, 5.);
+        assert!(health.is_dead());
+    }
+}
@@ -0,0 +1,156 @@
+//! An overlay which shows the details of the currently selected dwarf.
+use std::fmt::Write;
+
+use bevy::{
+    ecs::{Commands, Query, Res, With},
+    prelude::{
+        AppBuilder, AssetServer, CameraUiBundle, Color, IntoSystem, Plugin, PositionType, Rect,
+        Style, Text, TextBundle, TextStyle, Val,
+    },
+};
+
+use crate::combat::{CombatLog, Drafted, Fighter};
+use crate::daylight::Daylight;
+use crate::dwarf::{Dwarf, Name, SelectedDwarf};
+use crate::health::Health;
+use crate::mood::{MentalState, Mood, Personality, Thoughts};
+use crate::simulation::SimulationClock;
+use crate::vegetation::Chopping;
+
+const FONT: &str = "fonts/DejaVuSansMono.ttf";
+const FONT_SIZE: f32 = 16.;
+
+/// Marker for the text node that holds the inspector's contents.
+struct InspectorText;
+
+fn setup(commands: &mut Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(CameraUiBundle::default())
+        .spawn(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    left: Val::Px(5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load(FONT),
+                style: TextStyle {
+                    font_size: FONT_SIZE,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(InspectorText);
+}
+
+/// Rewrites the inspector text to describe the state of the simulation and the currently
+/// selected dwarf, followed by the most recent events from the combat log.
+#[allow(clippy::type_complexity)]
+fn update_inspector(
+    clock: Res<SimulationClock>,
+    daylight: Res<Daylight>,
+    selected_dwarf: Res<SelectedDwarf>,
+    combat_log: Res<CombatLog>,
+    dwarf_query: Query<(
+        &Dwarf,
+        &Name,
+        &Personality,
+        &Mood,
+        &MentalState,
+        &Thoughts,
+        &Health,
+        &Fighter,
+        Option<&Drafted>,
+        Option<&Chopping>,
+    )>,
+    mut text_query: Query<&mut Text, With<InspectorText>>,
+) {
+    let mut value = String::new();
+    writeln!(value, "{}", clock).unwrap();
+    writeln!(value, "{}", daylight).unwrap();
+    match selected_dwarf
+        .dwarf
+        .and_then(|entity| dwarf_query.get(entity).ok())
+    {
+        Some((
+            dwarf,
+            name,
+            personality,
+            mood,
+            mental_state,
+            thoughts,
+            health,
+            fighter,
+            drafted,
+            chopping,
+        )) => {
+            let traits = personality
+                .traits()
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(value, "{}", name).unwrap();
+            writeln!(value, "Personality: {}", traits).unwrap();
+            writeln!(value, "Mood: {}", mood).unwrap();
+            writeln!(value, "State: {}", mental_state).unwrap();
+            writeln!(value, "Health: {}", health).unwrap();
+            for wound in health.wounds() {
+                writeln!(value, "  - {}", wound).unwrap();
+            }
+            writeln!(value, "Thoughts:").unwrap();
+            for thought in thoughts.iter() {
+                writeln!(value, "  - {}", thought.kind()).unwrap();
+            }
+            let weapon = fighter
+                .weapon()
+                .map_or("nothing".to_string(), |w| w.to_string());
+            writeln!(value, "Wielding: {}", weapon).unwrap();
+            writeln!(value, "Skills: {}", fighter.skills()).unwrap();
+            if chopping.is_some() {
+                writeln!(value, "Chopping wood").unwrap();
+            } else if dwarf.is_asleep() && drafted.is_none() {
+                writeln!(value, "Asleep").unwrap();
+            }
+            if drafted.is_some() {
+                writeln!(
+                    value,
+                    "Drafted (R to release, click a creature to attack it)"
+                )
+                .unwrap();
+            } else {
+                writeln!(value, "Press R to draft").unwrap();
+            }
+        }
+        None => {
+            writeln!(value, "Click on a dwarf to inspect them.").unwrap();
+        }
+    }
+
+    writeln!(value, "Combat log:").unwrap();
+    for entry in combat_log.iter() {
+        writeln!(value, "  - {}", entry).unwrap();
+    }
+
+    for mut text in text_query.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+pub(crate) struct InspectorPlugin;
+
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(setup.system())
+            .add_system(update_inspector.system());
+    }
+}
@@ -0,0 +1,111 @@
+//! Loose items lying around the world.
+use std::fmt;
+
+use bevy::{
+    app::{stage, EventReader, Events},
+    ecs::{Commands, Entity, Local, Query, Res, ResMut, With},
+    math::Vec3,
+    pbr::PbrBundle,
+    prelude::{
+        shape, trace, AppBuilder, Assets, Color, IntoSystem, Mesh, Plugin, StandardMaterial,
+        Transform,
+    },
+};
+use bevy_rapier3d::rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder};
+
+use crate::combat::Weapon;
+use crate::terrain::TerrainGenerated;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ItemKind {
+    /// The remains of a creature, named after whoever it used to be.
+    Corpse(String),
+    Weapon(Weapon),
+    /// A log, named after the plant it was chopped from.
+    Wood(String),
+}
+
+impl ItemKind {
+    fn size(&self) -> Vec3 {
+        match self {
+            ItemKind::Corpse(_) => Vec3::new(1., 0.3, 0.5),
+            ItemKind::Weapon(_) => Vec3::new(0.8, 0.15, 0.15),
+            ItemKind::Wood(_) => Vec3::new(1.2, 0.3, 0.3),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            ItemKind::Corpse(_) => Color::rgb(0.5, 0.45, 0.4),
+            ItemKind::Weapon(_) => Color::rgb(0.6, 0.6, 0.65),
+            ItemKind::Wood(_) => Color::rgb(0.55, 0.35, 0.2),
+        }
+    }
+}
+
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemKind::Corpse(name) => write!(f, "corpse of {}", name),
+            ItemKind::Weapon(weapon) => write!(f, "{}", weapon),
+            ItemKind::Wood(plant) => write!(f, "{} log", plant),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Item {
+    pub(crate) kind: ItemKind,
+}
+
+/// Spawns an item as a physics-enabled box in the world.
+pub(crate) fn spawn_item(
+    kind: ItemKind,
+    position: Vec3,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+) -> Entity {
+    trace!("Spawning {} at {:?}", kind, position);
+    let size = kind.size();
+
+    let entity = commands
+        .spawn(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+            material: materials.add(kind.color().into()),
+            transform: Transform::from_translation(position),
+            ..Default::default()
+        })
+        .with(Item { kind })
+        .current_entity()
+        .unwrap();
+    let rigid_body =
+        RigidBodyBuilder::new_dynamic().translation(position.x, position.y, position.z);
+    let collider = ColliderBuilder::cuboid(size.x / 2., size.y / 2., size.z / 2.)
+        .user_data(entity.to_bits() as u128);
+    commands.insert(entity, (rigid_body, collider));
+    entity
+}
+
+/// Clears away everything left lying around whenever the world is generated again.
+fn clear_items(
+    commands: &mut Commands,
+    mut generated_reader: Local<EventReader<TerrainGenerated>>,
+    generated_events: Res<Events<TerrainGenerated>>,
+    item_query: Query<Entity, With<Item>>,
+) {
+    if generated_reader.iter(&generated_events).last().is_none() {
+        return;
+    }
+    for entity in item_query.iter() {
+        commands.despawn(entity);
+    }
+}
+
+pub(crate) struct ItemPlugin;
+
+impl Plugin for ItemPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(stage::PRE_UPDATE, clear_items.system());
+    }
+}
@@ -0,0 +1,229 @@
+//! A Dwarf Fortress/Rimworld-like game.
+//!
+//! The game itself is started by [`run`]. The library exists so that integration tests can
+//! build the same app headless, with [`headless_app`].
+extern crate bevy;
+extern crate bevy_mod_picking;
+extern crate bevy_rapier3d;
+#[cfg(target_arch = "wasm32")]
+extern crate bevy_webgl2;
+extern crate building_blocks;
+extern crate colonize_common;
+extern crate rand;
+
+mod camera;
+mod combat;
+mod creature;
+#[cfg(not(target_arch = "wasm32"))]
+mod data;
+mod daylight;
+mod dwarf;
+#[cfg(not(target_arch = "wasm32"))]
+mod headless;
+mod health;
+mod inspector;
+mod item;
+mod mood;
+mod navigation;
+mod simulation;
+mod terrain;
+mod vegetation;
+
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::prelude::AppBuilder;
+use bevy::{
+    app::{startup_stage, PluginGroupBuilder},
+    ecs::{Commands, IntoSystem, SystemStage},
+    math::Vec3,
+    prelude::{App, Camera3dBundle, PluginGroup, Transform},
+};
+#[cfg(target_arch = "wasm32")]
+use bevy::{
+    log::{Level, LogSettings},
+    window::WindowDescriptor,
+};
+use bevy_mod_picking::{DebugPickingPlugin, InteractablePickingPlugin, PickSource, PickingPlugin};
+use bevy_rapier3d::physics::RapierPhysicsPlugin;
+
+use camera::fps::{CameraMovementPlugin, CameraState};
+use colonize_pbr::PbrPlugin;
+use combat::CombatPlugin;
+use creature::CreaturePlugin;
+use daylight::{DaylightPlugin, SkyPlugin};
+use dwarf::DwarfPlugin;
+#[cfg(not(target_arch = "wasm32"))]
+pub use headless::{ColonyReport, CreatureReport, DwarfReport, HeadlessSettings, ItemReport};
+#[cfg(not(target_arch = "wasm32"))]
+use headless::{HeadlessPlugin, HeadlessPlugins};
+use health::HealthPlugin;
+use inspector::InspectorPlugin;
+use item::ItemPlugin;
+use mood::MoodPlugin;
+use navigation::NavigationPlugin;
+use simulation::SimulationPlugin;
+use terrain::{TerrainPlugin, TERRAIN};
+use vegetation::VegetationPlugin;
+
+pub struct DefaultPlugins;
+
+impl PluginGroup for DefaultPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(bevy::log::LogPlugin::default());
+        group.add(bevy::reflect::ReflectPlugin::default());
+        group.add(bevy::core::CorePlugin::default());
+        group.add(bevy::transform::TransformPlugin::default());
+        group.add(bevy::diagnostic::DiagnosticsPlugin::default());
+        group.add(bevy::input::InputPlugin::default());
+        group.add(bevy::window::WindowPlugin::default());
+        group.add(bevy::asset::AssetPlugin::default());
+        group.add(bevy::scene::ScenePlugin::default());
+
+        group.add(bevy::render::RenderPlugin::default());
+
+        group.add(bevy::sprite::SpritePlugin::default());
+
+        group.add(bevy::pbr::PbrPlugin::default());
+
+        group.add(bevy::ui::UiPlugin::default());
+
+        group.add(bevy::text::TextPlugin::default());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        group.add(bevy::audio::AudioPlugin::default());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        group.add(bevy_gilrs::GilrsPlugin::default());
+
+        group.add(bevy::gltf::GltfPlugin::default());
+
+        group.add(bevy::winit::WinitPlugin::default());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        group.add(bevy::wgpu::WgpuPlugin::default());
+
+        group.add(PbrPlugin);
+
+        #[cfg(target_arch = "wasm32")]
+        group.add(bevy_webgl2::WebGL2Plugin);
+    }
+}
+
+/// The plugins which make up the simulation itself. None of these need a window or a
+/// renderer, so they're shared between the interactive and headless configurations.
+pub struct GamePlugins;
+
+impl PluginGroup for GamePlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        // The simulation stage must exist before any plugin adds systems to it.
+        group.add(SimulationPlugin);
+        group.add(DaylightPlugin);
+        group.add(DwarfPlugin);
+        group.add(MoodPlugin);
+        group.add(HealthPlugin);
+        group.add(NavigationPlugin);
+        group.add(CreaturePlugin);
+        group.add(CombatPlugin);
+        group.add(ItemPlugin);
+        group.add(TerrainPlugin);
+        group.add(VegetationPlugin);
+        group.add(RapierPhysicsPlugin);
+    }
+}
+
+/// Builds the app for a headless run, without a window or a renderer. Once the requested
+/// number of ticks have been simulated, it adds a [`ColonyReport`] resource and exits.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn headless_app(settings: HeadlessSettings) -> AppBuilder {
+    let mut app = App::build();
+    app.add_startup_stage_after(startup_stage::PRE_STARTUP, TERRAIN, SystemStage::parallel())
+        .add_resource(settings)
+        .add_plugins(HeadlessPlugins)
+        .add_plugins(GamePlugins)
+        .add_plugin(HeadlessPlugin);
+    app
+}
+
+/// Runs the game, or a headless simulation if `--headless` was passed.
+pub fn run() {
+    let default_plugins = DefaultPlugins;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Some(settings) = HeadlessSettings::from_args(std::env::args().skip(1)) {
+            headless_app(settings).run();
+            return;
+        }
+
+        App::build()
+            .add_startup_stage_after(startup_stage::PRE_STARTUP, TERRAIN, SystemStage::parallel())
+            .add_plugins(default_plugins)
+            .add_plugins(GamePlugins)
+            .add_plugin(InspectorPlugin)
+            .add_plugin(CameraMovementPlugin)
+            .add_plugin(SkyPlugin)
+            .add_plugin(PickingPlugin)
+            .add_plugin(InteractablePickingPlugin)
+            .add_plugin(DebugPickingPlugin)
+            .add_startup_system(setup.system())
+            .add_system(toggle_cursor.system())
+            .run();
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        App::build()
+            // Silence bevy_webgl2
+            .add_resource(LogSettings {
+                filter: "bevy_webgl2=warn".into(),
+                level: Level::INFO,
+            })
+            .add_resource(WindowDescriptor {
+                width: 720.,
+                height: 480.,
+                canvas: Some("#bevy-canvas".to_string()),
+                ..Default::default()
+            })
+            .add_startup_stage_after(startup_stage::STARTUP, TERRAIN, SystemStage::parallel())
+            .add_plugins(default_plugins)
+            .add_plugins(GamePlugins)
+            .add_plugin(InspectorPlugin)
+            .add_plugin(CameraMovementPlugin)
+            .add_plugin(SkyPlugin)
+            .add_plugin(PickingPlugin)
+            .add_plugin(InteractablePickingPlugin)
+            .add_plugin(DebugPickingPlugin)
+            .add_startup_system(setup.system())
+            .run();
+    }
+}
+
+// Setup a simple 3D scene.
+fn setup(commands: &mut Commands) {
+    // Add entities to the world. The scene is lit by the `SkyPlugin`.
+    commands
+        // Camera
+        .spawn(Camera3dBundle {
+            transform: Transform::from_translation(Vec3::new(32.0, 100.0, 32.0))
+                .looking_at(Vec3::default(), Vec3::unit_y()),
+            ..Default::default()
+        })
+        .with(CameraState::default())
+        .with(PickSource::default());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::{
+    ecs::{Res, ResMut},
+    input::Input,
+    prelude::KeyCode,
+    window::Windows,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Toggles the cursor's visibility and lock mode when the space bar is pressed.
+fn toggle_cursor(input: Res<Input<KeyCode>>, mut windows: ResMut<Windows>) {
+    let window = windows.get_primary_mut().unwrap();
+    if input.just_pressed(KeyCode::Space) {
+        window.set_cursor_lock_mode(!window.cursor_locked());
+        window.set_cursor_visibility(!window.cursor_visible());
+    }
+}
@@ -0,0 +1,473 @@
+//! The inner lives of dwarves.
+//!
+//! Every dwarf has a fixed set of personality traits, a log of recent thoughts
+//! ("found some gold", "saw a friend die") and a mood value that is derived from
+//! both. Mood affects how hard a dwarf works, and a sufficiently miserable dwarf
+//! may suffer a mental break, during which they refuse to work at all.
+use std::collections::VecDeque;
+use std::fmt;
+
+use bevy::{
+    ecs::Query,
+    prelude::{debug, AppBuilder, IntoSystem, Plugin},
+};
+use rand::{seq::SliceRandom, Rng};
+
+use crate::dwarf::Name;
+use crate::simulation::{SIMULATION, TICK_SECONDS};
+
+/// The maximum number of thoughts a dwarf remembers at once. When a new thought
+/// arrives and the log is full, the oldest thought is forgotten.
+const MAX_THOUGHTS: usize = 16;
+/// The number of personality traits each dwarf is born with.
+const NUM_TRAITS: usize = 2;
+/// Mood below which a dwarf is at risk of a mental break.
+const BREAK_THRESHOLD: f32 = -50.;
+/// Chance per second that a dwarf below the break threshold actually breaks.
+const BREAK_CHANCE_PER_SECOND: f32 = 0.05;
+/// How long a mental break lasts, in seconds.
+const BREAK_DURATION: f32 = 30.;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum PersonalityTrait {
+    Cheerful,
+    Gloomy,
+    Hardworking,
+    Lazy,
+    Brave,
+    Nervous,
+    Greedy,
+}
+
+const ALL_TRAITS: [PersonalityTrait; 7] = [
+    PersonalityTrait::Cheerful,
+    PersonalityTrait::Gloomy,
+    PersonalityTrait::Hardworking,
+    PersonalityTrait::Lazy,
+    PersonalityTrait::Brave,
+    PersonalityTrait::Nervous,
+    PersonalityTrait::Greedy,
+];
+
+impl PersonalityTrait {
+    /// Traits which can't both be present on the same dwarf.
+    fn conflicts_with(&self, other: &PersonalityTrait) -> bool {
+        use PersonalityTrait::*;
+        matches!(
+            (self, other),
+            (Cheerful, Gloomy)
+                | (Gloomy, Cheerful)
+                | (Hardworking, Lazy)
+                | (Lazy, Hardworking)
+                | (Brave, Nervous)
+                | (Nervous, Brave)
+        )
+    }
+}
+
+impl fmt::Display for PersonalityTrait {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PersonalityTrait::Cheerful => "cheerful",
+            PersonalityTrait::Gloomy => "gloomy",
+            PersonalityTrait::Hardworking => "hardworking",
+            PersonalityTrait::Lazy => "lazy",
+            PersonalityTrait::Brave => "brave",
+            PersonalityTrait::Nervous => "nervous",
+            PersonalityTrait::Greedy => "greedy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Personality {
+    traits: Vec<PersonalityTrait>,
+}
+
+impl Personality {
+    /// Rolls a random, non-contradictory set of personality traits.
+    pub(crate) fn random<R: Rng>(rng: &mut R) -> Self {
+        let mut candidates = ALL_TRAITS.to_vec();
+        candidates.shuffle(rng);
+        let mut traits: Vec<PersonalityTrait> = Vec::with_capacity(NUM_TRAITS);
+        for candidate in candidates {
+            if traits.len() == NUM_TRAITS {
+                break;
+            }
+            if !traits.iter().any(|t| t.conflicts_with(&candidate)) {
+                traits.push(candidate);
+            }
+        }
+        Self { traits }
+    }
+
+    pub(crate) fn has(&self, personality_trait: PersonalityTrait) -> bool {
+        self.traits.contains(&personality_trait)
+    }
+
+    pub(crate) fn traits(&self) -> &[PersonalityTrait] {
+        &self.traits
+    }
+
+    /// The mood a dwarf with this personality settles at when nothing is on their mind.
+    fn baseline_mood(&self) -> f32 {
+        let mut baseline = 0.;
+        if self.has(PersonalityTrait::Cheerful) {
+            baseline += 15.;
+        }
+        if self.has(PersonalityTrait::Gloomy) {
+            baseline -= 15.;
+        }
+        baseline
+    }
+
+    /// Multiplier applied to the effect of a thought on this dwarf's mood.
+    fn sensitivity(&self, kind: ThoughtKind) -> f32 {
+        let mut sensitivity = 1.;
+        match kind {
+            ThoughtKind::FoundGold if self.has(PersonalityTrait::Greedy) => sensitivity *= 2.,
+            ThoughtKind::Fell | ThoughtKind::WasInjured => {
+                if self.has(PersonalityTrait::Brave) {
+                    sensitivity *= 0.5;
+                }
+                if self.has(PersonalityTrait::Nervous) {
+                    sensitivity *= 1.5;
+                }
+            }
+            _ => {}
+        }
+        sensitivity
+    }
+
+    /// Multiplier applied to the chance of a miserable dwarf with this personality breaking.
+    fn fragility(&self) -> f32 {
+        let mut fragility = 1.;
+        if self.has(PersonalityTrait::Brave) {
+            fragility *= 0.5;
+        }
+        if self.has(PersonalityTrait::Nervous) {
+            fragility *= 2.;
+        }
+        fragility
+    }
+
+    /// Multiplier applied to how hard a dwarf with this personality works.
+    fn work_ethic(&self) -> f32 {
+        let mut work_ethic = 1.;
+        if self.has(PersonalityTrait::Hardworking) {
+            work_ethic *= 1.25;
+        }
+        if self.has(PersonalityTrait::Lazy) {
+            work_ethic *= 0.75;
+        }
+        work_ethic
+    }
+}
+
+/// Something that happened to a dwarf which they keep thinking about for a while.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ThoughtKind {
+    FoundGold,
+    Fell,
+    WasInjured,
+    SawFriendDie,
+    HadAMentalBreak,
+    SleptOnTheGround,
+}
+
+impl ThoughtKind {
+    /// The effect this thought has on a dwarf's mood while it is fresh.
+    fn mood_effect(&self) -> f32 {
+        match self {
+            ThoughtKind::FoundGold => 10.,
+            ThoughtKind::Fell => -10.,
+            ThoughtKind::WasInjured => -20.,
+            ThoughtKind::SawFriendDie => -40.,
+            ThoughtKind::HadAMentalBreak => 20.,
+            ThoughtKind::SleptOnTheGround => -5.,
+        }
+    }
+
+    /// How long the thought stays on a dwarf's mind, in seconds.
+    fn duration(&self) -> f32 {
+        match self {
+            ThoughtKind::FoundGold => 60.,
+            ThoughtKind::Fell => 60.,
+            ThoughtKind::WasInjured => 120.,
+            ThoughtKind::SawFriendDie => 600.,
+            ThoughtKind::HadAMentalBreak => 120.,
+            ThoughtKind::SleptOnTheGround => 300.,
+        }
+    }
+}
+
+impl fmt::Display for ThoughtKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ThoughtKind::FoundGold => "found some gold",
+            ThoughtKind::Fell => "took a fall",
+            ThoughtKind::WasInjured => "was injured",
+            ThoughtKind::SawFriendDie => "saw a friend die",
+            ThoughtKind::HadAMentalBreak => "let off some steam",
+            ThoughtKind::SleptOnTheGround => "slept on the bare ground",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Thought {
+    kind: ThoughtKind,
+    /// How long ago the thought occurred, in seconds.
+    age: f32,
+}
+
+impl Thought {
+    pub(crate) fn kind(&self) -> ThoughtKind {
+        self.kind
+    }
+
+    /// The current effect of the thought on mood. Thoughts fade linearly as they age.
+    fn mood_effect(&self) -> f32 {
+        let freshness = 1. - (self.age / self.kind.duration()).min(1.);
+        self.kind.mood_effect() * freshness
+    }
+}
+
+/// A log of a dwarf's recent thoughts, newest first.
+#[derive(Debug, Default)]
+pub(crate) struct Thoughts {
+    log: VecDeque<Thought>,
+}
+
+impl Thoughts {
+    /// Records a new thought. Thinking the same thing twice refreshes the existing
+    /// thought rather than stacking its effect.
+    pub(crate) fn add(&mut self, kind: ThoughtKind) {
+        self.log.retain(|t| t.kind != kind);
+        self.log.push_front(Thought { kind, age: 0. });
+        self.log.truncate(MAX_THOUGHTS);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Thought> {
+        self.log.iter()
+    }
+}
+
+/// How a dwarf is feeling, from -100 (miserable) to 100 (ecstatic).
+#[derive(Debug, Default)]
+pub(crate) struct Mood {
+    value: f32,
+}
+
+impl Mood {
+    /// The mood of a dwarf with the given personality and thoughts.
+    fn of(personality: &Personality, thoughts: &Thoughts) -> Self {
+        let thought_effects: f32 = thoughts
+            .iter()
+            .map(|t| t.mood_effect() * personality.sensitivity(t.kind))
+            .sum();
+        Self {
+            value: (personality.baseline_mood() + thought_effects).clamp(-100., 100.),
+        }
+    }
+
+    /// Multiplier applied to the effort a dwarf puts into their work.
+    pub(crate) fn work_modifier(&self) -> f32 {
+        // Scale linearly from 0.5x when miserable to 1.5x when ecstatic.
+        1. + self.value / 200.
+    }
+}
+
+impl fmt::Display for Mood {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = if self.value >= 50. {
+            "ecstatic"
+        } else if self.value >= 15. {
+            "happy"
+        } else if self.value > -15. {
+            "content"
+        } else if self.value > BREAK_THRESHOLD {
+            "unhappy"
+        } else {
+            "miserable"
+        };
+        write!(f, "{} ({:.0})", description, self.value)
+    }
+}
+
+/// Whether a dwarf is of sound mind, or in the middle of a mental break.
+#[derive(Debug)]
+pub(crate) enum MentalState {
+    Stable,
+    /// The dwarf refuses to work until the break has run its course.
+    Break { remaining: f32 },
+}
+
+impl MentalState {
+    pub(crate) fn is_breaking(&self) -> bool {
+        matches!(self, MentalState::Break { .. })
+    }
+}
+
+impl Default for MentalState {
+    fn default() -> Self {
+        MentalState::Stable
+    }
+}
+
+impl fmt::Display for MentalState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MentalState::Stable => write!(f, "stable"),
+            MentalState::Break { remaining } => write!(f, "mental break ({:.0}s left)", remaining),
+        }
+    }
+}
+
+/// Ages every dwarf's thoughts and forgets the ones that have faded completely.
+fn age_thoughts(mut query: Query<&mut Thoughts>) {
+    for mut thoughts in query.iter_mut() {
+        for thought in thoughts.log.iter_mut() {
+            thought.age += TICK_SECONDS;
+        }
+        thoughts.log.retain(|t| t.age < t.kind.duration());
+    }
+}
+
+/// Recomputes every dwarf's mood from their personality and recent thoughts.
+fn update_mood(mut query: Query<(&Personality, &Thoughts, &mut Mood)>) {
+    for (personality, thoughts, mut mood) in query.iter_mut() {
+        *mood = Mood::of(personality, thoughts);
+    }
+}
+
+/// Sends miserable dwarves into a mental break, and brings them back out once it's over.
+fn mental_breaks(mut query: Query<(&Name, &Personality, &Mood, &mut Thoughts, &mut MentalState)>) {
+    let mut rng = rand::thread_rng();
+    for (name, personality, mood, mut thoughts, mut mental_state) in query.iter_mut() {
+        let recovered = match &mut *mental_state {
+            MentalState::Stable => false,
+            MentalState::Break { remaining } => {
+                *remaining -= TICK_SECONDS;
+                *remaining <= 0.
+            }
+        };
+
+        if recovered {
+            debug!("Dwarf {} has recovered from their mental break", name);
+            *mental_state = MentalState::Stable;
+            thoughts.add(ThoughtKind::HadAMentalBreak);
+        } else if !mental_state.is_breaking()
+            && mood.value < BREAK_THRESHOLD
+            && rng.gen::<f32>() < BREAK_CHANCE_PER_SECOND * personality.fragility() * TICK_SECONDS
+        {
+            debug!("Dwarf {} is having a mental break", name);
+            *mental_state = MentalState::Break {
+                remaining: BREAK_DURATION,
+            };
+        }
+    }
+}
+
+/// Combined multiplier applied to a dwarf's work, taking both mood and personality into account.
+pub(crate) fn work_speed(personality: &Personality, mood: &Mood) -> f32 {
+    personality.work_ethic() * mood.work_modifier()
+}
+
+pub(crate) struct MoodPlugin;
+
+impl Plugin for MoodPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(SIMULATION, age_thoughts.system())
+            .add_system_to_stage(SIMULATION, update_mood.system())
+            .add_system_to_stage(SIMULATION, mental_breaks.system());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn personality(traits: &[PersonalityTrait]) -> Personality {
+        Personality {
+            traits: traits.to_vec(),
+        }
+    }
+
+    #[test]
+    fn random_personalities_have_distinct_compatible_traits() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let personality = Personality::random(&mut rng);
+            let traits = personality.traits();
+            assert_eq!(traits.len(), NUM_TRAITS);
+            for (i, a) in traits.iter().enumerate() {
+                for b in &traits[i + 1..] {
+                    assert_ne!(a, b);
+                    assert!(!a.conflicts_with(b), "{} and {} conflict", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mood_settles_at_the_baseline() {
+        let thoughts = Thoughts::default();
+        assert_eq!(Mood::of(&personality(&[]), &thoughts).value, 0.);
+        let cheerful = personality(&[PersonalityTrait::Cheerful]);
+        assert_eq!(Mood::of(&cheerful, &thoughts).value, 15.);
+        let gloomy = personality(&[PersonalityTrait::Gloomy]);
+        assert_eq!(Mood::of(&gloomy, &thoughts).value, -15.);
+    }
+
+    #[test]
+    fn thoughts_add_up() {
+        let mut thoughts = Thoughts::default();
+        thoughts.add(ThoughtKind::FoundGold);
+        thoughts.add(ThoughtKind::WasInjured);
+        assert_eq!(Mood::of(&personality(&[]), &thoughts).value, -10.);
+        // Thinking the same thing again doesn't count twice.
+        thoughts.add(ThoughtKind::FoundGold);
+        assert_eq!(Mood::of(&personality(&[]), &thoughts).value, -10.);
+    }
+
+    #[test]
+    fn personality_changes_how_much_thoughts_matter() {
+        let mut thoughts = Thoughts::default();
+        thoughts.add(ThoughtKind::FoundGold);
+        let greedy = personality(&[PersonalityTrait::Greedy]);
+        assert_eq!(Mood::of(&greedy, &thoughts).value, 20.);
+
+        let mut thoughts = Thoughts::default();
+        thoughts.add(ThoughtKind::WasInjured);
+        let brave = personality(&[PersonalityTrait::Brave]);
+        assert_eq!(Mood::of(&brave, &thoughts).value, -10.);
+        let nervous = personality(&[PersonalityTrait::Nervous]);
+        assert_eq!(Mood::of(&nervous, &thoughts).value, -30.);
+    }
+
+    #[test]
+    fn thoughts_fade_as_they_age() {
+        let mut thoughts = Thoughts::default();
+        thoughts.add(ThoughtKind::WasInjured);
+        thoughts.log[0].age = ThoughtKind::WasInjured.duration() / 2.;
+        assert_eq!(Mood::of(&personality(&[]), &thoughts).value, -10.);
+        thoughts.log[0].age = ThoughtKind::WasInjured.duration();
+        assert_eq!(Mood::of(&personality(&[]), &thoughts).value, 0.);
+    }
+
+    #[test]
+    fn mood_is_clamped() {
+        let mut thoughts = Thoughts::default();
+        thoughts.add(ThoughtKind::SawFriendDie);
+        thoughts.add(ThoughtKind::WasInjured);
+        thoughts.add(ThoughtKind::Fell);
+        thoughts.add(ThoughtKind::SleptOnTheGround);
+        let gloomy = personality(&[PersonalityTrait::Gloomy, PersonalityTrait::Nervous]);
+        assert_eq!(Mood::of(&gloomy, &thoughts).value, -100.);
+    }
+}
@@ -0,0 +1,308 @@
+//! Pathfinding over the walkable surface of the terrain.
+//!
+//! The navigation graph is implicit: every air voxel which sits directly on top
+//! of a collidable voxel, with headroom above it, is a node. Nodes are connected
+//! to their horizontal and diagonal neighbours, including neighbours one voxel
+//! up or down, so agents can climb gentle slopes but not cliffs.
+//!
+//! Any agent with a [`Navigator`] and a rigid body will follow the path it has
+//! been given, regardless of whether it is a dwarf or a creature.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use bevy::{
+    ecs::{Query, ResMut},
+    math::Vec3,
+    prelude::{AppBuilder, IntoSystem, Plugin},
+};
+use bevy_rapier3d::{
+    na::Vector3,
+    physics::RigidBodyHandleComponent,
+    rapier::dynamics::RigidBodySet,
+};
+use building_blocks::{
+    core::{Extent3i, Point3i, PointN},
+    storage::{Array3, Get},
+};
+use colonize_common::{Voxel, VoxelType};
+
+use crate::health::Health;
+use crate::simulation::SIMULATION;
+use crate::terrain::TerrainResource;
+
+/// How far away from the start (horizontally) a path may wander.
+const MAX_SEARCH_RADIUS: i32 = 32;
+/// How far above or below the start a path may go.
+const MAX_SEARCH_HEIGHT: i32 = 16;
+/// The maximum number of nodes expanded before a search gives up.
+const MAX_EXPANDED_NODES: usize = 8192;
+/// How close an agent must get to a waypoint before moving on to the next one.
+const WAYPOINT_RADIUS: f32 = 0.5;
+/// How far a chased target must move before the chaser recomputes its path.
+const REPATH_DISTANCE: f32 = 2.;
+/// Upward speed given to an agent that needs to step up onto the next waypoint.
+const STEP_UP_SPEED: f32 = 4.;
+
+/// The cost of moving straight, diagonally, and up or down a step.
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+const STEP_COST: u32 = 5;
+
+/// A snapshot of the voxels around a search, so that we don't need to go through the
+/// compressed chunk storage for every lookup.
+struct NavGrid {
+    voxels: Array3<Voxel>,
+    extent: Extent3i,
+}
+
+impl NavGrid {
+    fn new(terrain: &TerrainResource, around: Point3i) -> Self {
+        let radius = PointN([MAX_SEARCH_RADIUS, MAX_SEARCH_HEIGHT, MAX_SEARCH_RADIUS]);
+        let shape = PointN([
+            2 * MAX_SEARCH_RADIUS + 1,
+            2 * MAX_SEARCH_HEIGHT + 1,
+            2 * MAX_SEARCH_RADIUS + 1,
+        ]);
+        let extent = Extent3i::from_min_and_shape(around - radius, shape);
+        Self {
+            voxels: terrain.voxels_in(&extent),
+            extent,
+        }
+    }
+
+    fn voxel_type(&self, p: Point3i) -> VoxelType {
+        if self.extent.contains(&p) {
+            *self.voxels.get(&p).voxel_type()
+        } else {
+            VoxelType::Air
+        }
+    }
+
+    fn is_solid(&self, p: Point3i) -> bool {
+        self.voxel_type(p).collidable()
+    }
+
+    /// Whether an agent can stand at the given point.
+    fn is_walkable(&self, p: Point3i) -> bool {
+        let up = PointN([0, 1, 0]);
+        self.extent.contains(&p)
+            && self.voxel_type(p) == VoxelType::Air
+            && !self.is_solid(p + up)
+            && self.is_solid(p - up)
+    }
+
+    /// Finds the closest walkable point in the column at or just below the given point.
+    fn snap_to_ground(&self, p: Point3i) -> Option<Point3i> {
+        (0..=3)
+            .map(|dy| p - PointN([0, dy, 0]))
+            .chain((1..=2).map(|dy| p + PointN([0, dy, 0])))
+            .find(|p| self.is_walkable(*p))
+    }
+
+    fn neighbours(&self, p: Point3i) -> impl Iterator<Item = (Point3i, u32)> + '_ {
+        const OFFSETS: [(i32, i32); 8] = [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ];
+        OFFSETS.iter().flat_map(move |&(dx, dz)| {
+            let diagonal = dx != 0 && dz != 0;
+            // Don't cut corners when moving diagonally.
+            let blocked = diagonal
+                && (self.is_solid(p + PointN([dx, 0, 0])) || self.is_solid(p + PointN([0, 0, dz])));
+            (-1..=1).filter_map(move |dy| {
+                let n = p + PointN([dx, dy, dz]);
+                if blocked || !self.is_walkable(n) {
+                    return None;
+                }
+                let mut cost = if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+                if dy != 0 {
+                    cost += STEP_COST;
+                }
+                Some((n, cost))
+            })
+        })
+    }
+}
+
+/// Octile distance, ignoring height, as an admissible heuristic for A*.
+fn heuristic(a: Point3i, b: Point3i) -> u32 {
+    let dx = (a.x() - b.x()).abs() as u32;
+    let dz = (a.z() - b.z()).abs() as u32;
+    let (min, max) = if dx < dz { (dx, dz) } else { (dz, dx) };
+    DIAGONAL_COST * min + STRAIGHT_COST * (max - min)
+}
+
+/// Finds a walkable path between two points using A*. The returned path excludes the start, so
+/// it's empty if the start and goal are the same point.
+pub(crate) fn find_path(
+    terrain: &TerrainResource,
+    start: Point3i,
+    goal: Point3i,
+) -> Option<Vec<Point3i>> {
+    let grid = NavGrid::new(terrain, start);
+    let start = grid.snap_to_ground(start)?;
+    let goal = grid.snap_to_ground(goal)?;
+
+    // Points are stored in the open set as raw arrays, since those have a total ordering.
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<Point3i, Point3i> = HashMap::new();
+    let mut cost_so_far: HashMap<Point3i, u32> = HashMap::new();
+    open.push(Reverse((heuristic(start, goal), start.0)));
+    cost_so_far.insert(start, 0);
+
+    let mut expanded = 0;
+    while let Some(Reverse((_, current))) = open.pop() {
+        let current = PointN(current);
+        if current == goal {
+            let mut path = Vec::new();
+            let mut p = current;
+            while p != start {
+                path.push(p);
+                p = came_from[&p];
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        expanded += 1;
+        if expanded > MAX_EXPANDED_NODES {
+            return None;
+        }
+
+        let current_cost = cost_so_far[&current];
+        for (next, step_cost) in grid.neighbours(current) {
+            let new_cost = current_cost + step_cost;
+            if cost_so_far.get(&next).map_or(true, |c| new_cost < *c) {
+                cost_so_far.insert(next, new_cost);
+                came_from.insert(next, current);
+                open.push(Reverse((new_cost + heuristic(next, goal), next.0)));
+            }
+        }
+    }
+
+    None
+}
+
+pub(crate) fn point_from_translation(translation: Vec3) -> Point3i {
+    PointN([
+        translation.x.floor() as i32,
+        translation.y.floor() as i32,
+        translation.z.floor() as i32,
+    ])
+}
+
+/// Keeps an agent headed towards a moving target. The path is only recomputed once the
+/// target has strayed far enough from where the current path ends.
+pub(crate) fn chase(
+    terrain: &TerrainResource,
+    navigator: &mut Navigator,
+    position: Vec3,
+    target: Vec3,
+) {
+    let stale = navigator
+        .destination()
+        .map_or(true, |d| d.distance(target) > REPATH_DISTANCE);
+    if stale {
+        let start = point_from_translation(position);
+        let goal = point_from_translation(target);
+        if let Some(path) = find_path(terrain, start, goal) {
+            navigator.set_path(path);
+        }
+    }
+}
+
+/// Steers an agent along a path through the world.
+#[derive(Debug)]
+pub(crate) struct Navigator {
+    waypoints: Vec<Vec3>,
+    next: usize,
+    /// Walking speed, in m/s.
+    pub(crate) speed: f32,
+}
+
+impl Navigator {
+    pub(crate) fn new(speed: f32) -> Self {
+        Self {
+            waypoints: Vec::new(),
+            next: 0,
+            speed,
+        }
+    }
+
+    pub(crate) fn set_path(&mut self, path: Vec<Point3i>) {
+        // Aim for the middle of each voxel.
+        self.waypoints = path
+            .into_iter()
+            .map(|p| Vec3::new(p.x() as f32 + 0.5, p.y() as f32 + 0.5, p.z() as f32 + 0.5))
+            .collect();
+        self.next = 0;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.waypoints.clear();
+        self.next = 0;
+    }
+
+    pub(crate) fn is_idle(&self) -> bool {
+        self.next >= self.waypoints.len()
+    }
+
+    /// The point the agent is ultimately headed to, if any.
+    pub(crate) fn destination(&self) -> Option<Vec3> {
+        if self.is_idle() {
+            None
+        } else {
+            self.waypoints.last().copied()
+        }
+    }
+}
+
+/// Moves every agent towards the next waypoint on their path.
+fn follow_paths(
+    mut rigid_body_set: ResMut<RigidBodySet>,
+    mut query: Query<(&mut Navigator, Option<&Health>, &RigidBodyHandleComponent)>,
+) {
+    for (mut navigator, health, rigid_body_handle) in query.iter_mut() {
+        if navigator.is_idle() {
+            continue;
+        }
+        // Agents that are unconscious or dead drop whatever they were doing.
+        if health.map_or(false, |h| !h.can_act()) {
+            navigator.clear();
+            continue;
+        }
+
+        let rigid_body = rigid_body_set.get_mut(rigid_body_handle.handle()).unwrap();
+        let position = rigid_body.position().translation.vector;
+        let position = Vec3::new(position.x, position.y, position.z);
+        let waypoint = navigator.waypoints[navigator.next];
+        let mut offset = waypoint - position;
+        let climb = offset.y;
+        offset.y = 0.;
+        if offset.length() < WAYPOINT_RADIUS {
+            navigator.next += 1;
+            continue;
+        }
+
+        let horizontal = offset.normalize() * navigator.speed;
+        let mut vertical = rigid_body.linvel().y;
+        if climb > WAYPOINT_RADIUS && vertical < STEP_UP_SPEED {
+            vertical = STEP_UP_SPEED;
+        }
+        rigid_body.set_linvel(Vector3::new(horizontal.x, vertical, horizontal.z), true);
+    }
+}
+
+pub(crate) struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(SIMULATION, follow_paths.system());
+    }
+}
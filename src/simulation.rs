@@ -0,0 +1,283 @@
+//! The fixed-timestep simulation clock.
+//!
+//! Gameplay systems don't run once per frame. Instead, they're added to the
+//! [`SIMULATION`] stage, which runs once per tick of the [`SimulationClock`]. Each
+//! tick advances the world by exactly [`TICK_SECONDS`], so the simulation behaves
+//! the same regardless of frame rate. The clock can be paused, single-stepped
+//! while paused, and sped up, in which case several ticks run in a single frame.
+//!
+//! When running headless, the clock is in lockstep mode instead: exactly one
+//! tick runs per frame, and frames run as fast as the machine allows.
+//!
+//! Physics is stepped as part of each tick too, in a fixed number of substeps, rather
+//! than by rapier once per frame. That way it pauses, single-steps and speeds up along
+//! with everything else, and fast speeds don't mean longer steps for things to tunnel
+//! through the terrain in. The clock doesn't start until the terrain's colliders have all
+//! been built, which happens in the background after the world is generated.
+//!
+//! Systems in the simulation stage should use [`TICK_SECONDS`] rather than
+//! [`Time`] to measure how much time has passed.
+use std::fmt;
+
+use bevy::{
+    app::stage,
+    core::Time,
+    ecs::{IntoSystem, Query, Res, ResMut, ShouldRun, SystemStage},
+    input::Input,
+    prelude::{debug, AppBuilder, KeyCode, Plugin},
+};
+use bevy_rapier3d::{
+    physics::{EventQueue, RapierConfiguration, RigidBodyHandleComponent},
+    rapier::{
+        dynamics::{IntegrationParameters, JointSet, RigidBodySet},
+        geometry::{BroadPhase, ColliderSet, NarrowPhase},
+        pipeline::PhysicsPipeline,
+    },
+};
+
+use crate::terrain::TerrainResource;
+
+pub(crate) const SIMULATION: &str = "SIMULATION";
+
+/// Ticks per second of simulated time.
+const TICKS_PER_SECOND: u32 = 20;
+/// Seconds of simulated time which pass each tick.
+pub(crate) const TICK_SECONDS: f32 = 1. / TICKS_PER_SECOND as f32;
+/// The most ticks run in a single frame. If the game can't keep up, the simulation
+/// slows down instead of falling further and further behind.
+const MAX_TICKS_PER_FRAME: u32 = 20;
+/// Physics steps per tick.
+const PHYSICS_SUBSTEPS: u32 = 3;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Speed {
+    Normal,
+    Fast,
+    Fastest,
+}
+
+impl Speed {
+    /// Simulated seconds per real second.
+    fn multiplier(&self) -> u32 {
+        match self {
+            Speed::Normal => 1,
+            Speed::Fast => 2,
+            Speed::Fastest => 5,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SimulationClock {
+    paused: bool,
+    speed: Speed,
+    /// Real time, scaled by the speed, which hasn't been simulated yet.
+    accumulator: f64,
+    /// Set when the player asks for a single tick while paused.
+    step_requested: bool,
+    /// Whether to run exactly one tick per frame, regardless of how much time has passed.
+    lockstep: bool,
+    /// Whether the run criteria is in the middle of running several ticks this frame.
+    looping: bool,
+    ticks_this_frame: u32,
+    /// The number of ticks simulated since the game started.
+    ticks: u64,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            speed: Speed::Normal,
+            accumulator: 0.,
+            step_requested: false,
+            lockstep: false,
+            looping: false,
+            ticks_this_frame: 0,
+            ticks: 0,
+        }
+    }
+}
+
+impl SimulationClock {
+    /// A clock which runs one tick per frame.
+    pub(crate) fn lockstep() -> Self {
+        Self {
+            lockstep: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+        self.ticks_this_frame += 1;
+    }
+}
+
+impl fmt::Display for SimulationClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Tick {}", self.ticks)?;
+        if self.paused {
+            write!(f, " (paused)")
+        } else {
+            write!(f, " ({}x)", self.speed.multiplier())
+        }
+    }
+}
+
+/// Decides how many ticks the simulation stage runs this frame. Bevy keeps running the
+/// stage for as long as this returns `YesAndLoop`.
+fn run_simulation(
+    time: Res<Time>,
+    terrain: Res<TerrainResource>,
+    mut clock: ResMut<SimulationClock>,
+) -> ShouldRun {
+    // Everything would fall through the ground if it ran before the ground could be stood on.
+    if !terrain.colliders_ready() {
+        return ShouldRun::No;
+    }
+
+    if clock.lockstep {
+        clock.tick();
+        return ShouldRun::Yes;
+    }
+
+    if !clock.looping {
+        // This is the first check of the frame.
+        clock.ticks_this_frame = 0;
+        if clock.paused {
+            clock.accumulator = 0.;
+            if clock.step_requested {
+                clock.step_requested = false;
+                clock.tick();
+                return ShouldRun::Yes;
+            }
+            return ShouldRun::No;
+        }
+        clock.accumulator += time.delta_seconds_f64() * clock.speed.multiplier() as f64;
+    }
+
+    let tick_seconds = TICK_SECONDS as f64;
+    if clock.accumulator >= tick_seconds && clock.ticks_this_frame < MAX_TICKS_PER_FRAME {
+        clock.accumulator -= tick_seconds;
+        clock.tick();
+        clock.looping = true;
+        ShouldRun::YesAndLoop
+    } else {
+        // Drop whatever we couldn't get through, rather than trying to catch up later.
+        clock.accumulator = clock.accumulator.min(tick_seconds);
+        clock.looping = false;
+        ShouldRun::No
+    }
+}
+
+fn simulation_controls(keyboard_input: Res<Input<KeyCode>>, mut clock: ResMut<SimulationClock>) {
+    if keyboard_input.just_pressed(KeyCode::F) {
+        clock.paused = !clock.paused;
+        debug!(
+            "Simulation {}",
+            if clock.paused { "paused" } else { "resumed" }
+        );
+    }
+    if keyboard_input.just_pressed(KeyCode::Period) && clock.paused {
+        clock.step_requested = true;
+    }
+
+    let speed = if keyboard_input.just_pressed(KeyCode::Key1) {
+        Some(Speed::Normal)
+    } else if keyboard_input.just_pressed(KeyCode::Key2) {
+        Some(Speed::Fast)
+    } else if keyboard_input.just_pressed(KeyCode::Key3) {
+        Some(Speed::Fastest)
+    } else {
+        None
+    };
+    if let Some(speed) = speed {
+        debug!("Simulation speed set to {}x", speed.multiplier());
+        clock.speed = speed;
+        clock.paused = false;
+    }
+}
+
+/// The fastest an entity's rigid body fell during the last tick. It's sampled after every
+/// physics substep, since the peak speed of a fall is reached just before landing, and the
+/// velocity at the end of the tick is already the velocity after it.
+#[derive(Debug, Default)]
+pub(crate) struct FallSpeed(f32);
+
+impl FallSpeed {
+    pub(crate) fn get(&self) -> f32 {
+        self.0
+    }
+}
+
+/// Stops rapier from stepping the physics pipeline itself, once per frame, since
+/// [`step_physics`] does that once per tick instead.
+fn configure_physics(
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut integration_parameters: ResMut<IntegrationParameters>,
+) {
+    rapier_config.physics_pipeline_active = false;
+    integration_parameters.set_dt(TICK_SECONDS / PHYSICS_SUBSTEPS as f32);
+}
+
+/// Advances physics by one tick. This is the first system in the simulation stage, so
+/// everything after it in the same tick sees the contact events it produced.
+#[allow(clippy::too_many_arguments)]
+fn step_physics(
+    rapier_config: Res<RapierConfiguration>,
+    integration_parameters: Res<IntegrationParameters>,
+    events: Res<EventQueue>,
+    mut pipeline: ResMut<PhysicsPipeline>,
+    mut broad_phase: ResMut<BroadPhase>,
+    mut narrow_phase: ResMut<NarrowPhase>,
+    mut rigid_body_set: ResMut<RigidBodySet>,
+    mut collider_set: ResMut<ColliderSet>,
+    mut joint_set: ResMut<JointSet>,
+    mut fall_speed_query: Query<(&mut FallSpeed, &RigidBodyHandleComponent)>,
+) {
+    for (mut fall_speed, _) in fall_speed_query.iter_mut() {
+        fall_speed.0 = 0.;
+    }
+
+    for _ in 0..PHYSICS_SUBSTEPS {
+        pipeline.step(
+            &rapier_config.gravity,
+            &integration_parameters,
+            &mut broad_phase,
+            &mut narrow_phase,
+            &mut rigid_body_set,
+            &mut collider_set,
+            &mut joint_set,
+            &*events,
+        );
+
+        for (mut fall_speed, rigid_body_handle) in fall_speed_query.iter_mut() {
+            if let Some(rigid_body) = rigid_body_set.get(rigid_body_handle.handle()) {
+                fall_speed.0 = fall_speed.0.max(-rigid_body.linvel().y);
+            }
+        }
+    }
+}
+
+/// Adds the [`SIMULATION`] stage. This must be added before any plugin which adds
+/// systems to that stage, so that physics is stepped before they run.
+pub(crate) struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(SimulationClock::default())
+            .add_stage_after(
+                stage::UPDATE,
+                SIMULATION,
+                SystemStage::parallel().with_run_criteria(run_simulation.system()),
+            )
+            .add_startup_system(configure_physics.system())
+            .add_system_to_stage(SIMULATION, step_physics.system())
+            .add_system(simulation_controls.system());
+    }
+}
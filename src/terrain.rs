@@ -8,73 +8,91 @@
 //! The origin point (0, 0, 0) is in the middle of the map. This means that
 //! the minimum point on the map is (-384, -384, -384) and the maximum point
 //! is (384, 384, 384).
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
 
+use bevy::app::Events;
 use bevy::pbr::PbrBundle;
 use bevy::{ecs::Query, render::pipeline::PrimitiveTopology};
 use bevy::{
-    ecs::{Commands, Entity, IntoSystem, Res, ResMut},
+    ecs::{Commands, Entity, IntoSystem, Res, ResMut, With, Without},
     input::Input,
-    prelude::{AppBuilder, Assets, Color, Handle, KeyCode, Mesh, Plugin},
+    prelude::{error, info, warn, AppBuilder, Assets, Color, Handle, KeyCode, Mesh, Plugin},
     reflect::TypeUuid,
     render::{
         mesh::{Indices, VertexAttributeValues},
         renderer::RenderResources,
+        texture::{Extent3d, Texture, TextureDimension, TextureFormat},
     },
 };
-use bevy::{log::trace, prelude::Visible};
 use bevy::{
-    prelude::AddAsset,
-    render::{pipeline::PipelineDescriptor, render_graph::RenderGraph},
-    tasks::ComputeTaskPool,
+    log::trace,
+    math::Vec3,
+    prelude::{GlobalTransform, Visible},
+    render::camera::PerspectiveProjection,
 };
-use bevy_rapier3d::rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder, math::Point};
-use building_blocks::{
-    core::Point3,
-    mesh::{
-        adf_dual_contour, surface_nets, AdfDualContourBuffer, IsOpaque, SignedDistance,
-        SurfaceNetsBuffer,
-    },
-    storage::{
-        padded_adf_chunk_extent, Adf, Array, Array3, ChunkMap, CompressibleChunkStorageReader,
-        ForEach, GetUncheckedRelease, IsEmpty, Local, Snappy, Stride, TransformMap,
-    },
-};
-use building_blocks::{
-    core::{Extent3i, Neighborhoods, Point2i, Point3i, PointN},
-    storage::Get,
+use bevy::{
+    prelude::AddAsset,
+    tasks::{AsyncComputeTaskPool, Task},
 };
-use building_blocks::{
-    mesh::{greedy_quads, padded_greedy_quads_chunk_extent, GreedyQuadsBuffer, PosNormMesh},
-    storage::LocalChunkCache,
+use bevy_rapier3d::{
+    na::Point3 as NaPoint3,
+    rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder},
 };
 use building_blocks::{
+    core::{Extent3i, Point2i, Point3, Point3i, PointN},
+    mesh::{IsOpaque, PosNormMesh},
     prelude::{copy_extent, LocalChunkCache3},
     storage::{
-        ChunkMapBuilder, ChunkMapBuilder3, CompressibleChunkMap3, CompressibleChunkStorage,
-        IterChunkKeys,
+        Array3, ChunkMap, ChunkMapBuilder, ChunkMapBuilder3, CompressibleChunkMap3,
+        CompressibleChunkStorage, CompressibleChunkStorageReader, ForEach, Get, IterChunkKeys,
+        LocalChunkCache, Snappy,
     },
 };
+#[cfg(not(target_arch = "wasm32"))]
+use colonize_core::ObjWriter;
+use colonize_core::{
+    add_skirts, cuboid_trimesh, generate_precise_map_with_masks, greedy_cuboids, material_texture,
+    MeshBuffers, MeshGenerationMethod, MesherBuffers, WorldGenConfig, WorldGenPresets,
+    TEXTURE_SIZE,
+};
 use colonize_noise::Noise2d;
-use colonize_pbr::{pbr_bundle, prelude::StandardMaterial, YLevel};
-use noise::{MultiFractal, RidgedMulti, Seedable};
+use colonize_pbr::{pbr_bundle, prelude::StandardMaterial, ShadowCaster, YLevel};
+use futures_lite::future;
+use noise::{RidgedMulti, Seedable};
 use rand::{thread_rng, Rng};
+use serde::Deserialize;
 
-use colonize_common::{Voxel, VoxelType, EMPTY_VOXEL, NUM_VOXEL_TYPES};
+use colonize_common::{Voxel, VoxelType, EMPTY_VOXEL};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::data::{asset_root, read_asset};
 
 const CHUNK_SIZE: usize = 128;
-const REGION_SIZE: usize = 512; // CHUNK_SIZE * NUM_CHUNKS
-                                // 512 underground blocks, plus 256 blocks above sea level.
-const REGION_HEIGHT: i32 = 512;
-const REGION_MIN_3D: Point3i = PointN([-(REGION_SIZE as i32 / 2), -256, -(REGION_SIZE as i32 / 2)]);
-const REGION_SHAPE_3D: Point3i = PointN([REGION_SIZE as i32, REGION_HEIGHT, REGION_SIZE as i32]);
-const REGION_MAX_3D: Point3i = PointN([
-    REGION_MIN_3D.0[0] + REGION_SHAPE_3D.0[0],
-    REGION_MIN_3D.0[1] + REGION_SHAPE_3D.0[1],
-    REGION_MIN_3D.0[2] + REGION_SHAPE_3D.0[2],
-]);
-
-const SEA_LEVEL: i32 = 0;
+/// How many of the Y-levels nearest the current one have their slices built ahead of time, so
+/// that they're ready by the time the player scrolls to them. Levels further away are dropped.
+const CACHED_SLICE_LEVELS: usize = 16;
+/// The number of levels of detail each chunk is meshed at.
+const LOD_LEVELS: u8 = 3;
+/// How far away from the camera a chunk has to be to drop to each lower level of detail.
+const LOD_DISTANCE: f32 = 256.;
+
+/// World generation presets, relative to the asset root. See the file itself for what each
+/// setting does.
+#[cfg(not(target_arch = "wasm32"))]
+const PRESETS_PATH: &str = "data/worldgen.ron";
+/// The presets built into the game, which are used when the presets file can't be loaded.
+const BUILT_IN_PRESETS: &str = include_str!("../assets/data/worldgen.ron");
+/// Where exported terrain is written to.
+#[cfg(not(target_arch = "wasm32"))]
+const EXPORT_DIRECTORY: &str = "exports";
 
 const DEFAULT_BUILDER: ChunkMapBuilder3<Voxel> = ChunkMapBuilder {
     chunk_shape: PointN([CHUNK_SIZE as i32; 3]),
@@ -87,45 +105,73 @@ pub(crate) const TERRAIN: &str = "terrain";
 #[derive(Debug)]
 pub struct Chunk;
 
+/// Sent whenever the voxels of the world have been generated, at startup and after every
+/// reset. Everything living on the surface is placed (or placed again) when this arrives.
+pub(crate) struct TerrainGenerated;
+
+/// The broad kind of environment found at a point on the surface.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub(crate) enum Biome {
+    /// Low-lying land right next to the water.
+    Shore,
+    Grassland,
+    Mountain,
+}
+
 pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut AppBuilder) {
+        let terrain_res = TerrainResource::default();
+        let y_level = YLevel {
+            value: terrain_res.config.extent().least_upper_bound().y(),
+        };
         app.add_asset::<MeshMaterial>()
-            .add_resource(TerrainResource::default())
+            .add_event::<TerrainGenerated>()
+            .add_resource(terrain_res)
             .add_resource(MeshResource::default())
-            .add_resource(YLevel {
-                value: REGION_MAX_3D.y(),
-            })
+            .add_resource(MeshGenerationMethod::default())
+            .add_resource(y_level)
             .add_startup_system(setup.system())
+            // The world is generated before the first frame, and again whenever it's reset.
             .add_startup_system_to_stage(TERRAIN, generate_voxels.system())
+            .add_system(generate_voxels.system())
             .add_system(generate_meshes.system())
+            .add_system(spawn_meshes.system())
+            .add_system(request_slice_meshes.system())
+            .add_system(spawn_slice_meshes.system())
             .add_system(hide_y_levels_system.system())
             .add_system(modify_config.system());
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system(export_terrain.system());
     }
 }
 
 fn setup(
     mut res: ResMut<TerrainResource>,
     mut standard_materials: ResMut<Assets<StandardMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
     _mesh_materials: ResMut<Assets<MeshMaterial>>,
-    _pipelines: ResMut<Assets<PipelineDescriptor>>,
-    _render_graph: ResMut<RenderGraph>,
 ) {
-    for (voxel_type, color) in &[
-        // Technically we don't use the "air" material ever, since air is transparent, but we still need it to
-        // ensure these indices match up with the ones provided by VoxelType::index.
-        (VoxelType::Air, Color::rgb(0.5, 0.5, 0.5)),
-        (VoxelType::Stone, Color::rgb(0.5, 0.5, 0.5)),
-        (VoxelType::Grass, Color::rgb(0.376, 0.502, 0.22)),
-        (VoxelType::Gold, Color::rgb(1.0, 0.843, 0.)),
-        (VoxelType::Water, Color::rgba(0.0, 0.0, 0.5, 0.5)),
-    ] {
+    // Technically we don't use the "air" material ever, since air is transparent, but we still need it to
+    // ensure these indices match up with the ones provided by VoxelType::index.
+    for voxel_type in VoxelType::ALL.iter() {
+        let [r, g, b, a] = voxel_type.color();
+        let color = Color::rgba(r, g, b, a);
+        let texture = textures.add(Texture::new(
+            Extent3d::new(TEXTURE_SIZE, TEXTURE_SIZE, 1),
+            TextureDimension::D2,
+            material_texture(*voxel_type),
+            TextureFormat::Rgba8Unorm,
+        ));
         res.materials.insert(
             *voxel_type,
             (
-                standard_materials.add((*color).into()),
-                HatMaterial(standard_materials.add((*color).into())),
+                standard_materials.add(StandardMaterial {
+                    albedo_texture: Some(texture),
+                    ..color.into()
+                }),
+                HatMaterial(standard_materials.add(color.into())),
             ),
         );
     }
@@ -133,14 +179,57 @@ fn setup(
 
 pub(crate) struct TerrainResource {
     materials: HashMap<VoxelType, (Handle<StandardMaterial>, HatMaterial)>,
-    noise: RidgedMulti,
-    chunks: CompressibleChunkMap3<Voxel>,
+    presets: WorldGenPresets,
+    /// The name of the preset the current config started out as.
+    preset: String,
+    /// The settings the world is generated with. These start out as a copy of the preset,
+    /// and may then be tweaked in-game.
+    config: WorldGenConfig,
+    /// Shared with the tasks meshing the chunks in the background. Each world gets a new map,
+    /// so tasks still meshing the last one don't see it change underneath them.
+    chunks: Arc<CompressibleChunkMap3<Voxel>>,
+    /// Counts up every time the chunk map is replaced, so that chunks cached from older maps
+    /// can be told apart.
+    generation: u64,
     generated_voxels: bool,
-    sea_level: f64,
+    /// Whether every chunk of the current world has its colliders yet.
+    colliders_ready: bool,
     y_offset: f64,
 }
 
 impl TerrainResource {
+    /// Switches to the named preset, discarding any changes made to the current config.
+    /// Falls back to the default preset if there's no preset with that name.
+    fn select_preset(&mut self, name: &str) {
+        let name = if self.presets.get(name).is_some() {
+            name
+        } else {
+            warn!("No world generation preset named {:?}", name);
+            &self.presets.default
+        };
+        self.preset = name.to_string();
+        self.config = self.presets.get(name).unwrap().clone();
+        info!("Using world generation preset {:?}", self.preset);
+    }
+
+    /// The name of the preset after the current one, wrapping around to the first.
+    fn next_preset(&self) -> String {
+        let mut names = self.presets.names();
+        names
+            .by_ref()
+            .find(|name| **name == self.preset)
+            .and_then(|_| names.next())
+            .or_else(|| self.presets.names().next())
+            .unwrap()
+            .clone()
+    }
+
+    /// Whether the ground can be stood on yet. The colliders of the chunks are built in the
+    /// background, so for a little while after the world is generated, some are missing.
+    pub(crate) fn colliders_ready(&self) -> bool {
+        self.colliders_ready
+    }
+
     pub(crate) fn surface_y(&self, column: Point2i) -> i32 {
         let local_cache = LocalChunkCache::new();
         let reader = self.chunks.storage().reader(&local_cache);
@@ -157,6 +246,47 @@ impl TerrainResource {
         min_y
     }
 
+    /// How steep the ground is at a column, as the most the surface rises or falls to any of
+    /// its neighbours.
+    pub(crate) fn slope_at(&self, column: Point2i) -> i32 {
+        let y = self.surface_y(column);
+        [[1, 0], [-1, 0], [0, 1], [0, -1]]
+            .iter()
+            .map(|[dx, dz]| (self.surface_y(PointN([column.x() + dx, column.y() + dz])) - y).abs())
+            .max()
+            .unwrap()
+    }
+
+    /// Determines the biome of a column from the height and material of its surface. Returns
+    /// `None` for columns that are underwater.
+    pub(crate) fn biome_at(&self, column: Point2i) -> Option<Biome> {
+        const SHORE_HEIGHT: i32 = 4;
+        const MOUNTAIN_HEIGHT: i32 = 64;
+
+        let y = self.surface_y(column);
+        let local_cache = LocalChunkCache::new();
+        let reader = self.chunks.storage().reader(&local_cache);
+        let reader_map = DEFAULT_BUILDER.build_with_read_storage(reader);
+        let surface = reader_map.get(&PointN([column.x(), y, column.y()]));
+        match surface.voxel_type() {
+            VoxelType::Water | VoxelType::Air => None,
+            _ if y < self.config.sea_level + SHORE_HEIGHT => Some(Biome::Shore),
+            voxel_type if voxel_type.is_stone() => Some(Biome::Mountain),
+            _ if y >= self.config.sea_level + MOUNTAIN_HEIGHT => Some(Biome::Mountain),
+            _ => Some(Biome::Grassland),
+        }
+    }
+
+    /// Copies the voxels in the given extent out of the chunk map.
+    pub(crate) fn voxels_in(&self, extent: &Extent3i) -> Array3<Voxel> {
+        let local_cache = LocalChunkCache::new();
+        let reader = self.chunks.storage().reader(&local_cache);
+        let reader_map = DEFAULT_BUILDER.build_with_read_storage(reader);
+        let mut voxels = Array3::fill(*extent, EMPTY_VOXEL);
+        copy_extent(extent, &reader_map, &mut voxels);
+        voxels
+    }
+
     pub(crate) fn find_nearest_gold(&self, x: i32, y: i32, z: i32) -> Option<Point3<i32>> {
         const SEARCH_SIZE: i32 = 10;
         let min = PointN([x, y, z]) + PointN([-(SEARCH_SIZE as i32 / 2); 3]);
@@ -179,29 +309,57 @@ impl TerrainResource {
 impl Default for TerrainResource {
     fn default() -> Self {
         let store = CompressibleChunkStorage::new(Snappy);
+        let presets = load_presets().unwrap_or_else(|e| {
+            error!("Using the built-in world generation presets: {}", e);
+            WorldGenPresets::from_ron(BUILT_IN_PRESETS).expect("built-in presets are invalid")
+        });
+        let preset = presets.default.clone();
+        let config = presets.get(&preset).unwrap().clone();
         Self {
             materials: HashMap::new(),
-            noise: RidgedMulti::new()
-                .set_frequency(0.001)
-                .set_lacunarity(4.0)
-                .set_persistence(0.7)
-                .set_octaves(8),
-            chunks: DEFAULT_BUILDER.build_with_write_storage(store),
+            presets,
+            preset,
+            config,
+            chunks: Arc::new(DEFAULT_BUILDER.build_with_write_storage(store)),
+            generation: 0,
             generated_voxels: false,
-            sea_level: 100.,
+            colliders_ready: false,
             y_offset: 10.,
         }
     }
 }
 
+fn load_presets() -> Result<WorldGenPresets, String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let data = read_asset(PRESETS_PATH)?;
+    #[cfg(target_arch = "wasm32")]
+    let data = BUILT_IN_PRESETS;
+    WorldGenPresets::from_ron(&data).map_err(|e| e.to_string())
+}
+
 struct MeshResource {
-    meshes: HashMap<Point3i, Vec<(Entity, Handle<Mesh>)>>,
+    /// The mesh each chunk is drawn with, at whichever level of detail it was last meshed at.
+    meshes: HashMap<Point3i, ChunkMesh>,
+    /// The chunks being meshed in the background, and the level of detail and method they're
+    /// meshed with.
+    mesh_tasks: HashMap<Point3i, (u8, MeshGenerationMethod, Task<MeshedChunk>)>,
+    /// The slice meshes of every chunk at each Y-level which has been built, or is being
+    /// built.
+    slices: HashMap<i32, Vec<(Entity, Handle<Mesh>)>>,
+    /// The slices still being meshed in the background, by Y-level.
+    slice_tasks: Vec<(i32, Task<Option<ChunkMeshes>>)>,
+    /// The collider of each chunk.
+    colliders: HashMap<Point3i, ChunkCollider>,
 }
 
 impl Default for MeshResource {
     fn default() -> Self {
         Self {
             meshes: HashMap::new(),
+            mesh_tasks: HashMap::new(),
+            slices: HashMap::new(),
+            slice_tasks: Vec::new(),
+            colliders: HashMap::new(),
         }
     }
 }
@@ -221,15 +379,46 @@ fn modify_config(
     keyboard_input: Res<Input<KeyCode>>,
     mut terrain_res: ResMut<TerrainResource>,
     mut mesh_res: ResMut<MeshResource>,
+    mut method: ResMut<MeshGenerationMethod>,
 ) {
     let mut reset_flag = false;
 
+    // Switch to the next meshing method if the player pressed `F7`. The voxels stay the same,
+    // so only the meshes need to be regenerated. Each chunk keeps its old mesh and collider
+    // until the new ones are ready, so nothing falls through the ground in the meantime.
+    if keyboard_input.just_pressed(KeyCode::F7) {
+        *method = method.next();
+        info!("Meshing terrain with {}", *method);
+        clear_slices(commands, &mut mesh_assets, &mut mesh_res);
+    }
+
+    // Reload the presets file if the player pressed `F5`, so that presets can be tweaked
+    // without restarting the game.
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        match load_presets() {
+            Ok(presets) => {
+                terrain_res.presets = presets;
+                let preset = terrain_res.preset.clone();
+                terrain_res.select_preset(&preset);
+                reset_flag = true;
+            }
+            Err(e) => warn!("Failed to reload world generation presets: {}", e),
+        }
+    }
+
+    // Switch to the next preset if the player pressed `F6`.
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        let preset = terrain_res.next_preset();
+        terrain_res.select_preset(&preset);
+        reset_flag = true;
+    }
+
     // Increase/decrease the sea level by 10 if the player pressed `Y` or `G`.
     if keyboard_input.pressed(KeyCode::Y) {
-        terrain_res.sea_level += 10.;
+        terrain_res.config.sea_level += 10;
         reset_flag = true;
     } else if keyboard_input.pressed(KeyCode::G) {
-        terrain_res.sea_level -= 10.;
+        terrain_res.config.sea_level -= 10;
         reset_flag = true;
     }
 
@@ -244,28 +433,28 @@ fn modify_config(
 
     // Increase/decrease the frequency by 0.001 if the player pressed `I` or `J`.
     if keyboard_input.pressed(KeyCode::I) {
-        terrain_res.noise.frequency += 0.001;
+        terrain_res.config.elevation.frequency += 0.001;
         reset_flag = true;
     } else if keyboard_input.pressed(KeyCode::J) {
-        terrain_res.noise.frequency -= 0.001;
+        terrain_res.config.elevation.frequency -= 0.001;
         reset_flag = true;
     }
 
     // Increase/decrease the lacunarity by 0.1 if the player pressed `O` or `K`.
     if keyboard_input.pressed(KeyCode::O) {
-        terrain_res.noise.lacunarity += 0.1;
+        terrain_res.config.elevation.lacunarity += 0.1;
         reset_flag = true;
     } else if keyboard_input.pressed(KeyCode::K) {
-        terrain_res.noise.lacunarity -= 0.1;
+        terrain_res.config.elevation.lacunarity -= 0.1;
         reset_flag = true;
     }
 
     // Increase/decrease the persistence by 0.1 if the player pressed `P` or `L`.
     if keyboard_input.pressed(KeyCode::P) {
-        terrain_res.noise.persistence += 0.1;
+        terrain_res.config.elevation.persistence += 0.1;
         reset_flag = true;
     } else if keyboard_input.pressed(KeyCode::L) {
-        terrain_res.noise.persistence -= 0.1;
+        terrain_res.config.elevation.persistence -= 0.1;
         reset_flag = true;
     }
 
@@ -275,93 +464,33 @@ fn modify_config(
 }
 
 fn hide_y_levels_system(
-    commands: &mut Commands,
     keyboard_input: Res<Input<KeyCode>>,
-    terrain_res: ResMut<TerrainResource>,
-    mut mesh_res: ResMut<MeshResource>,
-    mesh_query: Query<(Entity, Option<&FullDetailMesh>, &YLevel)>,
-    mut mesh_assets: ResMut<Assets<Mesh>>,
+    terrain_res: Res<TerrainResource>,
     mut y_level: ResMut<YLevel>,
+    mut slice_query: Query<(&YLevel, &mut Visible), (With<Chunk>, Without<FullDetailMesh>)>,
 ) {
-    // Increase/decrease the Y-level by 1 if the player pressed `<` or `>`.
+    // Increase/decrease the Y-level by 1 each time the player presses `-` or `=`.
     let old_y_level = *y_level;
-    if keyboard_input.pressed(KeyCode::Minus) {
-        y_level.value = i32::max(y_level.value - 1, REGION_MIN_3D.y());
+    let region = terrain_res.config.extent();
+    if keyboard_input.just_pressed(KeyCode::Minus) {
+        y_level.value = i32::max(y_level.value - 1, region.minimum.y());
         trace!("Decremented y-level to {:?}", y_level.value);
-    } else if keyboard_input.pressed(KeyCode::Equals) {
-        y_level.value = i32::min(y_level.value + 1, REGION_MAX_3D.y());
+    } else if keyboard_input.just_pressed(KeyCode::Equals) {
+        y_level.value = i32::min(y_level.value + 1, region.least_upper_bound().y());
         trace!("Incremented y-level to {:?}", y_level.value);
     }
 
     if old_y_level != *y_level {
-        // Find and disable all the meshes at the old y-level.
-        let mut to_remove = Vec::new();
-        for (_chunk_pos, mesh_entities) in mesh_res.meshes.iter() {
-            for (entity, _mesh) in mesh_entities {
-                if let Ok((mesh_entity, None, mesh_y_level)) = mesh_query.get(*entity) {
-                    if mesh_y_level != &*y_level {
-                        to_remove.push(mesh_entity);
-                    }
-                }
-            }
-        }
-        for entity in to_remove {
-            commands.despawn(entity);
-        }
-
-        let local_cache = LocalChunkCache3::new();
-        for (chunk_pos, mesh_entities) in mesh_res.meshes.iter_mut() {
-            // Generate a new mesh for the current y-level for each chunk.
-            if y_level.value < chunk_pos.y() || y_level.value > chunk_pos.y() + CHUNK_SIZE as i32 {
-                // If the y-level does not intersect this chunk, there's nothing to generate.
-                continue;
-            }
-            let chunk_extent = terrain_res
-                .chunks
-                .indexer
-                .extent_for_chunk_at_key(*chunk_pos);
-            let padded_chunk_extent = padded_greedy_quads_chunk_extent(&chunk_extent);
-            let mut padded_layer_extent = padded_chunk_extent;
-            *padded_layer_extent.shape.y_mut() = 3;
-            *padded_layer_extent.minimum.y_mut() = y_level.value - 1;
-            let extent_to_copy = padded_layer_extent.add_to_shape(PointN([0, -1, 0]));
-            trace!(
-                "Generating padded layers for extent {:?} for chunk {:?}",
-                padded_layer_extent,
-                chunk_pos
-            );
-            let meshes = METHOD.generate_mesh_for_extent(
-                &terrain_res.chunks,
-                chunk_pos,
-                &local_cache,
-                padded_layer_extent,
-                &extent_to_copy,
-            );
-            if let Some(meshes) = meshes {
-                for (material, pos_norm_mesh) in meshes {
-                    let (entity, mesh) = generate_mesh_entity(
-                        pos_norm_mesh,
-                        // Y-level meshes are not collidable. They're for rendering only.
-                        false,
-                        commands,
-                        terrain_res
-                            .materials
-                            .get(&material)
-                            .expect("failed to get material")
-                            .clone(),
-                        !material.is_opaque(),
-                        &mut mesh_assets,
-                        *y_level,
-                        false,
-                    );
-                    mesh_entities.push((entity, mesh));
-                }
-            }
+        // Show the slices at the new y-level and hide all the others. Slices which haven't
+        // been built yet are shown as soon as they are.
+        for (slice_y_level, mut visible) in slice_query.iter_mut() {
+            visible.is_visible = *slice_y_level == *y_level;
         }
     }
 }
 
-/// Removes all voxels & meshes and marks the world for regeneration.
+/// Removes all voxels & meshes and marks the world for regeneration. Once it's been
+/// regenerated, a [`TerrainGenerated`] event tells everything on the surface to respawn.
 fn reset_world(
     commands: &mut Commands,
     mesh_assets: &mut ResMut<Assets<Mesh>>,
@@ -370,491 +499,687 @@ fn reset_world(
 ) {
     // Delete the voxels associated with the current world.
     let store = CompressibleChunkStorage::new(Snappy);
-    terrain_res.chunks = DEFAULT_BUILDER.build_with_write_storage(store);
+    terrain_res.chunks = Arc::new(DEFAULT_BUILDER.build_with_write_storage(store));
+    terrain_res.generation += 1;
 
-    // Delete the entities and meshes associated with the current world.
-    let to_remove = mesh_res.meshes.keys().cloned().collect::<Vec<_>>();
-    for p in to_remove {
-        if let Some(meshes) = mesh_res.meshes.remove(&p) {
-            for (entity, mesh) in meshes {
-                commands.despawn(entity);
-                mesh_assets.remove(&mesh);
-            }
-        }
-    }
+    clear_meshes(commands, mesh_assets, mesh_res);
 
     // Mark the world as ungenerated.
     terrain_res.generated_voxels = false;
+    terrain_res.colliders_ready = false;
+}
+
+/// Removes the meshes of all chunks, so that they're regenerated from the current voxels.
+fn clear_meshes(
+    commands: &mut Commands,
+    mesh_assets: &mut ResMut<Assets<Mesh>>,
+    mesh_res: &mut ResMut<MeshResource>,
+) {
+    // Dropping the tasks cancels them.
+    mesh_res.mesh_tasks.clear();
+    clear_slices(commands, mesh_assets, mesh_res);
+    for (_, collider) in mesh_res.colliders.drain() {
+        if let Some(entity) = collider.entity {
+            commands.despawn(entity);
+        }
+    }
+    for (_, chunk_mesh) in mesh_res.meshes.drain() {
+        for (entity, mesh) in chunk_mesh.entities {
+            commands.despawn(entity);
+            mesh_assets.remove(&mesh);
+        }
+    }
+}
+
+/// Removes all of the slices, so that they're rebuilt as they're needed.
+fn clear_slices(
+    commands: &mut Commands,
+    mesh_assets: &mut ResMut<Assets<Mesh>>,
+    mesh_res: &mut ResMut<MeshResource>,
+) {
+    mesh_res.slice_tasks.clear();
+    for (_, slices) in mesh_res.slices.drain() {
+        for (entity, mesh) in slices {
+            commands.despawn(entity);
+            mesh_assets.remove(&mesh);
+        }
+    }
 }
 
-fn generate_voxels(mut terrain_res: ResMut<TerrainResource>) {
+fn generate_voxels(
+    mut terrain_res: ResMut<TerrainResource>,
+    mut generated_events: ResMut<Events<TerrainGenerated>>,
+) {
     if terrain_res.generated_voxels {
         return;
     }
 
     // Generate the 3D voxel map of the terrain.
-    let elevation_noise = Noise2d::new(
-        RidgedMulti::new()
-            .set_seed(random_seed())
-            .set_frequency(0.001)
-            .set_lacunarity(4.0)
-            .set_persistence(0.7)
-            .set_octaves(8),
-    );
-    let dirt_thickness_noise = Noise2d::new(RidgedMulti::new().set_seed(random_seed()));
-    let query = Extent3i::from_min_and_shape(REGION_MIN_3D, REGION_SHAPE_3D);
-    trace!("Generating 3D strata map for extent {:?}", query);
-    let strata_array = colonize_core::generate_precise_map(
-        &elevation_noise,
-        &dirt_thickness_noise,
-        SEA_LEVEL,
-        REGION_MIN_3D,
-        REGION_SHAPE_3D,
+    let config = &terrain_res.config;
+    let seed = config.seed.unwrap_or_else(random_seed);
+    let dirt_thickness_noise = Noise2d::new(RidgedMulti::new().set_seed(seed.wrapping_add(1)));
+    let strata_noise = colonize_noise::strata_noise(seed.wrapping_add(2));
+    // The images a preset names are read from the asset root, like the presets themselves.
+    // The WASM build can't read files, so it always falls back to the elevation noise.
+    #[cfg(not(target_arch = "wasm32"))]
+    let asset_root = asset_root();
+    #[cfg(target_arch = "wasm32")]
+    let asset_root = std::path::PathBuf::new();
+    let masks = config.load_material_masks(&asset_root).unwrap_or_else(|e| {
+        warn!("Ignoring material masks: {}", e);
+        Vec::new()
+    });
+    let query = config.extent();
+    trace!(
+        "Generating 3D strata map for extent {:?} with seed {}",
+        query,
+        seed
     );
+    let strata_array = match config.load_height_map(&asset_root) {
+        Ok(Some(height_map)) => generate_precise_map_with_masks(
+            &height_map,
+            &dirt_thickness_noise,
+            &strata_noise,
+            &masks,
+            config,
+        ),
+        result => {
+            if let Err(e) = result {
+                warn!("Using elevation noise instead of the height map: {}", e);
+            }
+            let elevation_noise = colonize_noise::ridged_multi(&config.elevation, seed);
+            generate_precise_map_with_masks(
+                &elevation_noise,
+                &dirt_thickness_noise,
+                &strata_noise,
+                &masks,
+                config,
+            )
+        }
+    };
 
     // Copy over the voxels from their intermediate representations to the chunk map.
     trace!("Copying chunk data to chunk map");
-    copy_extent(&query, &strata_array, &mut terrain_res.chunks);
+    let store = CompressibleChunkStorage::new(Snappy);
+    let mut chunks = DEFAULT_BUILDER.build_with_write_storage(store);
+    copy_extent(&query, &strata_array, &mut chunks);
+    terrain_res.chunks = Arc::new(chunks);
+    terrain_res.generation += 1;
     trace!("Finished generating the world");
 
     terrain_res.generated_voxels = true;
+    generated_events.send(TerrainGenerated);
 }
 
 fn random_seed() -> u32 {
     thread_rng().gen()
 }
 
+/// Starts meshing each chunk in the background whenever it isn't drawn at the level of detail
+/// for its distance from the camera, or with the current meshing method. Whatever the chunk is
+/// drawn with now stays until the new mesh is ready, so chunks never disappear while they're
+/// being meshed.
 fn generate_meshes(
+    mut terrain: ResMut<TerrainResource>,
+    mut mesh_res: ResMut<MeshResource>,
+    pool: Res<AsyncComputeTaskPool>,
+    method: Res<MeshGenerationMethod>,
+    camera_query: Query<&GlobalTransform, With<PerspectiveProjection>>,
+) {
+    let camera = camera_query
+        .iter()
+        .next()
+        .map(|transform| transform.translation);
+    let map_ref = &terrain.chunks;
+    let mut colliders_ready = terrain.generated_voxels;
+    for chunk_key in map_ref.storage().chunk_keys() {
+        // A collider built for another meshing method is still good to stand on until its
+        // replacement is ready.
+        colliders_ready &= mesh_res.colliders.contains_key(chunk_key);
+        let chunk_extent = map_ref.indexer.extent_for_chunk_at_key(*chunk_key);
+        let level = camera.map_or(0, |camera| lod_level(&chunk_extent, camera));
+        let wanted = Some((level, *method));
+        let collider_is_current = mesh_res
+            .colliders
+            .get(chunk_key)
+            .map_or(false, |collider| collider.method == *method);
+        let drawn = mesh_res
+            .meshes
+            .get(chunk_key)
+            .map(|mesh| (mesh.level, mesh.method));
+        if drawn == wanted && collider_is_current {
+            // The camera came back before the mesh for another level was ready, so it isn't
+            // needed any more. Dropping the task cancels it.
+            mesh_res.mesh_tasks.remove(chunk_key);
+            continue;
+        }
+        let meshing = mesh_res
+            .mesh_tasks
+            .get(chunk_key)
+            .map(|(level, method, _)| (*level, *method));
+        if meshing == wanted {
+            continue;
+        }
+
+        let chunks = Arc::clone(map_ref);
+        let generation = terrain.generation;
+        let chunk_key = *chunk_key;
+        let method = *method;
+        let with_collider = !collider_is_current;
+        let task = pool.spawn(async move {
+            generate_mesh(
+                &chunks,
+                generation,
+                &chunk_key,
+                method,
+                level,
+                with_collider,
+            )
+        });
+        mesh_res.mesh_tasks.insert(chunk_key, (level, method, task));
+    }
+    terrain.colliders_ready = colliders_ready;
+}
+
+/// The level of detail a chunk is drawn at, from its distance to the camera.
+fn lod_level(chunk_extent: &Extent3i, camera: Vec3) -> u8 {
+    let minimum = chunk_extent.minimum;
+    let center = Vec3::new(minimum.x() as f32, minimum.y() as f32, minimum.z() as f32)
+        + Vec3::splat(CHUNK_SIZE as f32 / 2.);
+    ((center.distance(camera) / LOD_DISTANCE) as u8).min(LOD_LEVELS - 1)
+}
+
+/// Adds the meshes and colliders which have finished building to the world, replacing the
+/// ones their chunks had before.
+fn spawn_meshes(
     commands: &mut Commands,
     mut mesh_assets: ResMut<Assets<Mesh>>,
     terrain: Res<TerrainResource>,
     mut mesh_res: ResMut<MeshResource>,
-    pool: Res<ComputeTaskPool>,
 ) {
-    let map_ref = &terrain.chunks;
-    let chunk_keys = map_ref
-        .storage()
-        .chunk_keys()
-        .into_iter()
-        .filter(|k| {
-            // If the mesh exists for this chunk, then there's nothing to do.
-            mesh_res.meshes.get(k).is_none()
-        })
-        .collect::<Vec<_>>();
-    let meshes = (&pool.0).scope(|s| {
-        for chunk_key in chunk_keys {
-            s.spawn(generate_mesh(map_ref, chunk_key))
+    let mut meshed = Vec::new();
+    mesh_res.mesh_tasks.retain(|p, (level, method, task)| {
+        match future::block_on(future::poll_once(task)) {
+            Some(chunk) => {
+                meshed.push((*p, *level, *method, chunk));
+                false
+            }
+            None => true,
         }
     });
-    for chunk in meshes.into_iter() {
-        if let (p, Some(layers_map)) = chunk {
-            let entities = layers_map
-                .into_iter()
-                .flat_map(|((y_level, full_detail), material_meshes)| {
-                    material_meshes
-                        .into_iter()
-                        .map(move |(material, mesh)| (y_level, full_detail, material, mesh))
-                })
-                .map(|(y_level, full_detail, material, mesh)| {
-                    generate_mesh_entity(
-                        mesh,
-                        // Only treat the mesh as collidable if it's the "full detail" mesh for the chunk.
-                        // The rest are for rendering only.
-                        full_detail && material.collidable(),
-                        commands,
-                        terrain
-                            .materials
-                            .get(&material)
-                            .expect("failed to get material")
-                            .clone(),
-                        !material.is_opaque(),
-                        &mut mesh_assets,
-                        y_level,
-                        full_detail,
-                    )
-                })
-                .collect::<Vec<_>>();
-            mesh_res.meshes.insert(p, entities);
-        } else if let (p, None) = chunk {
-            // Insert points with no associated mesh into the hash map.
-            // We use the presence of the chunk key in the hash map as a flag on
-            // whether or not to generate the mesh. Chunks without meshes (i.e.
-            // chunks with just air) shouldn't be regenerated so we add them to
-            // the hash map as well.
-            mesh_res.meshes.insert(p, Vec::new());
+
+    let map_ref = &terrain.chunks;
+    for (p, level, method, chunk) in meshed {
+        let chunk_extent = map_ref.indexer.extent_for_chunk_at_key(p);
+        let y_level = YLevel {
+            value: method.padded_chunk_extent(&chunk_extent).max().y() - 1,
+        };
+        let mut entities = Vec::new();
+        for (material, (mesh, occlusion)) in chunk.meshes.unwrap_or_default() {
+            entities.push(generate_mesh_entity(
+                mesh,
+                occlusion,
+                commands,
+                terrain
+                    .materials
+                    .get(&material)
+                    .expect("failed to get material")
+                    .clone(),
+                !material.is_opaque(),
+                &mut mesh_assets,
+                y_level,
+                true,
+                true,
+            ));
+        }
+        // Chunks without meshes (i.e. chunks with just air) are still added to the hash map,
+        // since we use the level they're in the hash map at to decide whether to mesh them.
+        let chunk_mesh = ChunkMesh {
+            level,
+            method,
+            entities,
+        };
+        if let Some(old) = mesh_res.meshes.insert(p, chunk_mesh) {
+            for (entity, mesh) in old.entities {
+                commands.despawn(entity);
+                mesh_assets.remove(&mesh);
+            }
+        }
+
+        if let Some(trimesh) = chunk.collider {
+            let collider = ChunkCollider {
+                method,
+                entity: spawn_collider(commands, trimesh, method),
+            };
+            if let Some(old) = mesh_res.colliders.insert(p, collider) {
+                if let Some(entity) = old.entity {
+                    commands.despawn(entity);
+                }
+            }
         }
     }
 }
 
-struct FullDetailMesh;
-
-async fn generate_mesh(
-    map_ref: &CompressibleChunkMap3<Voxel>,
-    chunk_key: &Point3i,
-) -> (
-    Point3i,
-    Option<HashMap<(YLevel, bool), HashMap<VoxelType, PosNormMesh>>>,
+/// Keeps the slices of the Y-levels nearest the current one built, so that scrolling to any of
+/// them shows them straight away. The levels are built in the background one at a time,
+/// nearest first, and the levels which fall out of range are dropped.
+fn request_slice_meshes(
+    commands: &mut Commands,
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    terrain: Res<TerrainResource>,
+    mut mesh_res: ResMut<MeshResource>,
+    pool: Res<AsyncComputeTaskPool>,
+    method: Res<MeshGenerationMethod>,
+    y_level: Res<YLevel>,
 ) {
-    trace!("Generating mesh for chunk at {:?}", chunk_key);
-    let local_cache = LocalChunkCache3::new();
-    let chunk_extent = map_ref.indexer.extent_for_chunk_at_key(*chunk_key);
-    let padded_chunk_extent = match METHOD {
-        MeshGenerationMethod::AdfDualContour => padded_adf_chunk_extent(&chunk_extent),
-        MeshGenerationMethod::GreedyQuads | MeshGenerationMethod::SurfaceNets => {
-            padded_greedy_quads_chunk_extent(&chunk_extent)
-        }
-    };
+    if !terrain.generated_voxels {
+        return;
+    }
 
-    // Create a mesh for each possible size of the chunk.
-    let mut padded_layer_extent = padded_chunk_extent;
-    *padded_layer_extent.shape.y_mut() = 3;
-    let mut padded_layer_extents = Vec::new();
-    while padded_layer_extent.max().y() <= padded_chunk_extent.max().y() {
-        padded_layer_extents.push(padded_layer_extent);
-        *padded_layer_extent.minimum.y_mut() += 1;
+    let region = terrain.config.extent();
+    let current = y_level.value;
+    let mut wanted: Vec<i32> = (region.minimum.y()..=region.least_upper_bound().y()).collect();
+    wanted.sort_by_key(|y| (y - current).abs());
+    wanted.truncate(CACHED_SLICE_LEVELS);
+
+    let dropped: Vec<i32> = mesh_res
+        .slices
+        .keys()
+        .filter(|y| !wanted.contains(*y))
+        .copied()
+        .collect();
+    for y in dropped {
+        trace!("Dropping the slices at y-level {}", y);
+        // Dropping the tasks cancels them.
+        mesh_res.slice_tasks.retain(|(level, _)| *level != y);
+        for (entity, mesh) in mesh_res.slices.remove(&y).unwrap_or_default() {
+            commands.despawn(entity);
+            mesh_assets.remove(&mesh);
+        }
     }
-    padded_layer_extents.push(padded_chunk_extent);
-    trace!(
-        "Generating {} padded layers for chunk {:?}",
-        padded_layer_extents.len(),
-        chunk_key
-    );
 
-    let mut layer_meshes: HashMap<(YLevel, bool), HashMap<VoxelType, PosNormMesh>> = HashMap::new();
-    // Iterate over the slice extents in reverse order. The first extent will be the "full" extent of the
-    // chunk. This is a special case because we want to generate both a sliced (i.e. "pretend there's only air
-    // above us") mesh and a full-detail one (no air padding above).
-    let mut extents = Vec::new();
-    for padded_layer_extent in padded_layer_extents.into_iter().rev() {
-        if extents.is_empty() {
-            // For the first (full size) extent, generate a mesh for it twice. Once with a layer of
-            // air padding, and once without. We need both versions for if we're viewing the top of the mesh
-            // or if the top of the mesh is obscured by the chunk above it.
-            extents.push((true, padded_layer_extent, padded_layer_extent));
+    // Only one level is built at a time, so that the nearest levels are ready first, unless the
+    // player has scrolled past all of the levels which were built.
+    let next = wanted
+        .iter()
+        .find(|y| !mesh_res.slices.contains_key(*y))
+        .copied();
+    if let Some(y) = next {
+        if mesh_res.slice_tasks.is_empty() || y == current {
+            request_slice_level(&mut mesh_res, &terrain, y, *method, &pool);
         }
-        extents.push((
-            false,
-            padded_layer_extent,
-            padded_layer_extent.add_to_shape(PointN([0, -1, 0])),
-        ));
     }
+}
 
-    for (full_detail, padded_layer_extent, extent_to_copy) in extents {
-        if !full_detail {
+/// Starts meshing the slice through each chunk at a Y-level. Every chunk gets sliced, even if
+/// it has no full-detail mesh: a chunk of solid rock has no surface until it's cut open.
+fn request_slice_level(
+    mesh_res: &mut MeshResource,
+    terrain: &TerrainResource,
+    y: i32,
+    method: MeshGenerationMethod,
+    pool: &AsyncComputeTaskPool,
+) {
+    trace!("Generating slices at y-level {}", y);
+    mesh_res.slices.entry(y).or_insert_with(Vec::new);
+    let map_ref = &terrain.chunks;
+    for chunk_key in map_ref.storage().chunk_keys() {
+        let chunk_extent = map_ref.indexer.extent_for_chunk_at_key(*chunk_key);
+        if y < chunk_extent.minimum.y() || y > chunk_extent.max().y() {
             continue;
         }
-        trace!(
-            "Copying extent {:?} for layer {:?}",
-            extent_to_copy,
-            padded_layer_extent
-        );
-        let meshes = METHOD.generate_mesh_for_extent(
-            map_ref,
-            chunk_key,
-            &local_cache,
-            padded_layer_extent,
-            &extent_to_copy,
-        );
-        if let Some(meshes) = meshes {
-            layer_meshes.insert(
-                (
-                    YLevel {
-                        value: padded_layer_extent.max().y() - 1,
-                    },
-                    full_detail,
-                ),
-                meshes,
-            );
-        } else {
-            // If the layer is empty, don't bother inserting the mesh (just discard it), and we can also
-            // break out of the loop since if a bigger cut of the chunk was empty then any subsets of that
-            // cut will also be empty.
-            break;
-        }
+        let chunks = Arc::clone(map_ref);
+        let generation = terrain.generation;
+        let chunk_key = *chunk_key;
+        let task = pool
+            .spawn(async move { generate_slice_mesh(&chunks, generation, &chunk_key, y, method) });
+        mesh_res.slice_tasks.push((y, task));
     }
+}
 
-    // If all the meshes of all the layers are empty, don't return anything.
-    if layer_meshes.is_empty() {
-        (*chunk_key, None)
-    } else {
-        (*chunk_key, Some(layer_meshes))
+/// Adds the slices which have finished meshing to the world.
+fn spawn_slice_meshes(
+    commands: &mut Commands,
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    terrain: Res<TerrainResource>,
+    mut mesh_res: ResMut<MeshResource>,
+    y_level: Res<YLevel>,
+) {
+    let tasks = std::mem::take(&mut mesh_res.slice_tasks);
+    for (y, mut task) in tasks {
+        let meshes = match future::block_on(future::poll_once(&mut task)) {
+            Some(meshes) => meshes,
+            None => {
+                mesh_res.slice_tasks.push((y, task));
+                continue;
+            }
+        };
+        let slice_y_level = YLevel { value: y };
+        let entities = mesh_res.slices.entry(y).or_insert_with(Vec::new);
+        for (material, (mesh, occlusion)) in meshes.unwrap_or_default() {
+            entities.push(generate_mesh_entity(
+                mesh,
+                occlusion,
+                commands,
+                terrain
+                    .materials
+                    .get(&material)
+                    .expect("failed to get material")
+                    .clone(),
+                !material.is_opaque(),
+                &mut mesh_assets,
+                slice_y_level,
+                false,
+                slice_y_level == *y_level,
+            ));
+        }
     }
 }
 
-const METHOD: MeshGenerationMethod = MeshGenerationMethod::AdfDualContour;
+struct FullDetailMesh;
 
-enum MeshGenerationMethod {
-    GreedyQuads,
-    SurfaceNets,
-    AdfDualContour,
+/// The mesh a chunk is drawn with, at one level of detail.
+struct ChunkMesh {
+    /// From 0 (every voxel) up, with each level downsampling the voxels by another factor of
+    /// two.
+    level: u8,
+    method: MeshGenerationMethod,
+    entities: Vec<(Entity, Handle<Mesh>)>,
 }
 
-impl MeshGenerationMethod {
-    fn generate_mesh_for_extent(
-        &self,
-        map_ref: &CompressibleChunkMap3<Voxel>,
-        chunk_key: &Point3i,
-        local_cache: &LocalChunkCache3<Voxel>,
-        padded_extent: Extent3i,
-        extent_to_copy: &Extent3i,
-    ) -> Option<HashMap<VoxelType, PosNormMesh>> {
-        match *self {
-            MeshGenerationMethod::GreedyQuads => generate_mesh_for_extent_with_greedy_quads(
-                map_ref,
-                chunk_key,
-                local_cache,
-                padded_extent,
-                extent_to_copy,
-            ),
-            MeshGenerationMethod::SurfaceNets => generate_mesh_for_extent_with_surface_nets(
-                map_ref,
-                chunk_key,
-                local_cache,
-                padded_extent,
-                extent_to_copy,
-            ),
-            MeshGenerationMethod::AdfDualContour => generate_mesh_for_extent_with_adf_dual_contour(
-                map_ref,
-                chunk_key,
-                local_cache,
-                padded_extent,
-                extent_to_copy,
-            ),
-        }
-    }
+/// The single static collider covering all of the solid voxels in a chunk.
+struct ChunkCollider {
+    /// The meshing method the collider lines up with.
+    method: MeshGenerationMethod,
+    /// Chunks without any solid voxels don't need a collider at all.
+    entity: Option<Entity>,
 }
 
-fn generate_mesh_for_extent_with_greedy_quads(
-    map_ref: &CompressibleChunkMap3<Voxel>,
-    chunk_key: &Point3i,
-    local_cache: &LocalChunkCache3<Voxel>,
-    padded_extent: Extent3i,
-    extent_to_copy: &Extent3i,
-) -> Option<HashMap<VoxelType, PosNormMesh>> {
-    trace!("Generating mesh for chunk at {:?}", chunk_key);
-    let reader = map_ref.storage().reader(local_cache);
-    let reader_map: ChunkMap<
-        [i32; 3],
-        Voxel,
-        (),
-        CompressibleChunkStorageReader<[i32; 3], Voxel, (), Snappy>,
-    > = DEFAULT_BUILDER.build_with_read_storage(reader);
-    trace!(
-        "Copying extent {:?} for padded extent {:?}",
-        extent_to_copy,
-        padded_extent
-    );
-    let mut padded_array = Array3::fill(padded_extent, EMPTY_VOXEL);
-    copy_extent(extent_to_copy, &reader_map, &mut padded_array);
-    let lookup = |v: Voxel| *v.voxel_type();
-    let voxel_types = TransformMap::new(&padded_array, lookup);
-
-    // TODO bevy: we could avoid re-allocating the buffers on every call if we had
-    // thread-local storage accessible from this task
-    let mut buffer = GreedyQuadsBuffer::new(padded_extent);
-    greedy_quads(&voxel_types, &padded_extent, &mut buffer);
-
-    // Separate the meshes by material, so that we can render each voxel type with a different color.
-    let mut meshes: HashMap<VoxelType, PosNormMesh> = HashMap::new();
-    for group in buffer.quad_groups.iter() {
-        for quad in group.quads.iter() {
-            let material = *reader_map.get(&quad.minimum).voxel_type();
-            let mesh = meshes.entry(material).or_insert_with(PosNormMesh::default);
-            group.face.add_quad_to_pos_norm_mesh(quad, mesh);
-        }
-    }
+/// The vertices and triangles of a triangle mesh.
+type Trimesh = (Vec<[f32; 3]>, Vec<[u32; 3]>);
 
-    // If all the meshes are empty, don't return anything.
-    let layer_is_empty = meshes.iter().fold(
-        false,
-        |acc, (_material, mesh)| if acc { acc } else { mesh.is_empty() },
-    );
+/// What meshing a chunk in the background produces.
+struct MeshedChunk {
+    meshes: Option<ChunkMeshes>,
+    /// The shape of the chunk's collider, if it needed a new one.
+    collider: Option<Trimesh>,
+}
 
-    if layer_is_empty {
-        None
+/// Meshes a chunk at a level of detail. Colliders are always built from every voxel, however
+/// far away the chunk is.
+fn generate_mesh(
+    map_ref: &CompressibleChunkMap3<Voxel>,
+    generation: u64,
+    chunk_key: &Point3i,
+    method: MeshGenerationMethod,
+    lod: u8,
+    with_collider: bool,
+) -> MeshedChunk {
+    trace!("Generating LOD {} mesh for chunk at {:?}", lod, chunk_key);
+    let chunk_extent = map_ref.indexer.extent_for_chunk_at_key(*chunk_key);
+    let padded_chunk_extent = method.padded_chunk_extent(&chunk_extent);
+    let build_collider = |padded_array: &Array3<Voxel>| {
+        cuboid_trimesh(padded_array, &greedy_cuboids(padded_array, &chunk_extent))
+    };
+    let (meshes, collider) = if lod == 0 {
+        mesh_padded_array(
+            map_ref,
+            generation,
+            padded_chunk_extent,
+            &padded_chunk_extent,
+            |padded_array, buffers| {
+                let meshes = method.generate_mesh(padded_array, buffers);
+                let collider = if with_collider {
+                    Some(build_collider(padded_array))
+                } else {
+                    None
+                };
+                (with_occlusion(meshes, padded_array, method), collider)
+            },
+        )
     } else {
-        Some(meshes)
-    }
+        let lod_extent = method.lod_extent(&chunk_extent, lod);
+        let meshes = mesh_padded_array(
+            map_ref,
+            generation,
+            lod_extent,
+            &lod_extent,
+            |array, buffers| {
+                let meshes = method.generate_lod_mesh(array, lod, buffers);
+                with_occlusion(meshes, array, method)
+            },
+        );
+        let collider = if with_collider {
+            Some(mesh_padded_array(
+                map_ref,
+                generation,
+                padded_chunk_extent,
+                &padded_chunk_extent,
+                |padded_array, _| build_collider(padded_array),
+            ))
+        } else {
+            None
+        };
+        (meshes, collider)
+    };
+
+    let meshes = meshes.map(|mut meshes| {
+        for (material, (mesh, occlusion)) in meshes.iter_mut() {
+            // Water is see-through, so skirts hanging under it would be visible.
+            if material.is_opaque() {
+                add_skirts(mesh, &chunk_extent);
+                // Skirts hang down into the cracks between chunks, which are in shadow.
+                occlusion.resize(mesh.positions.len(), 1.);
+            }
+        }
+        meshes
+    });
+    MeshedChunk { meshes, collider }
 }
 
-fn generate_mesh_for_extent_with_adf_dual_contour(
+/// Generates the mesh of a chunk as if everything above the given Y-level were air, so that
+/// the player can see inside of the terrain.
+fn generate_slice_mesh(
     map_ref: &CompressibleChunkMap3<Voxel>,
+    generation: u64,
     chunk_key: &Point3i,
-    local_cache: &LocalChunkCache3<Voxel>,
-    padded_extent: Extent3i,
-    extent_to_copy: &Extent3i,
-) -> Option<HashMap<VoxelType, PosNormMesh>> {
-    trace!("Generating mesh for chunk at {:?}", chunk_key);
-    let reader = map_ref.storage().reader(local_cache);
-    let reader_map: ChunkMap<
-        [i32; 3],
-        Voxel,
-        (),
-        CompressibleChunkStorageReader<[i32; 3], Voxel, (), Snappy>,
-    > = DEFAULT_BUILDER.build_with_read_storage(reader);
+    y: i32,
+    method: MeshGenerationMethod,
+) -> Option<ChunkMeshes> {
+    let chunk_extent = map_ref.indexer.extent_for_chunk_at_key(*chunk_key);
+    let padded_layer_extent = method.padded_slice_extent(&chunk_extent, y);
+    // Leave the top layer empty.
+    let extent_to_copy = padded_layer_extent.add_to_shape(PointN([0, -1, 0]));
     trace!(
-        "Copying extent {:?} for padded extent {:?}",
-        extent_to_copy,
-        padded_extent
+        "Generating slice mesh for extent {:?} for chunk {:?}",
+        padded_layer_extent,
+        chunk_key
     );
-    let mut padded_array = Array3::fill(padded_extent, EMPTY_VOXEL);
-    copy_extent(extent_to_copy, &reader_map, &mut padded_array);
+    mesh_padded_array(
+        map_ref,
+        generation,
+        padded_layer_extent,
+        &extent_to_copy,
+        |padded_array, buffers| {
+            let meshes = method.generate_mesh(padded_array, buffers);
+            with_occlusion(meshes, padded_array, method)
+        },
+    )
+}
 
-    let lookup = |v: Voxel| SignedDistance::distance(&v);
-    let voxel_types = TransformMap::new(&padded_array, lookup);
+/// The meshes of a chunk, one for each material, along with the ambient occlusion of each of
+/// their vertices.
+type ChunkMeshes = HashMap<VoxelType, (PosNormMesh, Vec<f32>)>;
 
-    trace!(
-        "Creating ADF from Array3 with extent: {:?}",
-        voxel_types.extent()
-    );
-    let iter_extent = *voxel_types.extent();
-    let adf = Adf::from_array3(&voxel_types, iter_extent, 1.0, 0.2);
-    // TODO bevy: we could avoid re-allocating the buffers on every call if we had
-    // thread-local storage accessible from this task
-    let mut buffer = AdfDualContourBuffer::default();
-    adf_dual_contour(&adf, &mut buffer);
-
-    if buffer.mesh.is_empty() {
-        return None;
+fn with_occlusion(
+    meshes: Option<HashMap<VoxelType, PosNormMesh>>,
+    voxels: &Array3<Voxel>,
+    method: MeshGenerationMethod,
+) -> Option<ChunkMeshes> {
+    let meshes = meshes?
+        .into_iter()
+        .map(|(material, mesh)| {
+            let occlusion = method.ambient_occlusion(voxels, &mesh);
+            (material, (mesh, occlusion))
+        })
+        .collect();
+    Some(meshes)
+}
+
+/// Exports the terrain to an OBJ file if the player pressed `F8`, so that it can be viewed in
+/// Blender and other 3D tools. With `Shift` held, only the slice at the current Y-level is
+/// exported.
+#[cfg(not(target_arch = "wasm32"))]
+fn export_terrain(
+    keyboard_input: Res<Input<KeyCode>>,
+    terrain: Res<TerrainResource>,
+    standard_materials: Res<Assets<StandardMaterial>>,
+    method: Res<MeshGenerationMethod>,
+    y_level: Res<YLevel>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F8) {
+        return;
+    }
+    let slice =
+        if keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift) {
+            Some(y_level.value)
+        } else {
+            None
+        };
+    match write_terrain_obj(&terrain, &standard_materials, *method, slice) {
+        Ok(path) => info!("Exported terrain to {}", path.display()),
+        Err(e) => warn!("Failed to export terrain: {}", e),
     }
+}
 
-    let AdfDualContourBuffer { mesh, .. } = buffer;
+/// Meshes every chunk the same way as it's drawn, and writes the meshes to an OBJ file in the
+/// export directory, along with an MTL file of the materials they're drawn with.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_terrain_obj(
+    terrain: &TerrainResource,
+    standard_materials: &Assets<StandardMaterial>,
+    method: MeshGenerationMethod,
+    slice: Option<i32>,
+) -> io::Result<PathBuf> {
+    let directory = Path::new(EXPORT_DIRECTORY);
+    fs::create_dir_all(directory)?;
+    let name = match slice {
+        Some(y) => format!("terrain_y_{}", y),
+        None => "terrain".to_string(),
+    };
+    let obj_path = directory.join(format!("{}.obj", name));
+    let mtl_file_name = format!("{}.mtl", name);
+    let mut writer = ObjWriter::new(BufWriter::new(File::create(&obj_path)?), &mtl_file_name)?;
 
-    // Separate the meshes by material, so that we can render each voxel type with a different color.
-    let mut meshes: HashMap<VoxelType, PosNormMesh> = HashMap::new();
-    // TODO: surface nets meshes don't have a material
-    meshes.insert(VoxelType::Gold, mesh);
+    let map_ref = &terrain.chunks;
+    for chunk_key in map_ref.storage().chunk_keys() {
+        let chunk_extent = map_ref.indexer.extent_for_chunk_at_key(*chunk_key);
+        let (padded_extent, extent_to_copy) = match slice {
+            Some(y) if y < chunk_extent.minimum.y() || y > chunk_extent.max().y() => continue,
+            Some(y) => {
+                let padded_layer_extent = method.padded_slice_extent(&chunk_extent, y);
+                (
+                    padded_layer_extent,
+                    padded_layer_extent.add_to_shape(PointN([0, -1, 0])),
+                )
+            }
+            None => {
+                let padded_chunk_extent = method.padded_chunk_extent(&chunk_extent);
+                (padded_chunk_extent, padded_chunk_extent)
+            }
+        };
+        let meshes = mesh_padded_array(
+            map_ref,
+            terrain.generation,
+            padded_extent,
+            &extent_to_copy,
+            |padded_array, buffers| method.generate_mesh(padded_array, buffers),
+        );
+        for (material, mesh) in meshes.unwrap_or_default() {
+            let [x, y, z] = chunk_key.0;
+            let name = format!("chunk_{}_{}_{}_{:?}", x, y, z, material);
+            writer.add_mesh(&name, material, &mesh)?;
+        }
+    }
 
-    Some(meshes)
+    let mtl = BufWriter::new(File::create(directory.join(&mtl_file_name))?);
+    writer.write_materials(mtl, |material| {
+        let color = terrain
+            .materials
+            .get(&material)
+            .and_then(|(handle, _)| standard_materials.get(handle))
+            .map_or(Color::WHITE, |standard_material| standard_material.albedo);
+        [color.r(), color.g(), color.b(), color.a()]
+    })?;
+    Ok(obj_path)
 }
 
-fn generate_mesh_for_extent_with_surface_nets(
+thread_local! {
+    /// Decompressed copies of the chunks read on this thread, along with the generation of the
+    /// chunk map they were read from. Neighbouring chunks are read over and over while meshing,
+    /// so the cache is kept between tasks, until a task reads from a different map.
+    static CHUNK_CACHE: RefCell<(u64, LocalChunkCache3<Voxel>)> =
+        RefCell::new((0, LocalChunkCache3::new()));
+}
+
+/// Copies the voxels in an extent of the chunk map into this thread's meshing buffers, leaving
+/// the rest of the padded extent empty, and meshes them with `mesh`.
+fn mesh_padded_array<T>(
     map_ref: &CompressibleChunkMap3<Voxel>,
-    chunk_key: &Point3i,
-    local_cache: &LocalChunkCache3<Voxel>,
+    generation: u64,
     padded_extent: Extent3i,
     extent_to_copy: &Extent3i,
-) -> Option<HashMap<VoxelType, PosNormMesh>> {
-    trace!("Generating mesh for chunk at {:?}", chunk_key);
-    let reader = map_ref.storage().reader(local_cache);
-    let reader_map: ChunkMap<
-        [i32; 3],
-        Voxel,
-        (),
-        CompressibleChunkStorageReader<[i32; 3], Voxel, (), Snappy>,
-    > = DEFAULT_BUILDER.build_with_read_storage(reader);
+    mesh: impl FnOnce(&Array3<Voxel>, &mut MesherBuffers) -> T,
+) -> T {
     trace!(
         "Copying extent {:?} for padded extent {:?}",
         extent_to_copy,
         padded_extent
     );
-    let mut padded_array = Array3::fill(padded_extent, EMPTY_VOXEL);
-    copy_extent(extent_to_copy, &reader_map, &mut padded_array);
-
-    // TODO bevy: we could avoid re-allocating the buffers on every call if we had
-    // thread-local storage accessible from this task
-    let mut buffer = SurfaceNetsBuffer::default();
-    surface_nets(&padded_array, &padded_extent, &mut buffer);
-
-    if buffer.mesh.is_empty() {
-        return None;
-    }
-
-    let SurfaceNetsBuffer {
-        mesh,
-        surface_strides,
-        ..
-    } = buffer;
-
-    let lookup = |v: Voxel| *v.voxel_type();
-    let voxel_types = TransformMap::new(&padded_array, lookup);
-    let material_counts = count_adjacent_materials(&voxel_types, &surface_strides);
-
-    // Separate the meshes by material, so that we can render each voxel type with a different color.
-    let mut meshes: HashMap<VoxelType, PosNormMesh> = HashMap::new();
-    // TODO: surface nets meshes don't have a material
-    meshes.insert(VoxelType::Gold, mesh);
-
-    Some(meshes)
-}
-
-pub trait TypedVoxel {
-    fn voxel_type(&self) -> VoxelType;
-}
-
-impl TypedVoxel for VoxelType {
-    fn voxel_type(&self) -> VoxelType {
-        *self
-    }
-}
-
-/// Uses a kernel to count the adjacent materials for each surface point. This is necessary because we used dual contouring to
-/// construct the mesh, so a given vertex has 8 adjacent voxels, some of which may be empty. This also assumes that the material
-/// layer can only be one of 0..NUM_VOXEL_TYPES.
-fn count_adjacent_materials<A, V>(
-    voxels: &A,
-    surface_strides: &[Stride],
-) -> Vec<[u8; NUM_VOXEL_TYPES]>
-where
-    A: Array<[i32; 3]> + GetUncheckedRelease<Stride, V>,
-    V: IsEmpty + TypedVoxel,
-{
-    let mut corner_offsets = [Stride(0); 8];
-    voxels.strides_from_local_points(
-        &Local::localize_points(&Point3i::corner_offsets()),
-        &mut corner_offsets,
-    );
-    let mut material_counts = vec![[0; NUM_VOXEL_TYPES]; surface_strides.len()];
-    for (stride, counts) in surface_strides.iter().zip(material_counts.iter_mut()) {
-        for corner in corner_offsets.iter() {
-            let corner_voxel = voxels.get(*stride + *corner);
-            // Only add weights from non-empty voxels.
-            if !corner_voxel.is_empty() {
-                let material = corner_voxel.voxel_type();
-                debug_assert!(material != VoxelType::Air);
-                counts[material.index()] += 1;
-            }
+    CHUNK_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.0 != generation {
+            *cache = (generation, LocalChunkCache3::new());
         }
-    }
-
-    material_counts
+        let reader = map_ref.storage().reader(&cache.1);
+        let reader_map: ChunkMap<
+            [i32; 3],
+            Voxel,
+            (),
+            CompressibleChunkStorageReader<[i32; 3], Voxel, (), Snappy>,
+        > = DEFAULT_BUILDER.build_with_read_storage(reader);
+        MeshBuffers::with(|buffers| {
+            let (padded_array, meshers) = buffers.array_and_meshers(padded_extent);
+            copy_extent(extent_to_copy, &reader_map, padded_array);
+            mesh(padded_array, meshers)
+        })
+    })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn generate_mesh_entity(
     mesh: PosNormMesh,
-    collidable: bool,
+    occlusion: Vec<f32>,
     commands: &mut Commands,
     material: (Handle<StandardMaterial>, HatMaterial),
     is_transparent: bool,
     meshes: &mut Assets<Mesh>,
     y_level: YLevel,
     full_detail: bool,
+    visible: bool,
 ) -> (Entity, Handle<Mesh>) {
     assert_eq!(mesh.positions.len(), mesh.normals.len());
+    assert_eq!(mesh.positions.len(), occlusion.len());
     let num_vertices = mesh.positions.len();
 
-    let rigid_body;
-    let collider;
-    if collidable {
-        // Generate a colliding entity for the mesh to use for physics.
-        // TODO: does this position actually need to match the position of the chunk mesh?
-        //   It appears to work fine without any translation applied.
-        rigid_body = Some(RigidBodyBuilder::new_static());
-        collider = Some(ColliderBuilder::trimesh(
-            nested_array_f32_to_points(&mesh.positions),
-            flat_array_f32_to_points(&mesh.indices),
-        ));
-    } else {
-        rigid_body = None;
-        collider = None;
-    }
-
     let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
     render_mesh.set_attribute(
         "Vertex_Position",
         VertexAttributeValues::Float3(mesh.positions),
     );
     render_mesh.set_attribute("Vertex_Normal", VertexAttributeValues::Float3(mesh.normals));
+    render_mesh.set_attribute("Vertex_Occlusion", VertexAttributeValues::Float(occlusion));
+    // Textures are projected onto the terrain from the world position, so the UVs are unused.
     render_mesh.set_attribute(
         "Vertex_Uv",
         VertexAttributeValues::Float2(vec![[0.0; 2]; num_vertices]),
@@ -872,9 +1197,7 @@ fn generate_mesh_entity(
                 mesh: mesh_handle.clone_weak(),
                 material: material.0,
                 visible: Visible {
-                    // Only the full-detail meshes of each chunk are visible to start with.
-                    // Slices of chunks become visible as the player lowers the y-level.
-                    is_visible: full_detail,
+                    is_visible: visible,
                     is_transparent,
                 },
                 ..pbr_bundle()
@@ -888,9 +1211,8 @@ fn generate_mesh_entity(
                 mesh: mesh_handle.clone_weak(),
                 material: material.0,
                 visible: Visible {
-                    // Only the full-detail meshes of each chunk are visible to start with.
-                    // Slices of chunks become visible as the player lowers the y-level.
-                    is_visible: true,
+                    // Slices are only visible while the player is looking at their y-level.
+                    is_visible: visible,
                     is_transparent,
                 },
                 ..PbrBundle::default()
@@ -901,23 +1223,47 @@ fn generate_mesh_entity(
     let entity = commands
         .current_entity()
         .expect("failed to get current entity");
-    if let (Some(rigid_body), Some(mut collider)) = (rigid_body, collider) {
-        collider = collider.user_data(entity.to_bits() as u128);
-        commands.insert(entity, (rigid_body, collider));
+    // Water lets the light through, and the slices are only seen from straight above.
+    if full_detail && !is_transparent {
+        commands.insert_one(entity, ShadowCaster);
     }
     (entity, mesh_handle)
 }
 
-fn nested_array_f32_to_points(array: &[[f32; 3]]) -> Vec<Point<f32>> {
-    array
-        .iter()
-        .map(|[x, y, z]| Point::new(*x, *y, *z))
-        .collect()
-}
+/// Spawns a static collider covering the solid voxels of a chunk, from the surfaces of the
+/// boxes they've been merged into which are open to the air. Colliders are built from the voxels rather than from the
+/// meshes, which are far more detailed than physics needs. Returns `None` if there's nothing
+/// to collide with.
+fn spawn_collider(
+    commands: &mut Commands,
+    (vertices, triangles): Trimesh,
+    method: MeshGenerationMethod,
+) -> Option<Entity> {
+    if triangles.is_empty() {
+        return None;
+    }
+    // Blocky meshes cover the whole of each voxel, while smooth meshes pass through the middle
+    // of the voxels on the surface, so the boxes are shifted down by half a voxel to match.
+    let offset = match method {
+        MeshGenerationMethod::GreedyQuads => 0.,
+        MeshGenerationMethod::SurfaceNets | MeshGenerationMethod::AdfDualContour => -0.5,
+    };
 
-fn flat_array_f32_to_points(array: &[u32]) -> Vec<Point<u32>> {
-    array
-        .chunks(3)
-        .map(|c| Point::new(c[0], c[1], c[2]))
-        .collect()
+    let entity = commands
+        .spawn((Chunk,))
+        .current_entity()
+        .expect("failed to get current entity");
+    let rigid_body = RigidBodyBuilder::new_static().translation(offset, offset, offset);
+    let vertices = vertices
+        .into_iter()
+        .map(|[x, y, z]| NaPoint3::new(x, y, z))
+        .collect();
+    let triangles = triangles
+        .into_iter()
+        .map(|[a, b, c]| NaPoint3::new(a, b, c))
+        .collect();
+    let collider =
+        ColliderBuilder::trimesh(vertices, triangles).user_data(entity.to_bits() as u128);
+    commands.insert(entity, (rigid_body, collider));
+    Some(entity)
 }
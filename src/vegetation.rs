@@ -0,0 +1,524 @@
+//! Trees, shrubs and grasses growing on the surface.
+//!
+//! Plants are defined in `assets/data/plants.ron`. Each kind grows in the biomes it's
+//! suited to, on ground no steeper than it can tolerate. Clicking on a plant which yields
+//! wood marks it for chopping; idle dwarves then walk over and chop it down into logs.
+//! Chopped plants grow back from the stump, so wood is a renewable resource.
+use std::collections::HashSet;
+
+use bevy::{
+    app::{stage, EventReader, Events},
+    ecs::{Commands, Entity, Local, Query, Res, ResMut, With, Without},
+    input::mouse::MouseButton,
+    math::Vec3,
+    pbr::PbrBundle,
+    prelude::{
+        debug, error, shape, trace, AppBuilder, Assets, Color, Handle, IntoSystem, Mesh, Plugin,
+        StandardMaterial, Transform,
+    },
+};
+use bevy_mod_picking::{Group, HighlightablePickMesh, InteractableMesh, PickableMesh};
+use building_blocks::core::{Point2i, PointN};
+use colonize_pbr::ShadowCaster;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+
+use crate::combat::Drafted;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::data::read_asset;
+use crate::daylight::Daylight;
+use crate::dwarf::Dwarf;
+use crate::health::Health;
+use crate::item::{spawn_item, ItemKind};
+use crate::mood::{work_speed, MentalState, Mood, Personality};
+use crate::navigation::{find_path, point_from_translation, Navigator};
+use crate::simulation::{SIMULATION, TICK_SECONDS};
+use crate::terrain::{Biome, TerrainGenerated, TerrainResource};
+
+/// The plant data file, relative to the asset root.
+const PLANTS_PATH: &str = "data/plants.ron";
+/// Plants grow within this distance of the origin.
+const SPAWN_RADIUS: f64 = 120.;
+/// The number of random locations to try when looking for a suitable spot for a plant.
+const SPAWN_ATTEMPTS: usize = 10;
+/// How big a plant is just after it's been chopped down, relative to its full size.
+const STUMP_SCALE: f32 = 0.2;
+/// Chance per second that an idle dwarf looks for a tree to chop.
+const CHOP_CHANCE_PER_SECOND: f32 = 1.;
+/// How close a dwarf has to be to a tree to chop it.
+const CHOP_REACH: f32 = 2.;
+/// Seconds of work needed to chop down a plant, for a dwarf working at normal speed.
+const CHOP_SECONDS: f32 = 5.;
+
+/// A kind of plant, as defined in the plant data file.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PlantKind {
+    name: String,
+    biomes: Vec<Biome>,
+    /// The steepest ground the plant grows on, as the most the surface rises or falls
+    /// between neighbouring columns.
+    max_slope: i32,
+    /// How many of this plant to try to place in the world.
+    count: u32,
+    width: f32,
+    height: f32,
+    color: (f32, f32, f32),
+    /// How many logs the plant yields when chopped down. Plants without any wood can't be
+    /// chopped.
+    wood: u32,
+    /// How long a chopped plant takes to grow back.
+    growth_seconds: f32,
+}
+
+impl PlantKind {
+    fn choppable(&self) -> bool {
+        self.wood > 0
+    }
+
+    /// Whether the plant grows on ground this steep, in this biome.
+    fn grows_on(&self, slope: i32, biome: Option<Biome>) -> bool {
+        slope <= self.max_slope && biome.map_or(false, |biome| self.biomes.contains(&biome))
+    }
+}
+
+pub(crate) struct PlantRegistry {
+    plants: Vec<PlantKind>,
+}
+
+impl PlantRegistry {
+    fn load() -> Result<Self, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let data = read_asset(PLANTS_PATH)?;
+        #[cfg(target_arch = "wasm32")]
+        let data = include_str!("../assets/data/plants.ron");
+        let plants = ron::de::from_str(&data)
+            .map_err(|e| format!("failed to parse {}: {}", PLANTS_PATH, e))?;
+        Ok(Self { plants })
+    }
+}
+
+/// The materials plants are drawn with: one for each kind, and one for any plant which has
+/// been marked for chopping.
+struct PlantMaterials {
+    kinds: Vec<Handle<StandardMaterial>>,
+    marked: Handle<StandardMaterial>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Plant {
+    kind: usize,
+    /// From 0 (just chopped) to 1 (fully grown).
+    growth: f32,
+    /// The height of the ground the plant grows from.
+    ground: f32,
+}
+
+impl Plant {
+    fn mature(&self) -> bool {
+        self.growth >= 1.
+    }
+}
+
+/// Marks a plant which a dwarf should chop down once it's fully grown.
+pub(crate) struct MarkedForChopping;
+
+/// The plant a dwarf is on their way to chop, or is chopping.
+#[derive(Debug)]
+pub(crate) struct Chopping {
+    plant: Entity,
+    /// Seconds of work put in so far.
+    progress: f32,
+}
+
+/// Places the transform of a plant so that it stands on the ground at its current size.
+fn plant_transform(kind: &PlantKind, plant: &Plant, transform: &mut Transform) {
+    let scale = STUMP_SCALE + (1. - STUMP_SCALE) * plant.growth;
+    transform.scale = Vec3::splat(scale);
+    transform.translation.y = plant.ground + kind.height * scale / 2.;
+}
+
+fn setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    registry: Res<PlantRegistry>,
+) {
+    commands.insert_resource(PlantMaterials {
+        kinds: registry
+            .plants
+            .iter()
+            .map(|kind| {
+                let (r, g, b) = kind.color;
+                materials.add(Color::rgb(r, g, b).into())
+            })
+            .collect(),
+        marked: materials.add(Color::rgb(0.8, 0.3, 0.2).into()),
+    });
+}
+
+/// Picks a random column within the spawn radius which no other plant has taken, and which
+/// is `suitable` for the plant. Gives up after a few attempts, since there may not be any.
+fn pick_column(
+    rng: &mut impl Rng,
+    occupied: &HashSet<Point2i>,
+    suitable: impl Fn(Point2i) -> bool,
+) -> Option<Point2i> {
+    (0..SPAWN_ATTEMPTS)
+        .map(|_| {
+            let r = SPAWN_RADIUS * rng.gen::<f64>().sqrt();
+            let theta = rng.gen::<f64>() * 2. * std::f64::consts::PI;
+            PointN([(r * theta.cos()) as i32, (r * theta.sin()) as i32])
+        })
+        .find(|column| !occupied.contains(column) && suitable(*column))
+}
+
+/// Replaces all of the plants whenever the world is generated.
+#[allow(clippy::too_many_arguments)]
+fn spawn_plants(
+    commands: &mut Commands,
+    mut generated_reader: Local<EventReader<TerrainGenerated>>,
+    generated_events: Res<Events<TerrainGenerated>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    terrain_res: Res<TerrainResource>,
+    registry: Res<PlantRegistry>,
+    plant_materials: Res<PlantMaterials>,
+    plant_query: Query<Entity, With<Plant>>,
+) {
+    if generated_reader.iter(&generated_events).last().is_none() {
+        return;
+    }
+    for entity in plant_query.iter() {
+        commands.despawn(entity);
+    }
+
+    let mut rng = thread_rng();
+    let mut occupied = HashSet::new();
+    for (index, kind) in registry.plants.iter().enumerate() {
+        let mesh = meshes.add(Mesh::from(shape::Box::new(
+            kind.width,
+            kind.height,
+            kind.width,
+        )));
+        let mut spawned = 0;
+        for _ in 0..kind.count {
+            let location = pick_column(&mut rng, &occupied, |column| {
+                kind.grows_on(terrain_res.slope_at(column), terrain_res.biome_at(column))
+            });
+            let column = match location {
+                Some(column) => column,
+                None => continue,
+            };
+            occupied.insert(column);
+
+            let plant = Plant {
+                kind: index,
+                growth: 1.,
+                ground: terrain_res.surface_y(column) as f32 + 1.,
+            };
+            let mut transform = Transform::from_translation(Vec3::new(
+                column.x() as f32 + 0.5,
+                0.,
+                column.y() as f32 + 0.5,
+            ));
+            plant_transform(kind, &plant, &mut transform);
+            commands
+                .spawn(PbrBundle {
+                    mesh: mesh.clone(),
+                    material: plant_materials.kinds[index].clone(),
+                    transform,
+                    ..Default::default()
+                })
+                .with(plant)
+                .with(ShadowCaster)
+                .with(PickableMesh::default())
+                .with(InteractableMesh::default())
+                .with(HighlightablePickMesh::default());
+            spawned += 1;
+        }
+        debug!("Placed {} of {} {}", spawned, kind.count, kind.name);
+    }
+}
+
+/// Grows plants back after they've been chopped.
+fn grow_plants(registry: Res<PlantRegistry>, mut plant_query: Query<(&mut Plant, &mut Transform)>) {
+    for (mut plant, mut transform) in plant_query.iter_mut() {
+        if plant.mature() {
+            continue;
+        }
+        let kind = &registry.plants[plant.kind];
+        plant.growth = (plant.growth + TICK_SECONDS / kind.growth_seconds).min(1.);
+        plant_transform(kind, &plant, &mut transform);
+    }
+}
+
+/// Marks or unmarks the plant the player clicks on for chopping.
+fn mark_plants(
+    commands: &mut Commands,
+    registry: Res<PlantRegistry>,
+    plant_materials: Res<PlantMaterials>,
+    mut plant_query: Query<(
+        Entity,
+        &InteractableMesh,
+        &Plant,
+        &mut Handle<StandardMaterial>,
+        Option<&MarkedForChopping>,
+    )>,
+) {
+    for (entity, interactable, plant, mut material, marked) in plant_query.iter_mut() {
+        // There are no events without the picking plugins, as when running headless.
+        let mouse_down_event = interactable.mouse_down_event(&Group::default(), MouseButton::Left);
+        if !matches!(mouse_down_event, Ok(Some(_))) {
+            continue;
+        }
+
+        let kind = &registry.plants[plant.kind];
+        if !kind.choppable() {
+            continue;
+        }
+        if marked.is_some() {
+            trace!("Unmarked {} for chopping", kind.name);
+            commands.remove_one::<MarkedForChopping>(entity);
+            *material = plant_materials.kinds[plant.kind].clone();
+        } else {
+            trace!("Marked {} for chopping", kind.name);
+            commands.insert_one(entity, MarkedForChopping);
+            *material = plant_materials.marked.clone();
+        }
+    }
+}
+
+/// Sends idle dwarves off to chop down the nearest fully grown plant that's been marked for
+/// chopping, and that nobody else is already chopping.
+#[allow(clippy::type_complexity)]
+fn assign_chopping(
+    commands: &mut Commands,
+    terrain_res: Res<TerrainResource>,
+    daylight: Res<Daylight>,
+    mut dwarf_query: Query<
+        (Entity, &Transform, &Health, &MentalState, &mut Navigator),
+        (With<Dwarf>, Without<Chopping>, Without<Drafted>),
+    >,
+    chopping_query: Query<&Chopping>,
+    plant_query: Query<(Entity, &Plant, &Transform), With<MarkedForChopping>>,
+) {
+    // Nobody starts chopping in the dark.
+    if daylight.is_night() {
+        return;
+    }
+
+    let mut rng = thread_rng();
+    let mut claimed = chopping_query
+        .iter()
+        .map(|chopping| chopping.plant)
+        .collect::<HashSet<_>>();
+
+    for (entity, transform, health, mental_state, mut navigator) in dwarf_query.iter_mut() {
+        if !health.can_act()
+            || mental_state.is_breaking()
+            || !navigator.is_idle()
+            || rng.gen::<f32>() >= CHOP_CHANCE_PER_SECOND * TICK_SECONDS
+        {
+            continue;
+        }
+
+        let position = transform.translation;
+        let nearest = plant_query
+            .iter()
+            .filter(|(plant_entity, plant, _)| plant.mature() && !claimed.contains(plant_entity))
+            .map(|(plant_entity, _, t)| (plant_entity, t.translation.distance(position), t))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let (plant, plant_transform) = match nearest {
+            Some((plant, _, t)) => (plant, t),
+            None => continue,
+        };
+
+        let start = point_from_translation(position);
+        let goal = point_from_translation(plant_transform.translation);
+        if let Some(path) = find_path(&terrain_res, start, goal) {
+            trace!("Dwarf {:?} is going to chop {:?}", entity, plant);
+            navigator.set_path(path);
+            claimed.insert(plant);
+            commands.insert_one(
+                entity,
+                Chopping {
+                    plant,
+                    progress: 0.,
+                },
+            );
+        }
+    }
+}
+
+/// Dwarves who have reached the plant they're chopping work away at it until it comes down.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn chop_plants(
+    commands: &mut Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    registry: Res<PlantRegistry>,
+    plant_materials: Res<PlantMaterials>,
+    mut dwarf_query: Query<
+        (
+            Entity,
+            &Transform,
+            &Personality,
+            &Mood,
+            &Health,
+            &MentalState,
+            &Navigator,
+            &mut Chopping,
+        ),
+        Without<Drafted>,
+    >,
+    mut plant_query: Query<(
+        &mut Plant,
+        &Transform,
+        &mut Handle<StandardMaterial>,
+        Option<&MarkedForChopping>,
+    )>,
+) {
+    for (entity, transform, personality, mood, health, mental_state, navigator, mut chopping) in
+        dwarf_query.iter_mut()
+    {
+        let (mut plant, plant_transform, mut material, marked) =
+            match plant_query.get_mut(chopping.plant) {
+                Ok(plant) => plant,
+                Err(_) => {
+                    commands.remove_one::<Chopping>(entity);
+                    continue;
+                }
+            };
+        // Someone else got to it first, or the player changed their mind.
+        if !plant.mature() || marked.is_none() {
+            commands.remove_one::<Chopping>(entity);
+            continue;
+        }
+        if !health.can_act() || mental_state.is_breaking() || !navigator.is_idle() {
+            continue;
+        }
+
+        // If the dwarf stopped short of the plant, they couldn't find a way to it.
+        let mut offset = plant_transform.translation - transform.translation;
+        offset.y = 0.;
+        if offset.length() > CHOP_REACH {
+            trace!("Dwarf {:?} couldn't reach {:?}", entity, chopping.plant);
+            commands.remove_one::<Chopping>(entity);
+            continue;
+        }
+
+        chopping.progress += TICK_SECONDS * work_speed(personality, mood);
+        if chopping.progress < CHOP_SECONDS {
+            continue;
+        }
+
+        let kind = &registry.plants[plant.kind];
+        debug!("Dwarf {:?} chopped down a {}", entity, kind.name);
+        let base = Vec3::new(
+            plant_transform.translation.x,
+            plant.ground + 0.5,
+            plant_transform.translation.z,
+        );
+        for i in 0..kind.wood {
+            // Stack the logs so that they don't spawn inside of each other.
+            let position = base + Vec3::new(0., i as f32 * 0.5, 0.);
+            spawn_item(
+                ItemKind::Wood(kind.name.clone()),
+                position,
+                commands,
+                &mut meshes,
+                &mut materials,
+            );
+        }
+        plant.growth = 0.;
+        *material = plant_materials.kinds[plant.kind].clone();
+        commands.remove_one::<MarkedForChopping>(chopping.plant);
+        commands.remove_one::<Chopping>(entity);
+    }
+}
+
+pub(crate) struct VegetationPlugin;
+
+impl Plugin for VegetationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // Without any plants the world is just bare, which beats not starting.
+        let registry = PlantRegistry::load().unwrap_or_else(|e| {
+            error!("Failed to load plants: {}", e);
+            PlantRegistry { plants: Vec::new() }
+        });
+        app.add_resource(registry)
+            .add_startup_system(setup.system())
+            .add_system_to_stage(stage::PRE_UPDATE, spawn_plants.system())
+            .add_system(mark_plants.system())
+            .add_system_to_stage(SIMULATION, grow_plants.system())
+            .add_system_to_stage(SIMULATION, assign_chopping.system())
+            .add_system_to_stage(SIMULATION, chop_plants.system());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn oak() -> PlantKind {
+        PlantKind {
+            name: "oak".to_string(),
+            biomes: vec![Biome::Grassland],
+            max_slope: 1,
+            count: 1,
+            width: 1.,
+            height: 5.,
+            color: (0., 0., 0.),
+            wood: 3,
+            growth_seconds: 300.,
+        }
+    }
+
+    #[test]
+    fn bundled_plants_parse() {
+        let plants: Vec<PlantKind> =
+            ron::de::from_str(include_str!("../assets/data/plants.ron")).unwrap();
+        assert!(!plants.is_empty());
+        let names = plants.iter().map(|p| &p.name).collect::<HashSet<_>>();
+        assert_eq!(names.len(), plants.len());
+        assert!(plants.iter().any(|p| p.choppable()));
+        assert!(plants.iter().any(|p| !p.choppable()));
+        assert!(plants
+            .iter()
+            .all(|p| !p.biomes.is_empty() && p.growth_seconds > 0.));
+    }
+
+    #[test]
+    fn plants_only_grow_on_suitable_ground() {
+        let oak = oak();
+        assert!(oak.grows_on(0, Some(Biome::Grassland)));
+        assert!(oak.grows_on(1, Some(Biome::Grassland)));
+        assert!(!oak.grows_on(2, Some(Biome::Grassland)));
+        assert!(!oak.grows_on(0, Some(Biome::Shore)));
+        assert!(!oak.grows_on(0, None));
+    }
+
+    #[test]
+    fn picked_columns_are_free_and_nearby() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut occupied = HashSet::new();
+        for _ in 0..100 {
+            let column = pick_column(&mut rng, &occupied, |_| true).unwrap();
+            assert!(!occupied.contains(&column));
+            let distance = ((column.x().pow(2) + column.y().pow(2)) as f64).sqrt();
+            assert!(distance <= SPAWN_RADIUS + 1.);
+            occupied.insert(column);
+        }
+    }
+
+    #[test]
+    fn no_column_is_picked_on_unsuitable_ground() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(pick_column(&mut rng, &HashSet::new(), |_| false), None);
+        // Only the eastern half of the world is suitable.
+        for _ in 0..100 {
+            let column = pick_column(&mut rng, &HashSet::new(), |c| c.x() > 0);
+            assert!(column.map_or(true, |c| c.x() > 0));
+        }
+    }
+}
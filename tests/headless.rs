@@ -0,0 +1,54 @@
+//! Runs the whole simulation headless for a little while, to catch anything which only goes
+//! wrong once worldgen, physics and the agents are all running together.
+use std::time::{Duration, Instant};
+
+use colonize::{headless_app, ColonyReport, HeadlessSettings};
+
+const TICKS: u64 = 200;
+/// No ticks run until the terrain's colliders have been built in the background, which takes
+/// however long it takes, so the run is given a deadline rather than a number of frames.
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+fn settings() -> HeadlessSettings {
+    let args = vec![
+        "--headless".to_string(),
+        "--ticks".to_string(),
+        TICKS.to_string(),
+    ];
+    HeadlessSettings::from_args(args.into_iter()).unwrap()
+}
+
+#[test]
+fn colony_survives_a_short_run() {
+    let mut app = headless_app(settings()).app;
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        app.update();
+        if app.resources.contains::<ColonyReport>() {
+            break;
+        }
+    }
+    let report = app
+        .resources
+        .get::<ColonyReport>()
+        .expect("the run never finished");
+
+    assert_eq!(report.ticks, TICKS);
+    assert!(!report.dwarves.is_empty());
+    // Creatures are spawned from the species data file, so they're missing if it couldn't be
+    // found.
+    assert!(!report.creatures.is_empty());
+    for dwarf in &report.dwarves {
+        let (x, y, z) = dwarf.position;
+        assert!(
+            x.is_finite() && y.is_finite() && z.is_finite(),
+            "{} is at {:?}",
+            dwarf.name,
+            dwarf.position
+        );
+        // Dwarves spawn within a few meters of the origin, and don't walk far in ten seconds.
+        // Any further, and they've fallen through the terrain.
+        let distance = (x * x + y * y + z * z).sqrt();
+        assert!(distance < 200., "{} is at {:?}", dwarf.name, dwarf.position);
+    }
+}